@@ -1,6 +1,7 @@
 pub mod parser;
 pub mod utils;
 pub mod graph;
+pub mod overlaps;
 // mod sequence;
 
 // pub mod cigar;
\ No newline at end of file
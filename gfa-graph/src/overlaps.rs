@@ -0,0 +1,234 @@
+use ascii::AsciiString;
+use ahash::AHashMap;
+
+use super::utils::Orientation;
+use super::parser::prepack1::Gfa1Prepack;
+use super::parser::structs1::LinkRec;
+
+// Overlap of the end of oriented segment with the beginning of another oriented segment.
+#[derive(Debug, Clone)]
+struct Overlap {
+    from_strand: Orientation,
+    from_len: u64,
+    to_name: AsciiString,
+    to_strand: Orientation,
+    to_len: u64,
+}
+
+// Index of overlaps between neighbouring segments. It allows to find positions on neighbouring segments
+// which are identical to a position inside of an overlap.
+pub struct OverlapIndex {
+    seq_lengths: AHashMap<AsciiString, u64>,
+    overlaps: AHashMap<AsciiString, Vec<Overlap>>,
+}
+
+impl OverlapIndex {
+    pub fn from_prepack(prepack: &Gfa1Prepack) -> OverlapIndex {
+        let mut index = OverlapIndex {
+            seq_lengths: AHashMap::default(),
+            overlaps: AHashMap::default(),
+        };
+
+        for rec in prepack.seq_recs_iter() {
+            let length = rec.get_length().or_else(|| rec.seq.as_ref().map(|s| s.len() as u64));
+            if let Some(sl) = length {
+                index.seq_lengths.insert(rec.name.clone(), sl);
+            }
+        }
+
+        for rec in prepack.link_recs_iter() {
+            index.add_link(rec);
+        }
+
+        index
+    }
+
+    // Returns (segment, position, orientation relative to the original segment) for every position on
+    // neighbouring segments that is covered by the same overlap as pos on segment name.
+    pub fn get_alts_within_overlap(&self, name: &str, pos: u64) -> Vec<(AsciiString, u64, Orientation)> {
+        let mut alts = Vec::new();
+
+        let key = match AsciiString::from_ascii(name) {
+            Ok(key) => key,
+            Err(_) => return alts,
+        };
+
+        let (ovps, from_seq_len) = match self.overlaps.get(&key).zip(self.seq_lengths.get(&key)) {
+            Some((ovps, &sl)) => (ovps, sl),
+            None => return alts,
+        };
+
+        for ovp in ovps {
+            let to_seq_len = match self.seq_lengths.get(&ovp.to_name) {
+                Some(&sl) => sl,
+                None => continue,
+            };
+
+            if ovp.from_len > from_seq_len || ovp.to_len > to_seq_len || pos >= from_seq_len {
+                continue;
+            }
+
+            // offset of position from the beginning of overlap on oriented segment
+            let offset = match ovp.from_strand {
+                Orientation::Forward if pos >= from_seq_len - ovp.from_len => pos - (from_seq_len - ovp.from_len),
+                Orientation::Reverse if pos < ovp.from_len => ovp.from_len - 1 - pos,
+                _ => continue,
+            };
+
+            if offset >= ovp.to_len {
+                continue;
+            }
+
+            let to_pos = match ovp.to_strand {
+                Orientation::Forward => offset,
+                Orientation::Reverse => to_seq_len - 1 - offset,
+            };
+            let strand = if is_same_orientation(&ovp.from_strand, &ovp.to_strand) {
+                Orientation::Forward
+            } else {
+                Orientation::Reverse
+            };
+
+            alts.push((ovp.to_name.clone(), to_pos, strand));
+        }
+
+        alts
+    }
+
+    fn add_link(&mut self, rec: &LinkRec) {
+        let (from_len, to_len) = match parse_overlap_lengths(rec.cigar.as_str()) {
+            Some(lens) => lens,
+            None => return,
+        };
+
+        if from_len == 0 || to_len == 0 {
+            return;
+        }
+
+        self.overlaps.entry(rec.from_name.clone()).or_default().push(Overlap {
+            from_strand: rec.from_strand.clone(),
+            from_len,
+            to_name: rec.to_name.clone(),
+            to_strand: rec.to_strand.clone(),
+            to_len,
+        });
+
+        // the same link read from the opposite direction: to- -> from-
+        self.overlaps.entry(rec.to_name.clone()).or_default().push(Overlap {
+            from_strand: Orientation::inverse(&rec.to_strand),
+            from_len: to_len,
+            to_name: rec.from_name.clone(),
+            to_strand: Orientation::inverse(&rec.from_strand),
+            to_len: from_len,
+        });
+    }
+}
+
+fn is_same_orientation(o1: &Orientation, o2: &Orientation) -> bool {
+    matches!((o1, o2), (Orientation::Forward, Orientation::Forward) | (Orientation::Reverse, Orientation::Reverse))
+}
+
+// Returns lengths of overlap on the first and the second segment respectively.
+fn parse_overlap_lengths(cigar: &str) -> Option<(u64, u64)> {
+    if cigar == "*" || cigar.is_empty() {
+        return None;
+    }
+
+    let mut from_len = 0;
+    let mut to_len = 0;
+    let mut num: u64 = 0;
+    let mut has_num = false;
+
+    for c in cigar.chars() {
+        if let Some(d) = c.to_digit(10) {
+            num = num * 10 + d as u64;
+            has_num = true;
+            continue;
+        }
+
+        if !has_num { return None; }
+
+        match c {
+            'M' | '=' | 'X' => { from_len += num; to_len += num; },
+            'D' | 'N' => { from_len += num; },
+            'I' | 'S' => { to_len += num; },
+            'H' | 'P' => {},
+            _ => return None,
+        }
+        num = 0;
+        has_num = false;
+    }
+
+    if has_num { None } else { Some((from_len, to_len)) }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use csv::ByteRecord;
+    use super::super::parser::structs1::SegRec;
+
+    fn segment(name: &str, length: Option<u64>) -> SegRec {
+        let length = length.map(|l| format!("LN:i:{}", l)).unwrap_or_default();
+        SegRec::from_raw(&ByteRecord::from(vec!["S", name, "*", length.as_str()])).unwrap()
+    }
+
+    fn link(from: &str, from_strand: &str, to: &str, to_strand: &str, cigar: &str) -> LinkRec {
+        LinkRec::from_raw(&ByteRecord::from(vec!["L", from, from_strand, to, to_strand, cigar])).unwrap()
+    }
+
+    // a+ overlaps b+ by 10 bp and c- by 5 bp, d has no length, link to d and link without overlap are ignored.
+    fn index() -> OverlapIndex {
+        let segments = vec![segment("a", Some(100)), segment("b", Some(50)), segment("c", Some(30)), segment("d", None)];
+        let links = vec![link("a", "+", "b", "+", "10M"), link("a", "+", "c", "-", "5M"),
+                         link("b", "+", "d", "+", "5M"), link("c", "+", "b", "+", "*")];
+        OverlapIndex::from_prepack(&Gfa1Prepack::from(segments, links))
+    }
+
+    fn alts(index: &OverlapIndex, name: &str, pos: u64) -> Vec<(String, u64, bool)> {
+        index.get_alts_within_overlap(name, pos).into_iter()
+            .map(|(name, pos, strand)| (name.to_string(), pos, matches!(strand, Orientation::Forward)))
+            .collect()
+    }
+
+    #[test]
+    fn test_forward_overlaps() {
+        let index = index();
+        assert_eq!(alts(&index, "a", 90), vec![("b".to_string(), 0, true)]);
+        assert_eq!(alts(&index, "a", 95), vec![("b".to_string(), 5, true), ("c".to_string(), 29, false)]);
+        assert_eq!(alts(&index, "a", 99), vec![("b".to_string(), 9, true), ("c".to_string(), 25, false)]);
+        assert!(alts(&index, "a", 89).is_empty());
+        assert!(alts(&index, "a", 0).is_empty());
+    }
+
+    // Overlaps are found from both segments of a link.
+    #[test]
+    fn test_reverse_overlaps() {
+        let index = index();
+        assert_eq!(alts(&index, "b", 0), vec![("a".to_string(), 90, true)]);
+        assert_eq!(alts(&index, "b", 9), vec![("a".to_string(), 99, true)]);
+        assert!(alts(&index, "b", 10).is_empty());
+        assert_eq!(alts(&index, "c", 29), vec![("a".to_string(), 95, false)]);
+        assert_eq!(alts(&index, "c", 25), vec![("a".to_string(), 99, false)]);
+        assert!(alts(&index, "c", 24).is_empty());
+    }
+
+    #[test]
+    fn test_missing_overlaps() {
+        let index = index();
+        assert!(alts(&index, "a", 100).is_empty());
+        assert!(alts(&index, "d", 0).is_empty());
+        assert!(alts(&index, "e", 0).is_empty());
+        assert!(alts(&index, "\u{e9}", 0).is_empty());
+    }
+
+    #[test]
+    fn test_parse_overlap_lengths() {
+        assert_eq!(parse_overlap_lengths("10M"), Some((10, 10)));
+        assert_eq!(parse_overlap_lengths("5M2I3M1D"), Some((9, 10)));
+        assert_eq!(parse_overlap_lengths("*"), None);
+        assert_eq!(parse_overlap_lengths("M5"), None);
+        assert_eq!(parse_overlap_lengths("5Z"), None);
+    }
+}
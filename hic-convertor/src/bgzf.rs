@@ -0,0 +1,349 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use flate2::{Compression, Crc};
+use flate2::read::{DeflateDecoder, MultiGzDecoder};
+use flate2::write::DeflateEncoder;
+use log::trace;
+
+// BGZF is a series of gzip members (blocks) with at most 64 KB of data each, size of a block is stored in extra field
+// of its header, so a position in a file is addressed by virtual offset: offset of block << 16 | offset within block.
+// BGZF file is a valid gzip file, so it can be read with zcat.
+const MAX_BLOCK_DATA: usize = 0xff00;
+const BLOCK_HEADER_SIZE: usize = 18;
+const BLOCK_FOOTER_SIZE: usize = 8;
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const EOF_BLOCK: [u8; 28] = [0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43,
+                             0x02, 0x00, 0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+
+// Pairs files with these extensions are written in BGZF, so they can be indexed.
+pub fn is_compressed_path(path: &Path) -> bool {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => ext == "gz" || ext == "bgz",
+        None => false
+    }
+}
+
+// Opens plain, gzip or BGZF pairs file, compression is detected by the content of the file.
+pub fn open_reader(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let head = reader.fill_buf()?;
+    if head.len() < BLOCK_HEADER_SIZE || head[..2] != GZIP_MAGIC {
+        return Ok(Box::new(reader));
+    }
+
+    if is_bgzf_header(head) {
+        trace!("Reading {} as BGZF file", path.display());
+        Ok(Box::new(BgzfReader::new(reader)))
+    } else {
+        trace!("Reading {} as gzip file", path.display());
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))))
+    }
+}
+
+// Creates BGZF file if path has .gz or .bgz extension and plain file otherwise.
+pub fn create_writer(path: &Path) -> io::Result<PairsWriter> {
+    let file = File::create(path)?;
+    if is_compressed_path(path) {
+        Ok(PairsWriter::Bgzf(BgzfWriter::new(file)))
+    } else {
+        Ok(PairsWriter::Plain(BufWriter::new(file)))
+    }
+}
+
+// Writer of plain or BGZF pairs file. It must be finished, so that errors of writing the last block and
+// the end of file marker are reported.
+pub enum PairsWriter {
+    Plain(BufWriter<File>),
+    Bgzf(BgzfWriter<File>)
+}
+
+impl PairsWriter {
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            PairsWriter::Plain(mut writer) => writer.flush(),
+            PairsWriter::Bgzf(mut writer) => writer.finish()
+        }
+    }
+}
+
+impl Write for PairsWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            PairsWriter::Plain(writer) => writer.write(buf),
+            PairsWriter::Bgzf(writer) => writer.write(buf)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            PairsWriter::Plain(writer) => writer.flush(),
+            PairsWriter::Bgzf(writer) => writer.flush()
+        }
+    }
+}
+
+fn is_bgzf_header(header: &[u8]) -> bool {
+    header[..2] == GZIP_MAGIC && header[3] & 0x04 != 0 && header[12] == b'B' && header[13] == b'C'
+}
+
+fn invalid_block(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Incorrect BGZF block: {}", msg))
+}
+
+// Buffers data and writes it as BGZF blocks. Empty block which marks the end of file is written on finish.
+// Writer which is dropped without finishing (e.g. output of failed run) is finished silently.
+pub struct BgzfWriter<W: Write> {
+    inner: W,
+    buffer: Vec<u8>,
+    level: Compression,
+    is_finished: bool
+}
+
+impl<W: Write> BgzfWriter<W> {
+    pub fn new(inner: W) -> BgzfWriter<W> {
+        BgzfWriter { inner, buffer: Vec::with_capacity(MAX_BLOCK_DATA), level: Compression::default(),
+                     is_finished: false }
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.is_finished { return Ok(()); }
+        self.flush()?;
+        self.inner.write_all(&EOF_BLOCK)?;
+        self.inner.flush()?;
+        self.is_finished = true;
+        Ok(())
+    }
+
+    fn write_block(&mut self) -> io::Result<()> {
+        let mut encoder = DeflateEncoder::new(Vec::with_capacity(self.buffer.len()), self.level);
+        encoder.write_all(self.buffer.as_slice())?;
+        let cdata = encoder.finish()?;
+        let mut crc = Crc::new();
+        crc.update(self.buffer.as_slice());
+
+        let block_size = BLOCK_HEADER_SIZE + cdata.len() + BLOCK_FOOTER_SIZE;
+        if block_size > u16::MAX as usize + 1 {
+            return Err(invalid_block("compressed data does not fit into block"));
+        }
+
+        let mut header = [0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, b'B', b'C', 0x02, 0x00, 0, 0];
+        header[16..].copy_from_slice(&((block_size - 1) as u16).to_le_bytes());
+        self.inner.write_all(&header)?;
+        self.inner.write_all(cdata.as_slice())?;
+        self.inner.write_all(&crc.sum().to_le_bytes())?;
+        self.inner.write_all(&(self.buffer.len() as u32).to_le_bytes())?;
+
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for BgzfWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(MAX_BLOCK_DATA - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..n]);
+        if self.buffer.len() == MAX_BLOCK_DATA {
+            self.write_block()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            self.write_block()?;
+        }
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for BgzfWriter<W> {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            trace!("Can not finish BGZF file: {}", e);
+        }
+    }
+}
+
+// Reads BGZF blocks one by one and keeps track of virtual offset of the current position.
+pub struct BgzfReader<R: Read> {
+    inner: R,
+    block: Vec<u8>,
+    pos: usize,
+    block_offset: u64,
+    next_block_offset: u64
+}
+
+impl<R: Read> BgzfReader<R> {
+    pub fn new(inner: R) -> BgzfReader<R> {
+        BgzfReader { inner, block: Vec::new(), pos: 0, block_offset: 0, next_block_offset: 0 }
+    }
+
+    // Virtual offset of the next byte to be read. Offset at the end of a block points to the end of the block,
+    // call fill_buf before to get offset of the beginning of the next block.
+    pub fn virtual_offset(&self) -> u64 {
+        self.block_offset << 16 | self.pos as u64
+    }
+
+    // Returns false at the end of file.
+    fn read_block(&mut self) -> io::Result<bool> {
+        let mut header = [0_u8; BLOCK_HEADER_SIZE];
+        let n = read_fully(&mut self.inner, &mut header)?;
+        if n == 0 { return Ok(false); }
+        if n < BLOCK_HEADER_SIZE || !is_bgzf_header(&header) {
+            return Err(invalid_block("header is truncated or it is not BGZF"));
+        }
+
+        let block_size = u16::from_le_bytes([header[16], header[17]]) as usize + 1;
+        if block_size < BLOCK_HEADER_SIZE + BLOCK_FOOTER_SIZE {
+            return Err(invalid_block("size of block is too small"));
+        }
+        let mut data = vec![0_u8; block_size - BLOCK_HEADER_SIZE];
+        if read_fully(&mut self.inner, &mut data)? < data.len() {
+            return Err(invalid_block("block is truncated"));
+        }
+
+        let cdata = &data[..data.len() - BLOCK_FOOTER_SIZE];
+        self.block.clear();
+        DeflateDecoder::new(cdata).read_to_end(&mut self.block)?;
+        self.pos = 0;
+        self.block_offset = self.next_block_offset;
+        self.next_block_offset += block_size as u64;
+        Ok(true)
+    }
+}
+
+impl<R: Read + Seek> BgzfReader<R> {
+    pub fn seek_virtual(&mut self, offset: u64) -> io::Result<()> {
+        let block_offset = offset >> 16;
+        self.inner.seek(SeekFrom::Start(block_offset))?;
+        self.next_block_offset = block_offset;
+        self.block.clear();
+        self.pos = 0;
+        if self.read_block()? {
+            self.pos = ((offset & 0xffff) as usize).min(self.block.len());
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for BgzfReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.fill_buf()?;
+        let n = data.len().min(buf.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: Read> BufRead for BgzfReader<R> {
+    // Empty blocks (e.g. the end of file marker) are skipped.
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.pos == self.block.len() {
+            if !self.read_block()? { break; }
+        }
+        Ok(&self.block[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.block.len());
+    }
+}
+
+fn read_fully(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match reader.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(k) => n += k,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(n)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Cursor;
+
+    // Lines of different length, so they cross boundaries of blocks at different positions.
+    fn test_lines(n: usize) -> Vec<String> {
+        (0..n).map(|i| format!("read{}\tctg{}\t{}\t{}", i, i % 3, i * 17, "x".repeat(i % 50))).collect()
+    }
+
+    fn write_bgzf(lines: &[String]) -> Vec<u8> {
+        let mut writer = BgzfWriter::new(Vec::new());
+        for line in lines {
+            writeln!(writer, "{}", line).unwrap();
+        }
+        writer.finish().unwrap();
+        writer.inner.clone()
+    }
+
+    #[test]
+    fn test_blocks_round_trip() {
+        let lines = test_lines(20000);
+        let data = write_bgzf(&lines);
+        assert!(data.ends_with(&EOF_BLOCK));
+        assert!(is_bgzf_header(&data));
+
+        let read: Vec<String> = BgzfReader::new(data.as_slice()).lines().map(|l| l.unwrap()).collect();
+        assert_eq!(read, lines);
+        // BGZF file is a valid gzip file
+        let mut text = String::new();
+        MultiGzDecoder::new(data.as_slice()).read_to_string(&mut text).unwrap();
+        assert_eq!(text.lines().collect::<Vec<&str>>(), lines);
+    }
+
+    #[test]
+    fn test_virtual_offsets() {
+        let lines = test_lines(20000);
+        let data = write_bgzf(&lines);
+
+        let mut reader = BgzfReader::new(data.as_slice());
+        let mut offsets = Vec::new();
+        let mut line = String::new();
+        loop {
+            reader.fill_buf().unwrap();
+            let offset = reader.virtual_offset();
+            line.clear();
+            if reader.read_line(&mut line).unwrap() == 0 { break; }
+            offsets.push(offset);
+        }
+        assert_eq!(offsets.len(), lines.len());
+        assert!(offsets.iter().any(|&o| o >> 16 > 0));
+
+        let mut reader = BgzfReader::new(Cursor::new(data));
+        for i in (0..lines.len()).rev().step_by(997) {
+            reader.seek_virtual(offsets[i]).unwrap();
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            assert_eq!(line.trim_end_matches('\n'), lines[i]);
+        }
+    }
+
+    #[test]
+    fn test_create_and_open() {
+        let lines = test_lines(100);
+        for name in ["pairs.gz", "pairs.txt"].iter() {
+            let path = std::env::temp_dir().join(format!("hic_convertor_bgzf_{}_{}", std::process::id(), name));
+            let mut writer = create_writer(&path).unwrap();
+            for line in lines.iter() {
+                writeln!(writer, "{}", line).unwrap();
+            }
+            writer.finish().unwrap();
+
+            let data = fs::read(&path).unwrap();
+            assert_eq!(is_compressed_path(&path), data.ends_with(&EOF_BLOCK));
+            let read: Vec<String> = open_reader(&path).unwrap().lines().map(|l| l.unwrap()).collect();
+            assert_eq!(read, lines);
+            fs::remove_file(&path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::convertor::{RescueStrategy, MATCHED_RATE_TRESH, MAX_MOLECULE_SIZE, MIN_MAPQ};
+use super::input::UnpairedPolicy;
+use super::pairs_format::ExtraColumn;
+use super::walks::WalkPolicy;
+use super::xa_tag::MultiMapMode;
+
+// Prefix of header line of pairs file with parameters of conversion.
+pub const CONFIG_PREFIX: &str = "#config:";
+
+// Parameters of conversion. They are loaded from TOML or YAML file (missing parameters have default values),
+// overridden by command line arguments and saved into pairs header and statistics, so that run can be reproduced.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConverterConfig {
+    pub min_mapq: u8,
+    pub max_molecule_size: u64,
+    pub matched_rate_tresh: f64,
+    pub mapq_zero_rescue: bool, // used only if assembly graph is provided
+    pub strategy: RescueStrategy,
+    pub walks_policy: WalkPolicy,
+    pub multimap: MultiMapMode,
+    pub seed: u64,
+    pub unpaired: UnpairedPolicy,
+    pub columns: Vec<ExtraColumn>,
+    pub enzymes: Vec<String>,
+}
+
+impl Default for ConverterConfig {
+    fn default() -> ConverterConfig {
+        ConverterConfig {
+            min_mapq: MIN_MAPQ,
+            max_molecule_size: MAX_MOLECULE_SIZE,
+            matched_rate_tresh: MATCHED_RATE_TRESH,
+            mapq_zero_rescue: false,
+            strategy: RescueStrategy::Complex,
+            walks_policy: WalkPolicy::Outer,
+            multimap: MultiMapMode::Drop,
+            seed: 0,
+            unpaired: UnpairedPolicy::Skip,
+            columns: Vec::new(),
+            enzymes: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ConfigFormat {
+    Toml,
+    Yaml
+}
+
+impl ConfigFormat {
+    pub fn from_path(path: &Path) -> Option<ConfigFormat> {
+        match path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()) {
+            Some(ext) if ext == "toml" => Some(ConfigFormat::Toml),
+            Some(ext) if ext == "yaml" || ext == "yml" => Some(ConfigFormat::Yaml),
+            _ => None
+        }
+    }
+}
+
+impl ConverterConfig {
+    pub fn from_file(path: &Path) -> io::Result<ConverterConfig> {
+        let format = ConfigFormat::from_path(path).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput,
+            format!("Unknown format of config {}, it must have .toml, .yaml or .yml extension.", path.display())))?;
+        let text = fs::read_to_string(path)?;
+
+        match format {
+            ConfigFormat::Toml => toml::from_str(text.as_str()).map_err(|e| invalid_config(path, e.to_string())),
+            ConfigFormat::Yaml => serde_yaml::from_str(text.as_str()).map_err(|e| invalid_config(path, e.to_string())),
+        }
+    }
+
+    // Parameters as pairs of name and value in JSON, e.g. ("strategy", "\"complex\"").
+    pub fn to_key_values(&self) -> Vec<(String, String)> {
+        match serde_json::to_value(self) {
+            Ok(Value::Object(map)) => map.into_iter().map(|(k, v)| (k, v.to_string())).collect(),
+            _ => Vec::new()
+        }
+    }
+
+    pub fn from_key_values(values: impl Iterator<Item = (String, String)>) -> io::Result<ConverterConfig> {
+        let mut map = serde_json::Map::new();
+        for (key, value) in values {
+            let value = serde_json::from_str(value.as_str())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Incorrect value of {}: {}", key, e)))?;
+            map.insert(key, value);
+        }
+        serde_json::from_value(Value::Object(map)).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    }
+
+    // Line of pairs header, config is written in JSON on a single line.
+    pub fn to_header_line(&self) -> String {
+        format!("{} {}", CONFIG_PREFIX, serde_json::to_string(self).unwrap_or_default())
+    }
+}
+
+fn invalid_config(path: &Path, msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Incorrect config {}: {}", path.display(), msg))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(name: &str, text: &str) -> io::Result<ConverterConfig> {
+        let path = std::env::temp_dir().join(format!("hic_convertor_config_{}_{}", std::process::id(), name));
+        fs::write(&path, text).unwrap();
+        let config = ConverterConfig::from_file(&path);
+        fs::remove_file(&path).unwrap();
+        config
+    }
+
+    #[test]
+    fn test_load_config() {
+        let config = load("ok.toml", "multimap = \"random\"\nseed = 7\ncolumns = [\"mapq\", \"rfrag\"]\n").unwrap();
+        assert!(config.multimap == MultiMapMode::Random);
+        assert_eq!(config.seed, 7);
+        assert!(config.columns == vec![ExtraColumn::Mapq, ExtraColumn::Fragments]);
+        assert!(!config.mapq_zero_rescue);
+
+        let config = load("ok.yaml", "multimap: all\ncolumns: [pair_type, walk_pair_index]\n").unwrap();
+        assert!(config.multimap == MultiMapMode::All);
+        assert!(config.columns == vec![ExtraColumn::PairType, ExtraColumn::WalkPairIndex]);
+
+        let restored = ConverterConfig::from_key_values(config.to_key_values().into_iter()).unwrap();
+        assert!(restored == config);
+    }
+
+    #[test]
+    fn test_reject_unknown_values() {
+        assert!(load("multimap.toml", "multimap = \"randon\"\n").is_err());
+        assert!(load("columns.toml", "columns = [\"mapq\", \"pairtype\"]\n").is_err());
+        assert!(load("columns.yaml", "columns: [rfrag1]\n").is_err());
+    }
+}
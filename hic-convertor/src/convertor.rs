@@ -1,14 +1,28 @@
 use std::path::{PathBuf, Path};
 use std::iter::FromIterator;
-use std::io::{self, BufWriter, Write};
+use std::io::{self, Write};
 use std::fs::File;
+use std::mem;
 use log::{info, trace, warn};
 
 use itertools::Itertools;
 use ascii::AsciiString;
-use bam::{RecordReader, Header};
-
-use super::pair_record::{self, PairRecord};
+use bam::{Header, RecordWriter};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use gfa_graph::overlaps::OverlapIndex;
+use gfa_graph::utils::Orientation;
+
+use super::bgzf::{self, PairsWriter};
+use super::pair_record::{self, Alignment, PairRecord, PairType, RescuePath, SideType, Strand};
+use super::pairs_format::{ExtraColumn, PairsHeader};
+use super::digest::FragmentIndex;
+use super::walks::{self, WalkPolicy, WalkStat};
+use super::stats::ConverterStat;
+use super::config::ConverterConfig;
+use super::input::{self, AlignmentReader, ReadNameTracker, UnpairedPolicy};
+use super::xa_tag::{self, MultiMapPolicy, MultiMapResolution, SeededRng};
 
 // When a read matches in its entirety, with an equal score in multiple locations, one of the locations is picked at
 // random, is labeled as primary, will be given a mapping quality of zero and will have an XA tag that contains the
@@ -21,76 +35,307 @@ use super::pair_record::{self, PairRecord};
 // When two complementary regions of a read (the two pieces add up to the full read) align to two different, non-linear
 // genomic locations one of the alignment will be labeled as primary, the other as supplementary alignment
 
-const MATCHED_RATE_TRESH: f64 = 0.8;
-const MIN_MAPQ: u8 = 10;
-const MAX_MOLECULE_SIZE: u64 = 2000;
-
+pub const MATCHED_RATE_TRESH: f64 = 0.8;
+pub const MIN_MAPQ: u8 = 10;
+pub const MAX_MOLECULE_SIZE: u64 = 2000;
+
+// Read groups are processed by workers in chunks, chunks of a batch are processed concurrently. Output of chunks
+// is written in order of reading, so the result does not depend on number of threads.
+const GROUPS_PER_CHUNK: usize = 1024;
+const CHUNKS_PER_BATCH: usize = 64;
+
+// Tags of alignments in output bam: pair type, rescue path, contig and position of ligation partner.
+const PAIR_TYPE_TAG: &[u8; 2] = b"Yt";
+const RESCUE_TAG: &[u8; 2] = b"Yr";
+const PARTNER_TIG_TAG: &[u8; 2] = b"Yc";
+const PARTNER_POS_TAG: &[u8; 2] = b"Yp";
+
+// Read pairs which are resolved besides pairs of unique alignments: unique - none of them, simple - pairs where
+// one of mates has a single alignment, complex - all of them (as walks).
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum RescueStrategy {
     Unique,
     Simple,
     Complex
 }
 
-pub struct Converter {
-    bam_path: PathBuf,
-    _graph: Option<PathBuf>,
-    pair_file: BufWriter<File>,
+impl RescueStrategy {
+    pub fn from_string(s: &str) -> RescueStrategy {
+        match s {
+            "unique" => RescueStrategy::Unique,
+            "simple" => RescueStrategy::Simple,
+            _ => RescueStrategy::Complex
+        }
+    }
+}
+
+pub struct Converter<W: Write = PairsWriter> {
+    input_path: PathBuf,
+    reference_path: Option<PathBuf>,
+    graph_path: Option<PathBuf>,
+    pair_file: W,
+    filtered_file: Option<PairsWriter>,
+    bam_output_path: Option<PathBuf>,
+    bam_output: Option<bam::BamWriter<File>>,
+    threads: usize,
+    unpaired_policy: UnpairedPolicy,
+    resolver: PairResolver,
+    stats: ConverterStat
+}
+
+// Parameters of converting alignments of read pairs to Hi-C pairs, shared between workers.
+struct PairResolver {
+    graph: Option<OverlapIndex>,
     strategy: RescueStrategy,
+    walk_policy: WalkPolicy,
     max_molecule_size: u64,
     matched_rate_tresh: f64,
     min_mapq: u8,
     mapq_zero_rescue: bool,
+    multimap_policy: MultiMapPolicy,
+    extra_columns: Vec<ExtraColumn>,
+    fragments: Option<FragmentIndex>,
+    keep_filtered: bool,
+    keep_alignments: bool,
+}
+
+// Alignments of both reads in pair.
+struct ReadGroup {
+    recs1: Vec<bam::Record>,
+    recs2: Vec<bam::Record>
+}
+
+// Alignments of both sides of a kept Hi-C pair.
+struct Ligation<'a> {
+    rec1: &'a bam::Record,
+    rec2: &'a bam::Record,
+    pair_type: PairType
+}
+
+// Result of processing of a chunk of read groups by a worker.
+struct ChunkOutput {
+    pairs: Vec<u8>,
+    filtered: Vec<u8>,
+    alignments: Vec<bam::Record>,
+    rng: SeededRng,
     stats: ConverterStat
 }
 
+impl ChunkOutput {
+    fn new(rng: SeededRng, walk_policy: WalkPolicy) -> ChunkOutput {
+        let mut stats = ConverterStat::new();
+        stats.walk_stats = WalkStat::new(walk_policy);
+        ChunkOutput { pairs: Vec::new(), filtered: Vec::new(), alignments: Vec::new(), rng, stats }
+    }
+}
+
 impl Converter {
-    pub fn new(bam_file: &Path, _graph: Option<PathBuf>, pair_file: &Path) -> Converter {
+    pub fn new(bam_file: &Path, graph: Option<PathBuf>, pair_file: &Path) -> Converter {
+        Converter::from_writer(bam_file, graph, bgzf::create_writer(pair_file).expect("Problem with file"))
+    }
+}
+
+impl<W: Write> Converter<W> {
+    pub fn from_writer(bam_file: &Path, graph: Option<PathBuf>, pair_file: W) -> Converter<W> {
         Converter {
-            bam_path: PathBuf::from(bam_file),
-            _graph,
-            pair_file: BufWriter::new(File::create(pair_file).expect("Problem with file")),
-            strategy: RescueStrategy::Complex,
-            max_molecule_size: MAX_MOLECULE_SIZE,
-            matched_rate_tresh: MATCHED_RATE_TRESH,
-            min_mapq: MIN_MAPQ,
-            mapq_zero_rescue: false,
+            input_path: PathBuf::from(bam_file),
+            reference_path: None,
+            graph_path: graph,
+            pair_file,
+            filtered_file: None,
+            bam_output_path: None,
+            bam_output: None,
+            threads: 1,
+            unpaired_policy: UnpairedPolicy::Skip,
+            resolver: PairResolver {
+                graph: None,
+                strategy: RescueStrategy::Complex,
+                walk_policy: WalkPolicy::Outer,
+                max_molecule_size: MAX_MOLECULE_SIZE,
+                matched_rate_tresh: MATCHED_RATE_TRESH,
+                min_mapq: MIN_MAPQ,
+                mapq_zero_rescue: false,
+                multimap_policy: MultiMapPolicy::Drop,
+                extra_columns: vec![ExtraColumn::PairType, ExtraColumn::Rescue],
+                fragments: None,
+                keep_filtered: false,
+                keep_alignments: false,
+            },
             stats: ConverterStat::new()
         }
     }
 
-    pub fn update_min_mapq(mut converter: Converter, mapq: u8) -> Converter {
-        converter.min_mapq = mapq;
+    pub fn update_min_mapq(mut converter: Converter<W>, mapq: u8) -> Converter<W> {
+        converter.resolver.min_mapq = mapq;
+        converter
+    }
+
+    pub fn update_max_mol_size(mut converter: Converter<W>, mol_sz: u64) -> Converter<W> {
+        converter.resolver.max_molecule_size = mol_sz;
+        converter
+    }
+
+    pub fn update_matched_rate_tresh(mut converter: Converter<W>, tresh: f64) -> Converter<W> {
+        converter.resolver.matched_rate_tresh = tresh;
+        converter
+    }
+
+    pub fn update_mapq_zero_rescue(mut converter: Converter<W>, mapq_zero_rescue: bool) -> Converter<W> {
+        converter.resolver.mapq_zero_rescue = mapq_zero_rescue;
+        converter
+    }
+
+    pub fn update_strategy(mut converter: Converter<W>, strategy: RescueStrategy) -> Converter<W> {
+        converter.resolver.strategy = strategy;
+        converter
+    }
+
+    pub fn update_multimap_policy(mut converter: Converter<W>, policy: MultiMapPolicy) -> Converter<W> {
+        let extra_columns = &mut converter.resolver.extra_columns;
+        if !matches!(policy, MultiMapPolicy::Drop) && !extra_columns.contains(&ExtraColumn::MultiMap) {
+            extra_columns.push(ExtraColumn::MultiMap);
+        }
+        converter.resolver.multimap_policy = policy;
+        converter
+    }
+
+    pub fn update_walk_policy(mut converter: Converter<W>, policy: WalkPolicy) -> Converter<W> {
+        converter.resolver.walk_policy = policy;
+        converter.stats.walk_stats = WalkStat::new(policy);
+        converter
+    }
+
+    pub fn update_filtered_file(mut converter: Converter<W>, filtered_file: &Path) -> Converter<W> {
+        converter.filtered_file = Some(bgzf::create_writer(filtered_file).expect("Problem with file"));
+        converter.resolver.keep_filtered = true;
+        converter
+    }
+
+    // Alignments which produced kept pairs are saved into bam annotated with Hi-C specific tags.
+    pub fn update_bam_output(mut converter: Converter<W>, bam_file: &Path) -> Converter<W> {
+        converter.bam_output_path = Some(PathBuf::from(bam_file));
+        converter.resolver.keep_alignments = true;
+        converter
+    }
+
+    pub fn update_fragments(mut converter: Converter<W>, fragments: FragmentIndex) -> Converter<W> {
+        if !converter.resolver.extra_columns.contains(&ExtraColumn::Fragments) {
+            converter.resolver.extra_columns.push(ExtraColumn::Fragments);
+        }
+        converter.resolver.fragments = Some(fragments);
+        converter
+    }
+
+    pub fn update_extra_columns(mut converter: Converter<W>, columns: &[ExtraColumn]) -> Converter<W> {
+        for col in columns {
+            if !converter.resolver.extra_columns.contains(col) {
+                converter.resolver.extra_columns.push(*col);
+            }
+        }
         converter
     }
 
-    pub fn update_max_mol_size(mut converter: Converter, mol_sz: u64) -> Converter {
-        converter.max_molecule_size = mol_sz;
+    pub fn update_unpaired_policy(mut converter: Converter<W>, policy: UnpairedPolicy) -> Converter<W> {
+        converter.unpaired_policy = policy;
         converter
     }
 
-    pub fn update_matched_rate_tresh(mut converter: Converter, tresh: f64) -> Converter {
-        converter.matched_rate_tresh = tresh;
+    // Reference is required for reading alignments in cram format.
+    pub fn update_reference(mut converter: Converter<W>, reference: &Path) -> Converter<W> {
+        converter.reference_path = Some(PathBuf::from(reference));
         converter
     }
 
-    pub fn update_mapq_zero_rescue(mut converter: Converter, mapq_zero_rescue: bool) -> Converter {
-        converter.mapq_zero_rescue = mapq_zero_rescue;
+    // Threads are used both for decompression of bam and for processing of read groups.
+    pub fn update_threads(mut converter: Converter<W>, threads: usize) -> Converter<W> {
+        converter.threads = threads.max(1);
         converter
     }
 
-    pub fn save_statistic(&self, file_path: &Path) {
-        self.stats.dump_stats_to_file(file_path);
+    // Applies parameters of conversion. Rescue of alignments with zero mapq needs assembly graph, it is turned
+    // off without graph (config saved with pairs and statistics has the value which is actually used).
+    pub fn update_config(converter: Converter<W>, config: &ConverterConfig) -> Converter<W> {
+        let mapq_zero_rescue = config.mapq_zero_rescue && converter.graph_path.is_some();
+        if config.mapq_zero_rescue && !mapq_zero_rescue {
+            warn!("Assembly graph is not provided, alignments with zero mapq will not be rescued.");
+        }
+
+        let converter = Converter::update_min_mapq(converter, config.min_mapq);
+        let converter = Converter::update_max_mol_size(converter, config.max_molecule_size);
+        let converter = Converter::update_matched_rate_tresh(converter, config.matched_rate_tresh);
+        let converter = Converter::update_mapq_zero_rescue(converter, mapq_zero_rescue);
+        let converter = Converter::update_strategy(converter, config.strategy);
+        let converter = Converter::update_walk_policy(converter, config.walks_policy);
+        let converter = Converter::update_multimap_policy(converter, MultiMapPolicy::new(config.multimap, config.seed));
+        let converter = Converter::update_unpaired_policy(converter, config.unpaired);
+        Converter::update_extra_columns(converter, config.columns.as_slice())
+    }
+
+    // Parameters which are actually used for conversion.
+    pub fn get_config(&self) -> ConverterConfig {
+        let resolver = &self.resolver;
+        let seed = match resolver.multimap_policy {
+            MultiMapPolicy::Random(seed) => seed,
+            _ => 0
+        };
+
+        ConverterConfig {
+            min_mapq: resolver.min_mapq,
+            max_molecule_size: resolver.max_molecule_size,
+            matched_rate_tresh: resolver.matched_rate_tresh,
+            mapq_zero_rescue: resolver.mapq_zero_rescue,
+            strategy: resolver.strategy,
+            walks_policy: resolver.walk_policy,
+            multimap: resolver.multimap_policy.get_mode(),
+            seed,
+            unpaired: self.unpaired_policy,
+            columns: resolver.extra_columns.clone(),
+            enzymes: resolver.fragments.as_ref().map(|f| f.enzyme_names().to_vec()).unwrap_or_default(),
+        }
+    }
+
+    pub fn save_statistic(&self, file_path: &Path) -> io::Result<()> {
+        self.stats.dump_stats_to_file(file_path)
+    }
+
+    // Returns writer of pairs and statistics of conversion (e.g. to be completed after deduplication).
+    pub fn into_parts(mut self) -> io::Result<(W, ConverterStat)> {
+        self.pair_file.flush()?;
+        Ok((self.pair_file, self.stats))
     }
 
     pub fn convert(&mut self) -> io::Result<()> {
         let mut recs1 = vec![];
         let mut recs2 = vec![];
+        let mut groups = Vec::with_capacity(GROUPS_PER_CHUNK * CHUNKS_PER_BATCH);
+        let mut n_chunks = 0;
         let mut prev_read_id: Option<AsciiString> = None;
+        let mut finished_reads = ReadNameTracker::new();
+
+        self.load_graph()?;
 
-        let mut reader = bam::BamReader::from_path(self.bam_path.as_path(), 0).unwrap();
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(self.threads).build()
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        let mut reader = AlignmentReader::open(self.input_path.as_path(), self.reference_path.as_deref(),
+                                               (self.threads - 1) as u16)?;
 
         trace!("Reading header...");
         let header = reader.header().clone();
+        input::check_sort_order(&header)?;
+        let config = self.get_config();
+        let mut pairs_header = PairsHeader::from_bam_header(&header, &self.resolver.extra_columns);
+        pairs_header.other.push(config.to_header_line());
+        self.stats.config = Some(config);
+        pairs_header.write(&mut self.pair_file)?;
+        if let Some(f) = &mut self.filtered_file {
+            pairs_header.write(f)?;
+        }
+        if let Some(path) = &self.bam_output_path {
+            self.bam_output = Some(bam::BamWriter::build()
+                .additional_threads((self.threads - 1) as u16)
+                .from_path(path, header.clone())?);
+        }
 
         trace!("Reading body...");
         let mut record = bam::Record::new();
@@ -101,12 +346,38 @@ impl Converter {
 
             self.stats.update_align_count();
 
-            let r_id = AsciiString::from_ascii(record.name()).unwrap();
+            if self.stats.alignment_counter % 1000000 == 0 {
+                info!("{} alignments were processed", self.stats.alignment_counter);
+            }
+
+            let r_id = AsciiString::from_ascii(record.name())
+                .map_err(|_| invalid_input(format!("Read name {} is not ASCII string.", String::from_utf8_lossy(record.name()))))?;
+
+            if !record.flag().is_paired() || record.flag().first_in_pair() == record.flag().last_in_pair() {
+                self.stats.update_unpaired_count();
+                match self.unpaired_policy {
+                    UnpairedPolicy::Skip => {
+                        trace!("Skipping alignment of unpaired read {}.", r_id);
+                        continue;
+                    },
+                    UnpairedPolicy::Error => return Err(invalid_input(format!("Read {} is not paired.", r_id))),
+                }
+            }
+
             if let Some(pr_id) = &prev_read_id {
                 if pr_id.as_str() != r_id.as_str() {
                     trace!("New group of alignments for {} is detected with {} and {}.", pr_id, recs1.len(), recs2.len());
-                    self.parse_paired_alignments(&recs1, &recs2, &header);
-                    recs1.clear(); recs2.clear();
+                    finished_reads.finish_group(pr_id.as_bytes());
+                    if finished_reads.is_finished(r_id.as_bytes()) {
+                        return Err(invalid_input(format!("Alignments of read {} are not grouped together, \
+                                                          alignments must be grouped by read name.", r_id)));
+                    }
+
+                    self.push_group(&mut groups, mem::take(&mut recs1), mem::take(&mut recs2))?;
+                    if groups.len() == GROUPS_PER_CHUNK * CHUNKS_PER_BATCH {
+                        let batch = mem::replace(&mut groups, Vec::with_capacity(GROUPS_PER_CHUNK * CHUNKS_PER_BATCH));
+                        n_chunks = self.process_batch(&pool, batch, &header, n_chunks)?;
+                    }
                 }
             }
 
@@ -115,74 +386,262 @@ impl Converter {
             if record.flag().first_in_pair() {
                 recs1.push(record.clone())
             } else {
-                assert!(record.flag().last_in_pair());
                 recs2.push(record.clone())
             }
-
-            if self.stats.alignment_counter % 1000000 == 0 {
-                info!("{} alignments were processed", self.stats.alignment_counter);
-            }
         }
 
         trace!("Dump the latest group of alignments");
-        self.parse_paired_alignments(&recs1, &recs2, &header);
+        self.push_group(&mut groups, recs1, recs2)?;
+        self.process_batch(&pool, groups, &header, n_chunks)?;
+        reader.finish()?;
+        self.pair_file.flush()?;
+        if let Some(f) = self.filtered_file.take() {
+            f.finish()?;
+        }
+        if let Some(w) = &mut self.bam_output {
+            w.finish()?;
+        }
+
+        Ok(())
+    }
+
+    // Groups where one of mates has no alignments are handled w.r.t. policy for unpaired reads.
+    fn push_group(&mut self, groups: &mut Vec<ReadGroup>, recs1: Vec<bam::Record>, recs2: Vec<bam::Record>) -> io::Result<()> {
+        if recs1.is_empty() && recs2.is_empty() {
+            return Ok(());
+        }
+
+        if recs1.is_empty() || recs2.is_empty() {
+            self.stats.update_singleton_count();
+            let name = recs1.first().or_else(|| recs2.first()).map(|r| String::from_utf8_lossy(r.name()).to_string());
+            return match self.unpaired_policy {
+                UnpairedPolicy::Skip => {
+                    trace!("Skipping read {} without alignments of its mate.", name.unwrap_or_default());
+                    Ok(())
+                },
+                UnpairedPolicy::Error => Err(invalid_input(format!("Mate of read {} has no alignments.", name.unwrap_or_default()))),
+            };
+        }
+
+        groups.push(ReadGroup { recs1, recs2 });
+        Ok(())
+    }
+
+    // Processes chunks of the batch concurrently and writes their output in order, returns number of processed chunks.
+    // Records are not Sync, so every worker gets its own chunk of groups instead of a slice of the shared batch.
+    fn process_batch(&mut self, pool: &rayon::ThreadPool, mut groups: Vec<ReadGroup>, header: &Header, n_chunks: usize) -> io::Result<usize> {
+        let mut chunks = Vec::with_capacity(groups.len() / GROUPS_PER_CHUNK + 1);
+        while groups.len() > GROUPS_PER_CHUNK {
+            let tail = groups.split_off(GROUPS_PER_CHUNK);
+            chunks.push(mem::replace(&mut groups, tail));
+        }
+        if !groups.is_empty() {
+            chunks.push(groups);
+        }
+
+        let resolver = &self.resolver;
+        let outputs: Vec<ChunkOutput> = pool.install(|| {
+            chunks.into_par_iter()
+                .enumerate()
+                .map(|(i, chunk)| resolver.process_chunk(&chunk, header, (n_chunks + i) as u64))
+                .collect::<io::Result<_>>()
+        })?;
+
+        let n_outputs = outputs.len();
+        for output in outputs {
+            self.pair_file.write_all(output.pairs.as_slice())?;
+            if let Some(f) = &mut self.filtered_file {
+                f.write_all(output.filtered.as_slice())?;
+            }
+            if let Some(w) = &mut self.bam_output {
+                for rec in output.alignments.iter() {
+                    w.write(rec)?;
+                }
+            }
+            self.stats.merge(&output.stats);
+        }
+        Ok(n_chunks + n_outputs)
+    }
 
+    fn load_graph(&mut self) -> io::Result<()> {
+        if !self.resolver.mapq_zero_rescue || self.resolver.graph.is_some() { return Ok(()); }
+
+        match &self.graph_path {
+            Some(path) => {
+                info!("Loading assembly graph from {} for rescuing alignments with zero mapq...", path.display());
+                let prepack = gfa_graph::parser::parse_gfa_v1(path)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+                self.resolver.graph = Some(OverlapIndex::from_prepack(&prepack));
+            },
+            None => warn!("Assembly graph is not provided, alignments with zero mapq will not be rescued."),
+        }
         Ok(())
     }
+}
 
-    fn parse_paired_alignments(&mut self, recs1: &[bam::Record], recs2: &[bam::Record], header: &bam::Header) {
+fn invalid_input(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl PairResolver {
+    // Random generator of chunk is seeded by its index, so that picked hits do not depend on scheduling of chunks.
+    fn process_chunk(&self, groups: &[ReadGroup], header: &Header, chunk_index: u64) -> io::Result<ChunkOutput> {
+        let seed = match self.multimap_policy {
+            MultiMapPolicy::Random(seed) => seed,
+            _ => 0
+        };
+        let mut out = ChunkOutput::new(SeededRng::new(seed.wrapping_add(chunk_index)), self.walk_policy);
+        for group in groups {
+            self.parse_paired_alignments(&mut out, &group.recs1, &group.recs2, header)?;
+        }
+        Ok(out)
+    }
+
+    fn parse_paired_alignments(&self, out: &mut ChunkOutput, recs1: &[bam::Record], recs2: &[bam::Record],
+                               header: &bam::Header) -> io::Result<()> {
         if recs1.is_empty() || recs2.is_empty() {
-            return;
+            return Ok(());
         }
 
-        self.stats.update_read_count();
-        self.stats.update_alignment_count(recs1);
-        self.stats.update_alignment_count(recs2);
+        out.stats.update_read_count();
+        out.stats.update_alignment_count(recs1);
+        out.stats.update_alignment_count(recs2);
 
         let prim_r1 = self.get_primary_alignment(recs1);
         let prim_r2 = self.get_primary_alignment(recs2);
 
         if prim_r1.zip(prim_r2).is_none() {
             warn!("It must be one and only one primary alignment for each read in pair");
-            return;
+            self.write_filtered(out, recs1[0].name(), None, None, (SideType::Corrupt, SideType::Corrupt), header);
+            return Ok(());
         }
 
         let prim_r1 = prim_r1.unwrap();
         let prim_r2 = prim_r2.unwrap();
 
-        self.stats.update_mapping_count(&prim_r1, &prim_r2);
+        out.stats.update_mapping_count(&prim_r1, &prim_r2);
 
         if !prim_r1.flag().is_mapped() || !prim_r2.flag().is_mapped() {
             trace!("At least one of reads in pair are unmapped.");
-            return;
+            let a1 = self.get_filtered_side(prim_r1, false);
+            let a2 = self.get_filtered_side(prim_r2, false);
+            self.write_filtered(out, prim_r1.name(), a1, a2, (SideType::Null, SideType::Null), header);
+            return Ok(());
         }
 
-        self.stats.update_mapq_count(prim_r1, prim_r2);
+        out.stats.update_mapq_count(prim_r1, prim_r2);
 
         if recs1.len() == 1 && recs2.len() == 1 {
             trace!("Pair read aligned 1&1 (perfectly) .");
-            let hic_records = self.convert_to_pair_records(prim_r1, prim_r2, header);
-            self.write_records(PairType::UU, &hic_records);
-        } else if (recs1.len() == 1 || recs2.len() == 1)
-            && matches!(self.strategy, RescueStrategy::Simple | RescueStrategy::Complex) {
-            trace!("Pair read aligned as 1&2 (simple).");
-            let resc_linear_pair = self.rescue_simple_walk(recs1, recs2);
-            if let Some((rec1, rec2)) = resc_linear_pair {
-                trace!("Hi-C read was rescued successfully.");
-                let hic_records = self.convert_to_pair_records(rec1, rec2, header);
-                self.write_records(PairType::UD, &hic_records);
+            let hic_records = self.convert_to_pair_records(out, prim_r1, prim_r2, header, (false, false), RescuePath::Linear)?;
+            let ligations: Vec<Ligation> = hic_records.first()
+                .map(|rec| Ligation { rec1: prim_r1, rec2: prim_r2, pair_type: rec.pair_type })
+                .into_iter()
+                .collect();
+            self.write_resolved(out, prim_r1, prim_r2, RescuePath::Linear, hic_records, &ligations, header);
+            return Ok(());
+        }
+
+        let can_resolve = match self.strategy {
+            RescueStrategy::Unique => false,
+            RescueStrategy::Simple => recs1.len() == 1 || recs2.len() == 1,
+            RescueStrategy::Complex => true
+        };
+
+        let walk = if can_resolve { walks::build_walk(recs1, recs2, self.max_molecule_size) } else { Vec::new() };
+        let walk_pairs = self.walk_policy.get_pairs(walk.len());
+        if can_resolve {
+            out.stats.update_walk_count(walk.len(), walk_pairs.len());
+        }
+
+        if walk_pairs.is_empty() {
+            trace!("Hi-C read was not rescued.");
+            let a1 = self.get_filtered_side(prim_r1, recs1.len() > 1);
+            let a2 = self.get_filtered_side(prim_r2, recs2.len() > 1);
+            self.write_filtered(out, prim_r1.name(), a1, a2, (SideType::Null, SideType::Null), header);
+            return Ok(());
+        }
+
+        let path = match (recs1.len(), recs2.len()) {
+            (1, 2) | (2, 1) => RescuePath::Simple,
+            (2, 2) => RescuePath::Complex,
+            _ => RescuePath::Walk
+        };
+        trace!("Hi-C read was resolved as {} walk with {} segments.", path, walk.len());
+
+        let mut hic_records = Vec::new();
+        let mut ligations = Vec::new();
+        for (index, &(i, j)) in walk_pairs.iter().enumerate() {
+            let (rec1, rec2) = (walk[i][0], walk[j][0]);
+            let is_chimeric = |rec: &bam::Record| if rec.flag().first_in_pair() { recs1.len() > 1 } else { recs2.len() > 1 };
+            let rescued = (is_chimeric(rec1), is_chimeric(rec2));
+            let records = self.convert_to_pair_records(out, rec1, rec2, header, rescued, path)?;
+            if let Some(rec) = records.first() {
+                ligations.push(Ligation { rec1, rec2, pair_type: rec.pair_type });
+            }
+            for mut rec in records {
+                rec.walk_pair_index = index as u32 + 1;
+                hic_records.push(rec);
             }
-        } else if recs1.len() < 3 && recs2.len() < 3 && matches!(self.strategy, RescueStrategy::Complex) {
-            trace!("Pair read aligned as 2&2 (complex).");
-            let resc_linear_pair = self.rescue_complex_walk(recs1, recs2);
-            if let Some((rec1, rec2)) = resc_linear_pair {
-                trace!("Hi-C read was rescued successfully.");
-                let hic_records = self.convert_to_pair_records(rec1, rec2, header);
-                self.write_records(PairType::DD, &hic_records);
+        }
+        self.write_resolved(out, prim_r1, prim_r2, path, hic_records, &ligations, header);
+        Ok(())
+    }
+
+    fn write_resolved(&self, out: &mut ChunkOutput, prim_r1: &bam::Record, prim_r2: &bam::Record, path: RescuePath,
+                      hic_records: Vec<PairRecord>, ligations: &[Ligation], header: &Header) {
+        if hic_records.is_empty() {
+            trace!("Alignments of Hi-C read have low mapq.");
+            let a1 = self.get_filtered_side(prim_r1, false);
+            let a2 = self.get_filtered_side(prim_r2, false);
+            self.write_filtered(out, prim_r1.name(), a1, a2, (SideType::Null, SideType::Null), header);
+        } else {
+            self.write_records(out, path, hic_records);
+            if self.keep_alignments {
+                self.write_alignments(out, path, ligations, header);
+            }
+        }
+    }
+
+    // Alignment which is a part of several pairs of a walk is written once with its first ligation partner.
+    fn write_alignments(&self, out: &mut ChunkOutput, path: RescuePath, ligations: &[Ligation], header: &Header) {
+        let mut written: Vec<&bam::Record> = Vec::new();
+        for ligation in ligations {
+            for &(rec, partner) in [(ligation.rec1, ligation.rec2), (ligation.rec2, ligation.rec1)].iter() {
+                if written.iter().any(|&r| std::ptr::eq(r, rec)) { continue; }
+                written.push(rec);
+
+                let mut rec = rec.clone();
+                let partner_tig = header.reference_name(partner.ref_id() as u32).unwrap_or("*");
+                let tags = rec.tags_mut();
+                for tag in [PAIR_TYPE_TAG, RESCUE_TAG, PARTNER_TIG_TAG, PARTNER_POS_TAG].iter() {
+                    tags.remove(tag);
+                }
+                tags.push_string(PAIR_TYPE_TAG, ligation.pair_type.to_string().as_bytes());
+                tags.push_string(RESCUE_TAG, path.to_string().as_bytes());
+                tags.push_string(PARTNER_TIG_TAG, partner_tig.as_bytes());
+                tags.push_num(PARTNER_POS_TAG, pair_record::get_alignment_pos(partner) as i32);
+                out.alignments.push(rec);
             }
         }
     }
 
+    // Side of filtered pair, it is None for unmapped read.
+    fn get_filtered_side(&self, rec: &bam::Record, is_chimeric: bool) -> Option<Alignment> {
+        if !rec.flag().is_mapped() || rec.ref_id() == -1 {
+            return None;
+        }
+
+        let side_type = if is_chimeric {
+            SideType::Walk
+        } else if rec.mapq() >= self.min_mapq {
+            SideType::Unique
+        } else {
+            SideType::Multi
+        };
+        Some(Alignment::from_bam(rec, side_type))
+    }
+
     fn get_primary_alignment<'a>(&self, records: &'a [bam::Record]) -> Option<&'a bam::Record> {
         let mut primary = None;
         for rec in records {
@@ -193,260 +652,154 @@ impl Converter {
         primary
     }
 
-    fn rescue_simple_walk<'a>(&self, recs1: &'a[bam::Record], recs2: &'a[bam::Record])
-        -> Option<(&'a bam::Record, &'a bam::Record)> {
-        if recs1.len() != 1 && recs2.len() != 1 { return None; }
-
-        let linear_algn = if recs1.len() == 1 { &recs1[0] } else { &recs2[0] };
-        let (falgn, salgn) = if recs1.len() == 1 {(&recs2[0], &recs2[1])} else {(&recs1[0], &recs1[1])};
-
-        let dist_fa = pair_record::calc_dist(falgn, linear_algn);
-        let dist_sa = pair_record::calc_dist(salgn, linear_algn);
+    fn convert_to_pair_records(&self, out: &mut ChunkOutput, prim_r1: &bam::Record, prim_r2: &bam::Record, header: &Header,
+                               rescued: (bool, bool), path: RescuePath) -> io::Result<Vec<PairRecord>> {
+        let (algns1, res1) = self.get_pair_sides(out, prim_r1, header, rescued.0)?;
+        let (algns2, res2) = self.get_pair_sides(out, prim_r2, header, rescued.1)?;
+        let multimap = self.get_multimap_annotation((res1, algns1.len()), (res2, algns2.len()));
+
+        Ok(Vec::from_iter(algns1.iter().cartesian_product(algns2.iter()).map(|(a1, a2)| {
+            let mut rec = PairRecord::from_alignments(prim_r1.name(), a1, a2, header);
+            rec.rescue = path;
+            rec.multimap = multimap;
+            rec
+        })))
+    }
 
-        trace!("Distances for simple read are {} {}", dist_fa, dist_sa);
-        let cor_end;
-        let on_linear_side;
-        if dist_sa < self.max_molecule_size {
-            cor_end = falgn;
-            on_linear_side = salgn;
-        } else if dist_fa < self.max_molecule_size {
-            cor_end = salgn;
-            on_linear_side = falgn;
-        } else {
-            return None
+    // Returns possible sides of Hi-C pair for the alignment and how they were resolved with XA tag (if they were).
+    fn get_pair_sides(&self, out: &mut ChunkOutput, rec: &bam::Record, header: &Header, rescued: bool)
+                      -> io::Result<(Vec<Alignment>, Option<MultiMapResolution>)> {
+        if rec.mapq() >= self.min_mapq {
+            let side_type = if rescued { SideType::Rescued } else { SideType::Unique };
+            return Ok((vec![Alignment::from_bam(rec, side_type)], None));
         }
 
-        if pair_record::is_opposite_pair(on_linear_side, linear_algn) {
-            Some((cor_end, linear_algn))
-        } else {
-            None
+        if rec.mapq() == 0 && self.mapq_zero_rescue {
+            let ans = self.rescue_via_graph(out, rec, header)?;
+            if !ans.is_empty() { return Ok((ans, None)); }
         }
-    }
-
-    fn rescue_complex_walk<'a>(&self, recs1: &'a[bam::Record], recs2: &'a[bam::Record])
-                               -> Option<(&'a bam::Record, &'a bam::Record)> {
-        if recs1.len() != 2 || recs2.len() != 2 { return None; }
 
-        let dist00 = pair_record::calc_dist(&recs1[0], &recs2[0]);
-        let dist01 = pair_record::calc_dist(&recs1[0], &recs2[1]);
-        let dist10 = pair_record::calc_dist(&recs1[1], &recs2[0]);
-        let dist11 = pair_record::calc_dist(&recs1[1], &recs2[1]);
+        Ok(self.resolve_via_xa(out, rec, header))
+    }
 
-        trace!("Distances for complex read are {} {} {} {}", dist00, dist01, dist10, dist11);
+    // Contig of alignment must be in BAM header, otherwise alternatives can not be looked up in the graph.
+    fn rescue_via_graph(&self, out: &mut ChunkOutput, rec: &bam::Record, header: &Header) -> io::Result<Vec<Alignment>> {
+        let mut ans = Vec::new();
 
-        if dist00 < self.max_molecule_size && dist11 < self.max_molecule_size {
-            if pair_record::is_opposite_pair(&recs1[0], &recs2[0])
-                && pair_record::is_opposite_pair(&recs1[1], &recs2[1]) {
-                Some((&recs1[0], &recs2[1]))
-            } else {
-                None
-            }
-        } else if dist01 < self.max_molecule_size && dist10 < self.max_molecule_size {
-            if pair_record::is_opposite_pair(&recs1[0], &recs2[1])
-                && pair_record::is_opposite_pair(&recs1[1], &recs2[0]) {
-                Some((&recs1[0], &recs2[0]))
-            } else {
-                None
+        if let Some(graph) = &self.graph {
+            if pair_record::get_matched_proportion(rec) < self.matched_rate_tresh {
+                return Ok(ans);
             }
-        } else {
-            None
-        }
-    }
-
-    fn convert_to_pair_records(&self, prim_r1: &bam::Record, prim_r2: &bam::Record, header: &Header) -> Vec<PairRecord> {
-        fn get_pairs(rec: &bam::Record, min_mapq: u8, is_rescue: bool) -> Vec<bam::Record> {
-            let mut ans = Vec::new();
-
-            if rec.mapq() >= min_mapq {
-                ans.push(rec.clone())
-            } else if rec.mapq() == 0 && is_rescue {
-                // println!("Add for future support. ")
-                //                 can_save = (rec.matched_proportion() - self.MATCHED_PROPORTION_TRESH >= 0)
-                //
-                //                 if can_save:
-                //                     alts = self.graph.get_recs_within_overlap(rec.ref_name, rec.ref_algn_start)
-                //
-                //                     for alt in alts:
-                //                         n_name, n_pos, n_strand = alt
-                //                         new_rec = deepcopy(rec)
-                //                         new_rec.ref_name = n_name
-                //                         new_rec.ref_algn_start = n_pos
-                //                         new_rec.strand = n_strand
-                //                         logger.debug(f"New pair in overlaps {n_name} {n_pos} {n_strand}")
-                //                         ans.append(new_rec)
-                //
-                //                     if len(alts):
-                //                         logger.debug(f"New pair in overlaps {rec.ref_name} {rec.ref_algn_start} {rec.strand}")
-                //                         ans.append(rec)
-                //                         logger.debug(f"We rescued read {rec.query_name} with {len(ans)} alignments")
+
+            let algn = Alignment::from_bam(rec, SideType::Multi);
+            let ref_name = header.reference_name(algn.ref_id).ok_or_else(|| {
+                invalid_input(format!("Contig {} of read {} is absent in BAM header", algn.ref_id,
+                                      String::from_utf8_lossy(rec.name())))
+            })?;
+            for (n_name, n_pos, n_strand) in graph.get_alts_within_overlap(ref_name, algn.pos as u64) {
+                if let Some(n_ref_id) = header.reference_id(n_name.as_str()) {
+                    trace!("New pair in overlaps {} {} {}", n_name, n_pos, n_strand);
+                    let strand = match (algn.strand, n_strand) {
+                        (s, Orientation::Forward) => s,
+                        (Strand::Forward, Orientation::Reverse) => Strand::Reverse,
+                        (Strand::Reverse, Orientation::Reverse) => Strand::Forward,
+                    };
+                    ans.push(Alignment { ref_id: n_ref_id, pos: n_pos as i64, strand, mapq: rec.mapq(), side_type: SideType::Multi });
+                }
             }
 
-            ans
+            if !ans.is_empty() {
+                trace!("We rescued alignment on {} {} with {} alternatives", ref_name, algn.pos, ans.len());
+                ans.push(algn);
+                out.stats.update_rescue_count(ans.len() as u64);
+            }
         }
 
-        let recs1 = get_pairs(prim_r1, self.min_mapq, self.mapq_zero_rescue);
-        let recs2 = get_pairs(prim_r2, self.min_mapq, self.mapq_zero_rescue);
-
-        Vec::from_iter(recs1.iter().cartesian_product(recs2.iter()).map(|(r1, r2)| {
-            PairRecord::from_bams(r1, r2, header)
-        }))
+        Ok(ans)
     }
 
-    fn write_records(&mut self, tp: PairType, records: &[PairRecord]) {
-        self.stats.update_cis_trans_count(records);
-        self.stats.update_pair_count(tp, records.len() as u64);
-        trace!("Saving {} Hi-C pairs into file", records.len());
-        for rec in records {
-            writeln!(self.pair_file, "{}", rec.to_string()).expect("Problem with writing file");
+    fn resolve_via_xa(&self, out: &mut ChunkOutput, rec: &bam::Record, header: &Header) -> (Vec<Alignment>, Option<MultiMapResolution>) {
+        if matches!(self.multimap_policy, MultiMapPolicy::Drop) {
+            return (Vec::new(), None);
         }
-    }
-}
 
-enum PairType {
-    UU,
-    UD,
-    DD
-}
-
-struct ConverterStat {
-    read_counter: u64,  // total number of reads
-    alignment_counter: u64,  // total number of alignment records
-    pairs_counter: u64, // total amount of pairst
-    
-    // n = non-mapped, m = mapped, each letter is for a read in pair
-    nn_counter: u64, 
-    nm_counter: u64,
-    mm_counter: u64,
-
-    // supp = supplementary, sec = secondary, prime = prime
-    prime_counter: u64,
-    supp_counter: u64,
-    sec_counter: u64,
-
-    // mapq = 0 is interesting since it may have perfect alignments but with XA tag
-    mq00_counter: u64,
-    mq01_counter: u64,
-    mq11_counter: u64,
-
-    // cis or trans hic pairs
-    intra_counter: u64,
-    inter_counter: u64,
-
-    // pair_type of hic pairs
-    uu_pair_counter: u64,
-    uw_pair_counter: u64,
-    ww_pair_counter: u64,
-}
-
-impl ConverterStat { 
-    pub fn new() -> ConverterStat { 
-        ConverterStat {
-            read_counter: 0,
-            alignment_counter: 0,
-            pairs_counter: 0,
-            nn_counter: 0,
-            nm_counter: 0,
-            mm_counter: 0,
-            prime_counter: 0,
-            supp_counter: 0,
-            sec_counter: 0,
-            mq00_counter: 0,
-            mq01_counter: 0,
-            mq11_counter: 0,
-            intra_counter: 0,
-            inter_counter: 0,
-            uu_pair_counter: 0,
-            uw_pair_counter: 0,
-            ww_pair_counter: 0
+        let hits = xa_tag::parse_xa_tag(rec);
+        if hits.is_empty() {
+            return (Vec::new(), None);
         }
-    }
 
-    pub fn update_align_count(&mut self) {
-        self.alignment_counter += 1;
-    }
-
-    pub fn update_read_count(&mut self) {
-        self.read_counter += 1
+        let mut candidates = vec![Alignment::from_bam(rec, SideType::Multi)];
+        for hit in hits {
+            match header.reference_id(hit.ref_name.as_str()) {
+                Some(ref_id) => candidates.push(Alignment {
+                    ref_id, pos: hit.get_alignment_pos(), strand: hit.strand, mapq: rec.mapq(), side_type: SideType::Multi
+                }),
+                None => warn!("Reference {} from XA tag is not in bam header.", hit.ref_name),
+            }
+        }
+        trace!("Alignment has {} equally good hits", candidates.len());
+        out.stats.update_multimap_count(candidates.len() as u64);
+
+        match self.multimap_policy {
+            MultiMapPolicy::Drop => (Vec::new(), None),
+            MultiMapPolicy::Random(_) => {
+                let ind = out.rng.next_index(candidates.len());
+                (vec![candidates.swap_remove(ind)], Some(MultiMapResolution::Random))
+            },
+            MultiMapPolicy::All => (candidates, Some(MultiMapResolution::All)),
+        }
     }
 
-    pub fn update_pair_count(&mut self, tp: PairType, count: u64) {
-        match tp {
-            PairType::UU => { self.uu_pair_counter += count; },
-            PairType::UD => { self.uw_pair_counter += count; }
-            PairType::DD => { self.ww_pair_counter += count; }
+    // Pair annotation is written only if XA tags are used. If all hits are used, weight of a pair is shared
+    // between all combinations of hits.
+    fn get_multimap_annotation(&self, side1: (Option<MultiMapResolution>, usize), side2: (Option<MultiMapResolution>, usize))
+        -> Option<(MultiMapResolution, f64)> {
+        if matches!(self.multimap_policy, MultiMapPolicy::Drop) {
+            return None;
         }
-    }
 
-    pub fn update_alignment_count(&mut self, records: &[bam::Record]) {
-        for rec in records {
-            if rec.flag().is_secondary() {
-                self.sec_counter += 1;
-            } else if rec.flag().is_supplementary() {
-                self.supp_counter += 1;
-            } else {
-                self.prime_counter += 1;
+        let mut resolution = MultiMapResolution::Unique;
+        let mut weight = 1.0;
+        for &(res, n_hits) in [side1, side2].iter() {
+            match res {
+                Some(MultiMapResolution::All) => {
+                    resolution = MultiMapResolution::All;
+                    weight /= n_hits as f64;
+                },
+                Some(r) if resolution == MultiMapResolution::Unique => resolution = r,
+                _ => {}
             }
         }
+        Some((resolution, weight))
     }
 
-    pub fn update_mapping_count(&mut self, rec1: &bam::Record, rec2: &bam::Record) {
-        if !rec1.flag().is_mapped() && !rec2.flag().is_mapped() {
-            self.nn_counter += 1;
-        } else if rec1.flag().is_mapped() != rec2.flag().is_mapped() {
-            self.nm_counter += 1;
-        } else {
-            self.mm_counter += 1;
+    fn write_records(&self, out: &mut ChunkOutput, path: RescuePath, mut records: Vec<PairRecord>) {
+        if let Some(index) = &self.fragments {
+            for rec in records.iter_mut() {
+                rec.annotate_fragments(index);
+                out.stats.update_fragment_count(rec);
+            }
         }
-    }
-
-    pub fn update_mapq_count(&mut self, rec1: &bam::Record, rec2: &bam::Record) {
-        if rec1.mapq() == 0 && rec2.mapq() == 0 { // Both of alignments are not unique
-            self.mq00_counter += 1;
-        } else if (rec1.mapq() == 0) != (rec2.mapq() == 0) { // One of alignments unique, another one is not
-            self.mq01_counter += 1;
-        } else {
-            self.mq11_counter += 1;
+        out.stats.update_cis_trans_count(&records);
+        out.stats.update_pair_count(path, records.len() as u64);
+        if let Some(rec) = records.first() {
+            out.stats.update_pair_type_count(rec.pair_type);
         }
-    }
-
-    pub fn update_cis_trans_count(&mut self, recs: &[PairRecord]) {
-        self.pairs_counter += recs.len() as u64;
-        for rec in recs {
-            if rec.name1 == rec.name2 { self.intra_counter += 1; } else { self.inter_counter += 1 }
+        trace!("Saving {} Hi-C pairs into file", records.len());
+        for rec in records.iter() {
+            writeln!(out.pairs, "{}", rec.to_string(&self.extra_columns)).expect("Problem with writing file");
         }
     }
 
-    pub fn dump_stats_to_file(&self, file_path: &Path) {
-        let f = File::create(file_path).expect("Problem with file");
-        let mut f = BufWriter::new(f);
-
-        writeln!(f, "Total number of good aligned reads {}", self.read_counter).expect("Problem with writing file");
-        writeln!(f, "Total number of good alignment records {}", self.alignment_counter).expect("Problem with writing file");
-        writeln!(f, "Total number of saved HiC pairs {}", self.pairs_counter).expect("Problem with writing file");
-
-        writeln!(f, "\nMapping Statistics").expect("Problem with writing file");
-        writeln!(f, "\tBoth reads in pair are unmapped {}", self.nn_counter).expect("Problem with writing file");
-        writeln!(f, "\tOne of the reads in pair is unmapped {}", self.nm_counter).expect("Problem with writing file");
-        writeln!(f, "\tBoth reads in pair are mapped {}", self.mm_counter).expect("Problem with writing file");
-
-        writeln!(f, "\nAlignment Type Statistics").expect("Problem with writing file");
-        writeln!(f, "\tPrimary alignments {}", self.prime_counter).expect("Problem with writing file");
-        writeln!(f, "\tSupplementary alignments {}", self.supp_counter).expect("Problem with writing file");
-        writeln!(f, "\tSecondary alignments {}", self.sec_counter).expect("Problem with writing file");
-
-        writeln!(f, "\nPair Alignment Statistics").expect("Problem with writing file");
-        writeln!(f, "\tBoth reads have non-unique mapping {}", self.mq00_counter).expect("Problem with writing file");
-        writeln!(f, "\tOne of the reads has non-unique mapping {}", self.mq01_counter).expect("Problem with writing file");
-        writeln!(f, "\tBoth reads have unique mapping {}", self.mq11_counter).expect("Problem with writing file");
-
-        writeln!(f, "\nHi-C Pair Statistics").expect("Problem with writing file");
-        writeln!(f, "\tIntra (cis-) tig interaction {}", self.intra_counter).expect("Problem with writing file");
-        writeln!(f, "\tInter (trans-) tig interaction {}", self.inter_counter).expect("Problem with writing file");
-        writeln!(f, "").expect("Problem with writing file");
-        writeln!(f, "\tUnique pairs {}", self.uu_pair_counter).expect("Problem with writing file");
-        writeln!(f, "\tSimple pairs {}", self.uw_pair_counter).expect("Problem with writing file");
-        writeln!(f, "\tvpairs {}", self.ww_pair_counter).expect("Problem with writing file");
-
-        f.flush().expect("Problem with flushing");
+    fn write_filtered(&self, out: &mut ChunkOutput, qname: &[u8], a1: Option<Alignment>, a2: Option<Alignment>,
+                      null_types: (SideType, SideType), header: &Header) {
+        let mut rec = PairRecord::from_filtered(qname, a1, a2, null_types, header);
+        if let Some(index) = &self.fragments {
+            rec.annotate_fragments(index);
+        }
+        out.stats.update_pair_type_count(rec.pair_type);
+        if self.keep_filtered {
+            writeln!(out.filtered, "{}", rec.to_string(&self.extra_columns)).expect("Problem with writing file");
+        }
     }
-} 
+}
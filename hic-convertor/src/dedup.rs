@@ -1,91 +1,544 @@
 use std::path::Path;
 use std::fs::File;
 use std::collections::VecDeque;
-use std::io::{BufWriter, Write};
-
-use log::info;
-use serde::Deserialize;
-
-pub fn deduplicate_pairs(inp_file: &Path, out_file: &Path, max_mismatch: i64) {
-    // Find and remove PCR/optical duplicates.
-    // Find PCR duplicates in an upper-triangular flipped sorted pairs file.
-    // Allow for a +/-N bp mismatch at each side of duplicated molecules.
-    let input = File::open(inp_file).unwrap();
-    let output= File::create(out_file).unwrap();
-
-    let mut rdr = csv::ReaderBuilder::new()
-        .delimiter(b'\t')
-        .comment(Some(b'#'))
-        .has_headers(false)
-        .from_reader(input);
-    let mut wrtr = BufWriter::new(output);
-
-    let mut total: u32 = 0;
-    let mut raw_record = csv::ByteRecord::new();
-    let mut cur_records = VecDeque::<(Record, bool)>::new();
-    while rdr.read_byte_record(&mut raw_record).unwrap() {
-        total += 1;
-
-        let low = cur_records.pop_front();
-        let high: Record = raw_record.deserialize(None).unwrap();
-
-        match low {
-            None => cur_records.push_back((high, false)),
-            Some((low, l_rm)) => {
-                if l_rm {
-                    cur_records.push_back((high, false));
-                    cur_records = update_records_wrt_first(cur_records);
-                } else if low.name1 != high.name1 || low.name2 != high.name2
-                    || high.pos1 - low.pos1 > max_mismatch || high.pos1 - low.pos1 < 0
-                    || high.pos2 - low.pos2 > max_mismatch || high.pos2 - low.pos2 < 0 {
-                    // if we jumped too far, continue
-                    save_record(&low, &mut wrtr);
-                    cur_records.push_back((high, false));
-                    cur_records = update_records_wrt_first(cur_records);
-                } else if is_duplicated_copies(&low, &high, max_mismatch) {
-                    cur_records.push_front((low, l_rm));
-                } else {
-                    cur_records.push_front((low, l_rm));
-                    cur_records.push_back((high, false));
+use std::fmt;
+use std::io::{self, BufRead, BufWriter, Write};
+
+use log::{info, warn};
+use serde::Serialize;
+
+use super::bgzf;
+use super::pair_record;
+use super::pairs_format::{self, PairsHeader};
+use super::stats::StatsFormat;
+
+// Duplicates on the same tile within this distance (in pixels) are optical, the default is the one of Picard.
+pub const OPTICAL_DIST: i64 = 100;
+// Default tolerance for positions of duplicates is the one of pairtools.
+pub const MAX_MISMATCH: i64 = 3;
+
+const FIELD_SEP: char = '\t';
+const COMMENT_SYMBOL: char = '#';
+
+// Column with marks of duplicates added if duplicates are marked instead of dropping them.
+const DUP_MARK_COLUMN: &str = "duplicate";
+const UNIQUE_MARK: &str = "U";
+
+// Find and remove PCR/optical duplicates in an upper-triangular flipped sorted pairs file.
+// Unique records are written into output file, duplicates are written into separate file if it is provided.
+pub fn deduplicate_pairs(inp_file: &Path, out_file: &Path, dups_file: Option<&Path>,
+                         config: &DedupConfig) -> io::Result<DedupStat> {
+    let reader = bgzf::open_reader(inp_file)?;
+    let output = bgzf::create_writer(out_file)?;
+
+    let mut dedup = Deduplicator::new(output, config);
+    if let Some(dups_file) = dups_file {
+        dedup = Deduplicator::update_dups_file(dedup, dups_file)?;
+    }
+
+    for line in reader.lines() {
+        dedup.push_line(line?.as_str())?;
+    }
+    let (output, stats) = dedup.finish()?;
+    output.finish()?;
+    Ok(stats)
+}
+
+// How duplicates are detected and reported. Two pairs are duplicates if they are on the same contigs and
+// both their positions differ by at most max_mismatch (strands must be the same if matching is strand-aware).
+// Duplicates are classified as optical if their reads are close on the same tile of flowcell, otherwise they
+// are PCR duplicates. Marked duplicates are written to output with mark column, otherwise they are dropped.
+#[derive(Clone, Copy)]
+pub struct DedupConfig {
+    pub max_mismatch: i64,
+    pub strand_aware: bool,
+    pub optical_dist: i64,
+    pub mark_dups: bool,
+}
+
+impl Default for DedupConfig {
+    fn default() -> DedupConfig {
+        DedupConfig { max_mismatch: MAX_MISMATCH, strand_aware: true, optical_dist: OPTICAL_DIST, mark_dups: false }
+    }
+}
+
+#[derive(Clone, Serialize)]
+pub struct DedupStat {
+    pub total: u64,
+    pub unique: u64,
+    pub duplicates: u64,
+    pub pcr_duplicates: u64,
+    pub optical_duplicates: u64,
+}
+
+impl DedupStat {
+    pub fn new() -> DedupStat {
+        DedupStat { total: 0, unique: 0, duplicates: 0, pcr_duplicates: 0, optical_duplicates: 0 }
+    }
+
+    fn rate(&self, count: u64) -> f64 {
+        if self.total == 0 { 0.0 } else { count as f64 / self.total as f64 }
+    }
+
+    pub fn dup_rate(&self) -> f64 {
+        self.rate(self.duplicates)
+    }
+
+    pub fn pcr_rate(&self) -> f64 {
+        self.rate(self.pcr_duplicates)
+    }
+
+    pub fn optical_rate(&self) -> f64 {
+        self.rate(self.optical_duplicates)
+    }
+
+    // Report with pairtools-like keys, rates are rounded to 6 digits.
+    pub fn to_key_values(&self) -> Vec<(String, String)> {
+        vec![
+            ("total_pairs".to_string(), self.total.to_string()),
+            ("total_nodups".to_string(), self.unique.to_string()),
+            ("total_dups".to_string(), self.duplicates.to_string()),
+            ("dups/pcr".to_string(), self.pcr_duplicates.to_string()),
+            ("dups/optical".to_string(), self.optical_duplicates.to_string()),
+            ("dups/rate".to_string(), format!("{:.6}", self.dup_rate())),
+            ("dups/pcr_rate".to_string(), format!("{:.6}", self.pcr_rate())),
+            ("dups/optical_rate".to_string(), format!("{:.6}", self.optical_rate())),
+        ]
+    }
+
+    // Report is written in JSON if file has .json extension, otherwise in TSV.
+    pub fn dump_stats_to_file(&self, file_path: &Path) -> io::Result<()> {
+        let mut f = BufWriter::new(File::create(file_path)?);
+
+        match StatsFormat::from_path(file_path) {
+            StatsFormat::Json => {
+                let report = serde_json::json!({
+                    "total": self.total,
+                    "unique": self.unique,
+                    "duplicates": self.duplicates,
+                    "pcr_duplicates": self.pcr_duplicates,
+                    "optical_duplicates": self.optical_duplicates,
+                    "dup_rate": self.dup_rate(),
+                    "pcr_rate": self.pcr_rate(),
+                    "optical_rate": self.optical_rate(),
+                });
+                serde_json::to_writer_pretty(&mut f, &report)
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+                writeln!(f)?;
+            },
+            StatsFormat::Tsv => {
+                for (key, value) in self.to_key_values() {
+                    writeln!(f, "{}{}{}", key, FIELD_SEP, value)?;
+                }
+            }
+        }
+
+        f.flush()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum DupKind {
+    Pcr,
+    Optical
+}
+
+impl fmt::Display for DupKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DupKind::Pcr => write!(f, "P"),
+            DupKind::Optical => write!(f, "O"),
+        }
+    }
+}
+
+// Writer of unique records and duplicates, duplicates are either marked in output or dropped from it (and
+// written into separate file if it is provided). Header lines are collected until the first record.
+pub struct DedupOutput<W: Write> {
+    output: W,
+    dups_output: Option<bgzf::PairsWriter>,
+    mark_dups: bool,
+    header: Vec<String>,
+    is_header_written: bool,
+    stats: DedupStat,
+}
+
+impl<W: Write> DedupOutput<W> {
+    pub fn new(output: W, mark_dups: bool) -> DedupOutput<W> {
+        DedupOutput {
+            output,
+            dups_output: None,
+            mark_dups,
+            header: Vec::new(),
+            is_header_written: false,
+            stats: DedupStat::new()
+        }
+    }
+
+    pub fn update_dups_file(mut out: DedupOutput<W>, dups_file: &Path) -> io::Result<DedupOutput<W>> {
+        out.dups_output = Some(bgzf::create_writer(dups_file)?);
+        Ok(out)
+    }
+
+    pub fn is_header_written(&self) -> bool {
+        self.is_header_written
+    }
+
+    pub fn push_header_line(&mut self, line: &str) -> io::Result<()> {
+        if self.is_header_written {
+            return Err(invalid_line(line));
+        }
+        self.header.push(line.to_string());
+        Ok(())
+    }
+
+    // Header is copied into outputs, column with marks is added to the main output if duplicates are marked.
+    // Returns parsed header if it is in pairs format.
+    pub fn write_header(&mut self) -> io::Result<Option<PairsHeader>> {
+        self.is_header_written = true;
+        if !self.header.first().map_or(false, |l| l.starts_with(pairs_format::FORMAT_LINE)) {
+            for line in self.header.iter() {
+                writeln!(self.output, "{}", line)?;
+            }
+            return Ok(None);
+        }
+
+        let header = PairsHeader::from_lines(self.header.iter().map(|l| l.as_str()))?;
+        if let Some(f) = &mut self.dups_output {
+            header.write(f)?;
+        }
+        if self.mark_dups && !header.columns.is_empty() {
+            let mut marked_header = header.clone();
+            marked_header.columns.push(DUP_MARK_COLUMN.to_string());
+            marked_header.write(&mut self.output)?;
+        } else {
+            header.write(&mut self.output)?;
+        }
+        Ok(Some(header))
+    }
+
+    pub fn save_record(&mut self, rec: &Record) -> io::Result<()> {
+        self.stats.total += 1;
+        self.stats.unique += 1;
+        self.log_progress();
+        if self.mark_dups {
+            writeln!(self.output, "{}{}{}", rec.line, FIELD_SEP, UNIQUE_MARK)
+        } else {
+            writeln!(self.output, "{}", rec.line)
+        }
+    }
+
+    pub fn save_duplicate(&mut self, rec: &Record, kind: DupKind) -> io::Result<()> {
+        self.stats.total += 1;
+        match kind {
+            DupKind::Pcr => self.stats.pcr_duplicates += 1,
+            DupKind::Optical => self.stats.optical_duplicates += 1,
+        }
+        self.log_progress();
+        if self.mark_dups {
+            writeln!(self.output, "{}{}{}", rec.line, FIELD_SEP, kind)?;
+        }
+        if let Some(f) = &mut self.dups_output {
+            writeln!(f, "{}", rec.line)?;
+        }
+        Ok(())
+    }
+
+    fn log_progress(&self) {
+        if self.stats.total % 10000000 == 0 {
+            info!("{} hic pairs were checked", self.stats.total);
+        }
+    }
+
+    // Returns output (to be finished by the caller) and statistics, duplicates file is finished here.
+    pub fn finish(mut self) -> io::Result<(W, DedupStat)> {
+        if !self.is_header_written {
+            self.write_header()?;
+        }
+        self.output.flush()?;
+        if let Some(f) = self.dups_output.take() {
+            f.finish()?;
+        }
+
+        self.stats.duplicates = self.stats.total - self.stats.unique;
+        info!("{} hic pairs were checked, {} of them are unique", self.stats.total, self.stats.unique);
+        info!("PCR duplicates rate is {:.4}, optical duplicates rate is {:.4}",
+              self.stats.pcr_rate(), self.stats.optical_rate());
+        Ok((self.output, self.stats))
+    }
+}
+
+// Removes duplicates from stream of sorted pairs records, lines are pushed one by one. Each record is compared
+// with unique records which precede it within tolerance, so records are written in the same order as pushed.
+pub struct Deduplicator<W: Write> {
+    out: DedupOutput<W>,
+    config: DedupConfig,
+    window: VecDeque<Record>,
+    prev_key: Option<(String, String, i64)>,
+}
+
+impl<W: Write> Deduplicator<W> {
+    pub fn new(output: W, config: &DedupConfig) -> Deduplicator<W> {
+        Deduplicator {
+            out: DedupOutput::new(output, config.mark_dups),
+            config: *config,
+            window: VecDeque::new(),
+            prev_key: None,
+        }
+    }
+
+    pub fn update_dups_file(mut dedup: Deduplicator<W>, dups_file: &Path) -> io::Result<Deduplicator<W>> {
+        dedup.out = DedupOutput::update_dups_file(dedup.out, dups_file)?;
+        Ok(dedup)
+    }
+
+    pub fn push_line(&mut self, line: &str) -> io::Result<()> {
+        if line.is_empty() { return Ok(()); }
+
+        if line.starts_with(COMMENT_SYMBOL) {
+            return self.out.push_header_line(line);
+        }
+
+        if !self.out.is_header_written() {
+            self.write_header()?;
+        }
+        self.push(Record::from_line(line)?)
+    }
+
+    pub fn push(&mut self, high: Record) -> io::Result<()> {
+        let max_mismatch = self.config.max_mismatch;
+
+        // only positions within the same pair of contigs are checked, order of contigs is not known in advance
+        let is_same_tigs = self.prev_key.as_ref().map_or(false, |(tig1, tig2, _)| *tig1 == high.tig1 && *tig2 == high.tig2);
+        match &mut self.prev_key {
+            Some((_, _, pos1)) if is_same_tigs => {
+                if *pos1 > high.pos1 {
+                    return Err(unsorted_error(&high));
                 }
+                *pos1 = high.pos1;
+            },
+            prev_key => {
+                self.window.clear();
+                *prev_key = Some((high.tig1.clone(), high.tig2.clone(), high.pos1));
             }
         }
 
-        if total % 10000000 == 0 {
-            info!("{} hic pairs were checked", total);
+        while self.window.front().map_or(false, |low| high.pos1 - low.pos1 > max_mismatch) {
+            self.window.pop_front();
+        }
+
+        let strand_aware = self.config.strand_aware;
+        let orig = self.window.iter().find(|low| is_duplicated_copies(low, &high, max_mismatch, strand_aware));
+        match orig.map(|low| get_dup_kind(low.read_name.as_str(), high.read_name.as_str(), self.config.optical_dist)) {
+            Some(kind) => self.out.save_duplicate(&high, kind),
+            None => {
+                self.out.save_record(&high)?;
+                self.window.push_back(high);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> io::Result<(W, DedupStat)> {
+        if !self.out.is_header_written() {
+            self.write_header()?;
+        }
+        self.out.finish()
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        match self.out.write_header()? {
+            None => warn!("Pairs file has no header, it is assumed to be sorted."),
+            Some(header) if header.sorted.as_deref() != Some(pairs_format::SORTED_ORDER) =>
+                warn!("Pairs are not declared as sorted by {}, they must be sorted for deduplication.", pairs_format::SORTED_ORDER),
+            Some(_) => {}
+        }
+        Ok(())
+    }
+}
+
+// Pairs record with its mandatory fields, the whole line is kept for output.
+#[derive(Clone)]
+pub struct Record {
+    pub line: String,
+    pub read_name: String,
+    pub tig1: String,
+    pub pos1: i64,
+    pub tig2: String,
+    pub pos2: i64,
+    pub strand1: String,
+    pub strand2: String,
+}
+
+impl Record {
+    pub fn from_line(line: &str) -> io::Result<Record> {
+        let fields: Vec<&str> = line.split(FIELD_SEP).collect();
+        if fields.len() <= pair_record::COL_STRAND2 {
+            return Err(invalid_line(line));
         }
+
+        Ok(Record {
+            line: line.to_string(),
+            read_name: fields[pair_record::COL_READID].to_string(),
+            tig1: fields[pair_record::COL_TIG1].to_string(),
+            pos1: fields[pair_record::COL_POS1].parse().map_err(|_| invalid_line(line))?,
+            tig2: fields[pair_record::COL_TIG2].to_string(),
+            pos2: fields[pair_record::COL_POS2].parse().map_err(|_| invalid_line(line))?,
+            strand1: fields[pair_record::COL_STRAND1].to_string(),
+            strand2: fields[pair_record::COL_STRAND2].to_string(),
+        })
+    }
+
+    fn is_same_tigs(&self, other: &Record) -> bool {
+        self.tig1 == other.tig1 && self.tig2 == other.tig2
+    }
+}
+
+fn is_duplicated_copies(rec1: &Record, rec2: &Record, max_mismatch: i64, strand_aware: bool) -> bool {
+    rec1.is_same_tigs(rec2)
+        && (!strand_aware || (rec1.strand1 == rec2.strand1 && rec1.strand2 == rec2.strand2))
+        && (rec1.pos1 - rec2.pos1).abs().max((rec1.pos2 - rec2.pos2).abs()) <= max_mismatch
+}
+
+// Duplicate is optical if both reads come from the same tile and are close to each other, reads with
+// names not in Illumina format are always considered as PCR duplicates.
+pub fn get_dup_kind(orig_name: &str, dup_name: &str, optical_dist: i64) -> DupKind {
+    match (IlluminaName::parse(orig_name), IlluminaName::parse(dup_name)) {
+        (Some(n1), Some(n2)) if n1.is_same_tile(&n2)
+            && (n1.x - n2.x).abs() <= optical_dist && (n1.y - n2.y).abs() <= optical_dist => DupKind::Optical,
+        _ => DupKind::Pcr
     }
 }
 
-#[derive(Debug, Deserialize, Clone)]
-struct Record {
-    read_name: String,
-    name1: String,
-    pos1: i64,
-    name2: String,
-    pos2: i64,
-    strand1: char,
-    strand2: char,
+// Location of cluster on flowcell from read name, e.g. INSTRUMENT:RUN:FLOWCELL:LANE:TILE:X:Y (Casava 1.8+)
+// or INSTRUMENT:LANE:TILE:X:Y (older format) optionally followed by #INDEX or /MATE.
+struct IlluminaName<'a> {
+    flowcell: &'a str,
+    lane: &'a str,
+    tile: &'a str,
+    x: i64,
+    y: i64,
+}
+
+impl<'a> IlluminaName<'a> {
+    fn parse(name: &'a str) -> Option<IlluminaName<'a>> {
+        let name = name.split(|c| c == ' ' || c == '#' || c == '/').next()?;
+        let fields: Vec<&str> = name.split(':').collect();
+        let (flowcell, lane, tile, x, y) = match fields.len() {
+            7 => (fields[2], fields[3], fields[4], fields[5], fields[6]),
+            5 => ("", fields[1], fields[2], fields[3], fields[4]),
+            _ => return None
+        };
+        Some(IlluminaName { flowcell, lane, tile, x: x.parse().ok()?, y: y.parse().ok()? })
+    }
+
+    fn is_same_tile(&self, other: &IlluminaName) -> bool {
+        self.flowcell == other.flowcell && self.lane == other.lane && self.tile == other.tile
+    }
 }
 
-fn is_duplicated_copies(rec1: &Record, rec2: &Record, max_mismatch: i64) -> bool {
-    rec1.name1 == rec2.name1 && rec1.name2 == rec2.name2
-        && rec1.strand1 == rec2.strand1 && rec1.strand2 == rec2.strand2
-        && (rec1.pos1 - rec2.pos1).abs().max(rec1.pos2 - rec2.pos2) <= max_mismatch
+fn invalid_line(line: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Incorrect pairs record: {}", line))
 }
 
-fn save_record(rec: &Record, output: &mut BufWriter<File>) {
-    writeln!(output, "{}", rec.read_name).expect("Problem with writing file");
+fn unsorted_error(rec: &Record) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData,
+                   format!("Pairs are not sorted by {} at record {}.", pairs_format::SORTED_ORDER, rec.read_name))
 }
 
-fn update_records_wrt_first(mut recs: VecDeque<(Record, bool)>) -> VecDeque<(Record, bool)> {
-    if recs.len() < 2 { return recs; }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let f_rec = recs.front().unwrap().clone();
-    if f_rec.1 { return recs; }
+    fn pair(name: &str, pos1: i64, pos2: i64, strand1: &str, strand2: &str) -> String {
+        format!("{}\tctg1\t{}\tctg2\t{}\t{}\t{}", name, pos1, pos2, strand1, strand2)
+    }
+
+    fn run_dedup(lines: &[String], config: &DedupConfig, dups_file: Option<&Path>) -> io::Result<(Vec<String>, DedupStat)> {
+        let mut dedup = Deduplicator::new(Vec::new(), config);
+        if let Some(dups_file) = dups_file {
+            dedup = Deduplicator::update_dups_file(dedup, dups_file)?;
+        }
+        for line in lines {
+            dedup.push_line(line)?;
+        }
+        let (output, stats) = dedup.finish()?;
+        let output = String::from_utf8(output).unwrap();
+        Ok((output.lines().map(|l| l.to_string()).collect(), stats))
+    }
+
+    #[test]
+    fn test_max_mismatch_bound() {
+        let config = DedupConfig::default();
+        let lines = vec![pair("r1", 100, 200, "+", "-"),
+                         pair("r2", 100 + MAX_MISMATCH, 200 - MAX_MISMATCH, "+", "-"),
+                         pair("r3", 200, 300, "+", "-"),
+                         pair("r4", 200 + MAX_MISMATCH + 1, 300, "+", "-"),
+                         pair("r5", 400, 500, "+", "-"),
+                         pair("r6", 400, 500 + MAX_MISMATCH + 1, "+", "-")];
+        let (output, stats) = run_dedup(&lines, &config, None).unwrap();
+        assert_eq!(output, vec![lines[0].clone(), lines[2].clone(), lines[3].clone(), lines[4].clone(), lines[5].clone()]);
+        assert_eq!(stats.total, 6);
+        assert_eq!(stats.unique, 5);
+        assert_eq!(stats.duplicates, 1);
+        assert_eq!(stats.pcr_duplicates, 1);
+    }
+
+    #[test]
+    fn test_strands() {
+        let lines = vec![pair("r1", 100, 200, "+", "-"), pair("r2", 100, 200, "-", "-"), pair("r3", 101, 200, "+", "+")];
+
+        let (output, stats) = run_dedup(&lines, &DedupConfig::default(), None).unwrap();
+        assert_eq!(output, lines);
+        assert_eq!(stats.duplicates, 0);
+
+        let config = DedupConfig { strand_aware: false, ..DedupConfig::default() };
+        let (output, stats) = run_dedup(&lines, &config, None).unwrap();
+        assert_eq!(output, vec![lines[0].clone()]);
+        assert_eq!(stats.duplicates, 2);
+    }
+
+    #[test]
+    fn test_dups_file() {
+        let dups_file = std::env::temp_dir().join(format!("hic_dedup_test_{}.pairs", std::process::id()));
+        let sorted = format!("{} {}", pairs_format::SORTED_PREFIX, pairs_format::SORTED_ORDER);
+        let lines = vec![pairs_format::FORMAT_LINE.to_string(), sorted,
+                         pair("A:1:FC:1:1101:1000:1000", 100, 200, "+", "-"),
+                         pair("A:1:FC:1:1101:1050:1020", 101, 200, "+", "-"),
+                         pair("A:1:FC:1:2202:1000:1000", 102, 201, "+", "-"),
+                         pair("r4", 500, 600, "+", "-")];
+        let (output, stats) = run_dedup(&lines, &DedupConfig::default(), Some(dups_file.as_path())).unwrap();
+        let dups = std::fs::read_to_string(&dups_file).unwrap();
+        std::fs::remove_file(&dups_file).unwrap();
+
+        assert_eq!(output, vec![lines[0].clone(), lines[1].clone(), lines[2].clone(), lines[5].clone()]);
+        let dups: Vec<&str> = dups.lines().collect();
+        assert_eq!(dups, vec![lines[0].as_str(), lines[1].as_str(), lines[3].as_str(), lines[4].as_str()]);
+        assert_eq!(stats.optical_duplicates, 1);
+        assert_eq!(stats.pcr_duplicates, 1);
+    }
+
+    #[test]
+    fn test_mark_dups() {
+        let columns = format!("{} {}", pairs_format::COLUMNS_PREFIX, pairs_format::COLUMNS.join(" "));
+        let lines = vec![pairs_format::FORMAT_LINE.to_string(), columns.clone(),
+                         pair("r1", 100, 200, "+", "-"), pair("r2", 101, 201, "+", "-"), pair("r3", 500, 600, "+", "-")];
+        let config = DedupConfig { mark_dups: true, ..DedupConfig::default() };
+        let (output, stats) = run_dedup(&lines, &config, None).unwrap();
+
+        assert_eq!(output, vec![pairs_format::FORMAT_LINE.to_string(),
+                                format!("{} {}", columns, DUP_MARK_COLUMN),
+                                format!("{}\t{}", lines[2], UNIQUE_MARK),
+                                format!("{}\t{}", lines[3], DupKind::Pcr),
+                                format!("{}\t{}", lines[4], UNIQUE_MARK)]);
+        assert_eq!(stats.unique, 2);
+        assert_eq!(stats.duplicates, 1);
+    }
 
-    for elem in recs.iter_mut().skip(1) {
-        if is_duplicated_copies(&f_rec.0, &elem.0, 3) { elem.1 = true; }
+    #[test]
+    fn test_unsorted_input() {
+        let lines = vec![pair("r1", 200, 300, "+", "-"), pair("r2", 100, 300, "+", "-")];
+        let err = run_dedup(&lines, &DedupConfig::default(), None).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
     }
-    recs
 }
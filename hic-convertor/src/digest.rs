@@ -0,0 +1,338 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+// Position of cut inside of motif is marked with '^' (e.g. G^ANTC), motif may contain IUPAC codes.
+const CUT_SYMBOL: char = '^';
+
+// Well known enzymes and kits, Arima kit uses DpnII and HinfI.
+const KNOWN_ENZYMES: [(&str, &[&str]); 10] = [
+    ("DpnII", &["^GATC"]),
+    ("MboI", &["^GATC"]),
+    ("Sau3AI", &["^GATC"]),
+    ("HindIII", &["A^AGCTT"]),
+    ("NcoI", &["C^CATGG"]),
+    ("HinfI", &["G^ANTC"]),
+    ("DdeI", &["C^TNAG"]),
+    ("MseI", &["T^TAA"]),
+    ("MluCI", &["^AATT"]),
+    ("Arima", &["^GATC", "G^ANTC"]),
+];
+
+// Recognition site of restriction enzyme and offset of cut from the start of the site (on forward strand).
+#[derive(Clone)]
+pub struct Enzyme {
+    pub name: String,
+    pub site: Vec<u8>,
+    pub cut_offset: usize,
+}
+
+impl Enzyme {
+    pub fn from_motif(name: &str, motif: &str) -> Option<Enzyme> {
+        let cut_offset = motif.find(CUT_SYMBOL).unwrap_or(0);
+        let site: Vec<u8> = motif.bytes().filter(|c| *c as char != CUT_SYMBOL).map(|c| c.to_ascii_uppercase()).collect();
+        if site.is_empty() || !site.iter().all(|c| iupac_bases(*c).is_some()) {
+            return None;
+        }
+        Some(Enzyme { name: name.to_string(), site, cut_offset })
+    }
+
+    // Enzyme is given either by name of known enzyme (or kit) or by motif, e.g. DpnII or G^ANTC.
+    pub fn from_string(s: &str) -> Option<Vec<Enzyme>> {
+        match KNOWN_ENZYMES.iter().find(|(name, _)| name.eq_ignore_ascii_case(s)) {
+            Some((name, motifs)) => motifs.iter().map(|m| Enzyme::from_motif(name, m)).collect(),
+            None => Enzyme::from_motif(s, s).map(|e| vec![e]),
+        }
+    }
+
+    // Cut positions of the enzyme on both strands of the sequence.
+    pub fn find_cuts(&self, seq: &[u8]) -> Vec<u64> {
+        let mut cuts = find_site(seq, self.site.as_slice(), self.cut_offset);
+
+        let rc_site = reverse_complement(self.site.as_slice());
+        if rc_site != self.site {
+            cuts.extend(find_site(seq, rc_site.as_slice(), self.site.len() - self.cut_offset));
+        }
+        cuts
+    }
+}
+
+fn find_site(seq: &[u8], site: &[u8], cut_offset: usize) -> Vec<u64> {
+    if seq.len() < site.len() {
+        return Vec::new();
+    }
+
+    (0..=seq.len() - site.len())
+        .filter(|&i| site.iter().zip(seq[i..].iter()).all(|(s, c)| is_matched(*s, *c)))
+        .map(|i| (i + cut_offset) as u64)
+        .collect()
+}
+
+fn is_matched(code: u8, base: u8) -> bool {
+    match iupac_bases(code) {
+        Some(bases) => bases.contains(&base.to_ascii_uppercase()),
+        None => false
+    }
+}
+
+fn iupac_bases(code: u8) -> Option<&'static [u8]> {
+    match code {
+        b'A' => Some(b"A"),
+        b'C' => Some(b"C"),
+        b'G' => Some(b"G"),
+        b'T' => Some(b"T"),
+        b'R' => Some(b"AG"),
+        b'Y' => Some(b"CT"),
+        b'S' => Some(b"CG"),
+        b'W' => Some(b"AT"),
+        b'K' => Some(b"GT"),
+        b'M' => Some(b"AC"),
+        b'B' => Some(b"CGT"),
+        b'D' => Some(b"AGT"),
+        b'H' => Some(b"ACT"),
+        b'V' => Some(b"ACG"),
+        b'N' => Some(b"ACGT"),
+        _ => None
+    }
+}
+
+fn reverse_complement(site: &[u8]) -> Vec<u8> {
+    site.iter().rev().map(|c| match c {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' => b'A',
+        b'R' => b'Y',
+        b'Y' => b'R',
+        b'K' => b'M',
+        b'M' => b'K',
+        b'B' => b'V',
+        b'V' => b'B',
+        b'D' => b'H',
+        b'H' => b'D',
+        other => *other, // S, W and N are complementary to themselves
+    }).collect()
+}
+
+// Restriction fragment [start, end) with its index on contig.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Fragment {
+    pub index: u32,
+    pub start: u64,
+    pub end: u64,
+}
+
+// Ends of restriction fragments for each contig and names of enzymes used for digestion.
+pub struct FragmentIndex {
+    fragment_ends: HashMap<String, Vec<u64>>,
+    enzyme_names: Vec<String>,
+}
+
+impl Default for FragmentIndex {
+    fn default() -> FragmentIndex {
+        FragmentIndex::new()
+    }
+}
+
+impl FragmentIndex {
+    pub fn new() -> FragmentIndex {
+        FragmentIndex { fragment_ends: HashMap::new(), enzyme_names: Vec::new() }
+    }
+
+    pub fn enzyme_names(&self) -> &[String] {
+        &self.enzyme_names
+    }
+
+    pub fn add_sequence(&mut self, name: &str, seq: &[u8], enzymes: &[Enzyme]) {
+        for enzyme in enzymes {
+            if !self.enzyme_names.contains(&enzyme.name) {
+                self.enzyme_names.push(enzyme.name.clone());
+            }
+        }
+        let mut ends: Vec<u64> = enzymes.iter()
+            .flat_map(|e| e.find_cuts(seq))
+            .filter(|&c| c > 0 && c < seq.len() as u64)
+            .collect();
+        ends.push(seq.len() as u64);
+        ends.sort_unstable();
+        ends.dedup();
+        self.fragment_ends.insert(name.to_string(), ends);
+    }
+
+    pub fn from_fasta(fasta_file: &Path, enzymes: &[Enzyme]) -> io::Result<FragmentIndex> {
+        info!("Digesting sequences from {}...", fasta_file.display());
+        let mut index = FragmentIndex::new();
+        let reader = BufReader::new(File::open(fasta_file)?);
+
+        let mut name: Option<String> = None;
+        let mut seq = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            if let Some(header) = line.strip_prefix('>') {
+                if let Some(name) = name.take() {
+                    index.add_sequence(name.as_str(), seq.as_slice(), enzymes);
+                }
+                name = header.split_whitespace().next().map(|n| n.to_string());
+                seq.clear();
+            } else {
+                seq.extend(line.trim_end().bytes());
+            }
+        }
+        if let Some(name) = name {
+            index.add_sequence(name.as_str(), seq.as_slice(), enzymes);
+        }
+
+        info!("{} sequences were digested.", index.fragment_ends.len());
+        Ok(index)
+    }
+
+    pub fn from_gfa(gfa_file: &Path, enzymes: &[Enzyme]) -> io::Result<FragmentIndex> {
+        info!("Digesting segments from {}...", gfa_file.display());
+        let prepack = gfa_graph::parser::parse_gfa_v1(gfa_file)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+        let mut index = FragmentIndex::new();
+        for rec in prepack.seq_recs_iter() {
+            match &rec.seq {
+                Some(seq) => index.add_sequence(rec.name.as_str(), seq.as_bytes(), enzymes),
+                None => warn!("Segment {} has no sequence, it will not be digested.", rec.name),
+            }
+        }
+
+        info!("{} segments were digested.", index.fragment_ends.len());
+        Ok(index)
+    }
+
+    pub fn find_fragment(&self, name: &str, pos: i64) -> Option<Fragment> {
+        let ends = self.fragment_ends.get(name)?;
+        if pos < 0 { return None; }
+
+        let index = match ends.binary_search(&(pos as u64)) {
+            Ok(i) => i + 1,
+            Err(i) => i
+        };
+        if index >= ends.len() { return None; }
+
+        Some(Fragment {
+            index: index as u32,
+            start: if index == 0 { 0 } else { ends[index - 1] },
+            end: ends[index]
+        })
+    }
+}
+
+// Contacts classified by distance between restriction fragments of its sides.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FragmentStat {
+    pub same_counter: u64,
+    pub adjacent_counter: u64,
+    pub distal_counter: u64,
+    pub unannotated_counter: u64,
+}
+
+impl FragmentStat {
+    pub fn new() -> FragmentStat {
+        FragmentStat {
+            same_counter: 0,
+            adjacent_counter: 0,
+            distal_counter: 0,
+            unannotated_counter: 0,
+        }
+    }
+
+    pub fn merge(&mut self, other: &FragmentStat) {
+        self.same_counter += other.same_counter;
+        self.adjacent_counter += other.adjacent_counter;
+        self.distal_counter += other.distal_counter;
+        self.unannotated_counter += other.unannotated_counter;
+    }
+
+    // Trans contacts are always distal.
+    pub fn update(&mut self, is_cis: bool, frag1: Option<Fragment>, frag2: Option<Fragment>) {
+        match (frag1, frag2) {
+            (Some(f1), Some(f2)) if is_cis && f1.index == f2.index => self.same_counter += 1,
+            (Some(f1), Some(f2)) if is_cis && (f1.index as i64 - f2.index as i64).abs() == 1 => self.adjacent_counter += 1,
+            (Some(_), Some(_)) => self.distal_counter += 1,
+            _ => self.unannotated_counter += 1,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enzyme(motif: &str) -> Enzyme {
+        Enzyme::from_motif(motif, motif).unwrap()
+    }
+
+    #[test]
+    fn test_iupac_motif() {
+        let hinfi = enzyme("G^ANTC");
+        assert_eq!(hinfi.site, b"GANTC".to_vec());
+        assert_eq!(hinfi.cut_offset, 1);
+        // N matches any base, the site is palindromic, so each site is cut once
+        assert_eq!(hinfi.find_cuts(b"GAATCTTGACTCTTGCGTCgagtc"), vec![1, 8, 20]);
+        assert_eq!(enzyme("RGATCY").find_cuts(b"AGATCTTGGATCCTTCGATCG"), vec![0, 7]);
+
+        assert!(Enzyme::from_motif("bad", "G^AZTC").is_none());
+        assert!(Enzyme::from_motif("empty", "^").is_none());
+    }
+
+    #[test]
+    fn test_known_enzymes() {
+        let arima = Enzyme::from_string("arima").unwrap();
+        let sites: Vec<Vec<u8>> = arima.iter().map(|e| e.site.clone()).collect();
+        assert_eq!(sites, vec![b"GATC".to_vec(), b"GANTC".to_vec()]);
+        assert!(arima.iter().all(|e| e.name == "Arima"));
+        assert_eq!(Enzyme::from_string("HindIII").unwrap()[0].cut_offset, 1);
+        assert_eq!(Enzyme::from_string("c^tnag").unwrap()[0].site, b"CTNAG".to_vec());
+        assert!(Enzyme::from_string("unknown").is_none());
+    }
+
+    // Site which is not palindromic is also found on reverse strand, cut is mirrored inside of it.
+    #[test]
+    fn test_reverse_complement_sites() {
+        let bbsi = enzyme("GAAG^AC");
+        assert_eq!(bbsi.find_cuts(b"TTGAAGACTT"), vec![6]);
+        assert_eq!(bbsi.find_cuts(b"TTGTCTTCTT"), vec![4]);
+        assert_eq!(bbsi.find_cuts(b"GAAGACGTCTTC"), vec![4, 8]);
+        assert_eq!(reverse_complement(b"GAAGNRYAC"), b"GTRYNCTTC".to_vec());
+    }
+
+    #[test]
+    fn test_fragment_index() {
+        let mut index = FragmentIndex::default();
+        index.add_sequence("ctg1", b"AAGATCAAAAGATCAA", &[enzyme("^GATC")]);
+        index.add_sequence("ctg2", b"GATCAAAA", &[enzyme("^GATC")]);
+        assert_eq!(index.enzyme_names(), ["^GATC".to_string()]);
+
+        let fragment = |name: &str, pos: i64| index.find_fragment(name, pos).map(|f| (f.index, f.start, f.end));
+        assert_eq!(fragment("ctg1", 0), Some((0, 0, 2)));
+        assert_eq!(fragment("ctg1", 2), Some((1, 2, 10)));
+        assert_eq!(fragment("ctg1", 9), Some((1, 2, 10)));
+        assert_eq!(fragment("ctg1", 15), Some((2, 10, 16)));
+        assert_eq!(fragment("ctg1", 16), None);
+        assert_eq!(fragment("ctg1", -1), None);
+        // cut at the start of contig does not make an empty fragment
+        assert_eq!(fragment("ctg2", 0), Some((0, 0, 8)));
+        assert_eq!(fragment("ctg3", 0), None);
+    }
+
+    #[test]
+    fn test_from_fasta() {
+        let fasta_file = std::env::temp_dir().join(format!("hic_convertor_digest_{}.fa", std::process::id()));
+        std::fs::write(&fasta_file, ">ctg1 description\nAAGA\nTCAAAAGA\nTCAA\n>ctg2\nAAAA\n").unwrap();
+        let index = FragmentIndex::from_fasta(&fasta_file, &Enzyme::from_string("DpnII").unwrap()).unwrap();
+        std::fs::remove_file(&fasta_file).unwrap();
+
+        assert!(index.find_fragment("ctg1", 5) == Some(Fragment { index: 1, start: 2, end: 10 }));
+        assert!(index.find_fragment("ctg2", 3) == Some(Fragment { index: 0, start: 0, end: 4 }));
+        assert_eq!(index.enzyme_names(), ["DpnII".to_string()]);
+    }
+}
@@ -0,0 +1,285 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use log::{info, trace, warn};
+
+use super::bgzf;
+use super::dedup::{self, DedupConfig, DedupOutput, DedupStat, Record};
+
+const COMMENT_SYMBOL: char = '#';
+// Records which do not fit into memory are distributed among partitions by hash of their keys.
+const N_PARTITIONS: u64 = 64;
+// Partitions which still do not fit into memory are partitioned again with another seed of hash.
+const MAX_DEPTH: u64 = 4;
+// Rough per-key overhead of hash map (strings headers, hash map slot) used to respect memory budget.
+const KEY_OVERHEAD: usize = 128;
+// Number of partitionings made by the process, it makes names of their temporary files unique
+// (among nested partitionings and concurrent deduplications).
+static N_PARTITIONINGS: AtomicUsize = AtomicUsize::new(0);
+
+// Pairs with the same key are duplicates: positions are binned into buckets (exact positions if bucket is 1),
+// strands are ignored if matching is not strand-aware.
+#[derive(Hash, PartialEq, Eq)]
+struct DedupKey {
+    tig1: String,
+    bin1: i64,
+    strand1: String,
+    tig2: String,
+    bin2: i64,
+    strand2: String,
+}
+
+impl DedupKey {
+    fn from_record(rec: &Record, bucket: i64, strand_aware: bool) -> DedupKey {
+        let strand = |s: &String| if strand_aware { s.clone() } else { String::new() };
+        DedupKey {
+            tig1: rec.tig1.clone(),
+            bin1: rec.pos1.div_euclid(bucket),
+            strand1: strand(&rec.strand1),
+            tig2: rec.tig2.clone(),
+            bin2: rec.pos2.div_euclid(bucket),
+            strand2: strand(&rec.strand2),
+        }
+    }
+
+    fn mem_size(&self) -> usize {
+        self.tig1.len() + self.tig2.len() + self.strand1.len() + self.strand2.len() + KEY_OVERHEAD
+    }
+
+    fn partition(&self, depth: u64) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        depth.hash(&mut hasher);
+        self.hash(&mut hasher);
+        hasher.finish() % N_PARTITIONS
+    }
+}
+
+// Removes duplicates from pairs file which is not sorted. The first record with each key is kept, so for
+// bucket of size 1 and zero mismatch the result is the same as of deduplication of sorted pairs (up to order).
+// Keys are kept in memory within budget, records with new keys are spilled into partitions when budget
+// is exceeded and the partitions are deduplicated one by one.
+pub fn deduplicate_unsorted_pairs(inp_file: &Path, out_file: &Path, dups_file: Option<&Path>, config: &DedupConfig,
+                                  bucket: i64, mem_limit: usize, tmp_dir: &Path) -> io::Result<DedupStat> {
+    let reader = bgzf::open_reader(inp_file)?;
+    let mut out = DedupOutput::new(bgzf::create_writer(out_file)?, config.mark_dups);
+    if let Some(dups_file) = dups_file {
+        out = DedupOutput::update_dups_file(out, dups_file)?;
+    }
+
+    let params = HashDedupParams { config: *config, bucket: bucket.max(1), mem_limit, tmp_dir: PathBuf::from(tmp_dir) };
+    let mut lines = reader.lines();
+    let mut records = Vec::new();
+    for line in &mut lines {
+        let line = line?;
+        if line.starts_with(COMMENT_SYMBOL) {
+            out.push_header_line(line.as_str())?;
+        } else if !line.is_empty() {
+            records.push(line);
+            break;
+        }
+    }
+    out.write_header()?;
+
+    dedup_records(records.into_iter().map(Ok).chain(lines), &mut out, &params, 0)?;
+    let (output, stats) = out.finish()?;
+    output.finish()?;
+    Ok(stats)
+}
+
+struct HashDedupParams {
+    config: DedupConfig,
+    bucket: i64,
+    mem_limit: usize,
+    tmp_dir: PathBuf,
+}
+
+fn dedup_records<W: Write>(lines: impl Iterator<Item = io::Result<String>>, out: &mut DedupOutput<W>,
+                           params: &HashDedupParams, depth: u64) -> io::Result<()> {
+    let mut seen: HashMap<DedupKey, String> = HashMap::new();
+    let mut mem_size = 0;
+    let mut partitions: Option<Partitions> = None;
+    let mut is_over_budget = false;
+
+    for line in lines {
+        let line = line?;
+        if line.is_empty() { continue; }
+        let rec = Record::from_line(line.as_str())?;
+        let key = DedupKey::from_record(&rec, params.bucket, params.config.strand_aware);
+
+        if let Some(orig_name) = seen.get(&key) {
+            let kind = dedup::get_dup_kind(orig_name.as_str(), rec.read_name.as_str(), params.config.optical_dist);
+            out.save_duplicate(&rec, kind)?;
+        } else if let Some(partitions) = &mut partitions {
+            partitions.write(key.partition(depth), line.as_str())?;
+        } else {
+            out.save_record(&rec)?;
+            mem_size += key.mem_size() + rec.read_name.len();
+            seen.insert(key, rec.read_name);
+
+            if mem_size >= params.mem_limit && !is_over_budget {
+                is_over_budget = true;
+                if depth < MAX_DEPTH {
+                    info!("Keys of pairs do not fit into memory, new pairs are spilled into partitions.");
+                    partitions = Some(Partitions::new(params.tmp_dir.as_path(), depth)?);
+                } else {
+                    warn!("Keys of pairs do not fit into memory after {} rounds of partitioning.", MAX_DEPTH);
+                }
+            }
+        }
+    }
+
+    // records in partitions have keys which were not seen before, so partitions are independent
+    drop(seen);
+    if let Some(mut partitions) = partitions {
+        partitions.flush()?;
+        for path in partitions.paths.iter() {
+            trace!("Deduplicating partition {}", path.display());
+            let reader = BufReader::new(File::open(path)?);
+            dedup_records(reader.lines(), out, params, depth + 1)?;
+        }
+    }
+    Ok(())
+}
+
+// Temporary files with partitions of records. Files are removed when partitions are not needed anymore.
+struct Partitions {
+    paths: Vec<PathBuf>,
+    writers: Vec<BufWriter<File>>,
+}
+
+impl Partitions {
+    fn new(tmp_dir: &Path, depth: u64) -> io::Result<Partitions> {
+        let mut partitions = Partitions { paths: Vec::new(), writers: Vec::new() };
+        let n = N_PARTITIONINGS.fetch_add(1, Ordering::Relaxed);
+        for i in 0..N_PARTITIONS {
+            let path = tmp_dir.join(format!("hic_pairs_dedup.{}.{}.{}.{}.tmp", process::id(), n, depth, i));
+            // partition is registered before creating, so it is removed even if creating fails
+            partitions.paths.push(path.clone());
+            partitions.writers.push(BufWriter::new(File::create(path)?));
+        }
+        Ok(partitions)
+    }
+
+    fn write(&mut self, partition: u64, line: &str) -> io::Result<()> {
+        writeln!(self.writers[partition as usize], "{}", line)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        for writer in self.writers.iter_mut() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Partitions {
+    fn drop(&mut self) {
+        for path in self.paths.iter() {
+            if let Err(e) = fs::remove_file(path) {
+                trace!("Can not remove temporary file {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pairs_format;
+
+    fn tmp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hic_convertor_hash_dedup_{}_{}", name, process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_pairs(path: &Path, lines: &[String]) {
+        let mut writer = bgzf::create_writer(path).unwrap();
+        writeln!(writer, "{}", pairs_format::FORMAT_LINE).unwrap();
+        for line in lines {
+            writeln!(writer, "{}", line).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    fn read_records(path: &Path) -> Vec<String> {
+        let mut records: Vec<String> = bgzf::open_reader(path).unwrap().lines()
+            .map(|l| l.unwrap())
+            .filter(|l| !l.starts_with(COMMENT_SYMBOL))
+            .collect();
+        records.sort();
+        records
+    }
+
+    // Shuffled pairs of 1000 positions, each position is repeated 3 times on different strands.
+    fn shuffled_pairs() -> Vec<String> {
+        (0..3000_usize).map(|i| {
+            let j = (i * 7919) % 3000;
+            let (tig1, tig2) = [("ctg1", "ctg2"), ("ctg2", "ctg2"), ("ctg1", "ctg1")][(j % 1000) % 3];
+            let strand = if (i * 31) % 7 < 5 { "+" } else { "-" };
+            format!("r{}\t{}\t{}\t{}\t{}\t{}\t-", i, tig1, (j % 1000) * 10, tig2, (j % 1000) * 10 + 500, strand)
+        }).collect()
+    }
+
+    fn sort_key(line: &str) -> (String, String, i64, i64) {
+        let rec = Record::from_line(line).unwrap();
+        (rec.tig1, rec.tig2, rec.pos1, rec.pos2)
+    }
+
+    // Hash deduplication with buckets of size 1 gives the same pairs as deduplication of sorted pairs with zero
+    // mismatch, also when keys do not fit into memory and pairs are spilled into (nested) partitions.
+    #[test]
+    fn test_same_as_sorted_dedup() {
+        let dir = tmp_dir("sorted");
+        let tmp = tmp_dir("partitions");
+        let lines = shuffled_pairs();
+        let mut sorted_lines = lines.clone();
+        sorted_lines.sort_by_key(|l| sort_key(l));
+        let (unsorted_file, sorted_file) = (dir.join("unsorted.pairs"), dir.join("sorted.pairs"));
+        write_pairs(&unsorted_file, &lines);
+        write_pairs(&sorted_file, &sorted_lines);
+
+        let config = DedupConfig { max_mismatch: 0, ..DedupConfig::default() };
+        let out_file = dir.join("sorted.dedup.pairs");
+        let sorted_stats = dedup::deduplicate_pairs(&sorted_file, &out_file, None, &config).unwrap();
+        let expected = read_records(&out_file);
+        assert!(sorted_stats.duplicates > 500);
+
+        // memory fits all keys, keys are partitioned once and partitions are partitioned again
+        for &(mem_limit, n_partitionings) in [(1 << 20, 0), (16 << 10, 1), (2 << 10, 2)].iter() {
+            let out_file = dir.join("unsorted.dedup.pairs");
+            let n_before = N_PARTITIONINGS.load(Ordering::Relaxed);
+            let stats = deduplicate_unsorted_pairs(&unsorted_file, &out_file, None, &config, 1, mem_limit, &tmp)
+                .unwrap();
+            let n_made = N_PARTITIONINGS.load(Ordering::Relaxed) - n_before;
+            assert!(n_made >= n_partitionings && (n_partitionings > 0 || n_made == 0));
+            assert_eq!(read_records(&out_file), expected);
+            assert_eq!((stats.total, stats.unique, stats.duplicates),
+                       (sorted_stats.total, sorted_stats.unique, sorted_stats.duplicates));
+            assert_eq!(fs::read_dir(&tmp).unwrap().count(), 0);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&tmp).unwrap();
+    }
+
+    #[test]
+    fn test_buckets() {
+        let dir = tmp_dir("buckets");
+        let lines = vec!["r1\tctg1\t100\tctg2\t200\t+\t-".to_string(), "r2\tctg1\t250\tctg2\t300\t+\t-".to_string(),
+                         "r3\tctg1\t199\tctg2\t250\t+\t-".to_string(), "r4\tctg1\t101\tctg2\t299\t+\t-".to_string()];
+        let (in_file, out_file) = (dir.join("in.pairs"), dir.join("out.pairs"));
+        write_pairs(&in_file, &lines);
+        let stats = deduplicate_unsorted_pairs(&in_file, &out_file, None, &DedupConfig::default(), 100, 1 << 20, &dir)
+            .unwrap();
+        assert_eq!(read_records(&out_file), vec![lines[0].clone(), lines[1].clone()]);
+        assert_eq!(stats.duplicates, 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashSet, VecDeque};
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::process::{Child, Command, ExitStatus, Stdio};
+
+use bam::RecordReader;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+// Alignments are read from stdin if path is '-'.
+pub const STDIN_PATH: &str = "-";
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const CRAM_MAGIC: &[u8] = b"CRAM";
+const SAMTOOLS: &str = "samtools";
+
+// Number of recently finished read groups which are checked for alignments which are not grouped by name.
+const RECENT_GROUPS_WINDOW: usize = 100_000;
+
+// How records of reads without mates (single-end records or pairs with one missing mate) are handled.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnpairedPolicy {
+    Skip,
+    Error
+}
+
+impl UnpairedPolicy {
+    pub fn from_option(s: Option<&str>) -> UnpairedPolicy {
+        match s {
+            Some("error") => UnpairedPolicy::Error,
+            _ => UnpairedPolicy::Skip
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum InputFormat {
+    Bam,
+    Sam,
+    Cram
+}
+
+impl InputFormat {
+    // Format is detected by the first bytes of input: BAM is always BGZF compressed, CRAM starts with its magic.
+    pub fn detect(stream: &mut impl BufRead) -> io::Result<InputFormat> {
+        let buf = stream.fill_buf()?;
+        if buf.starts_with(GZIP_MAGIC) {
+            Ok(InputFormat::Bam)
+        } else if buf.starts_with(CRAM_MAGIC) {
+            Ok(InputFormat::Cram)
+        } else {
+            Ok(InputFormat::Sam)
+        }
+    }
+}
+
+enum Reader {
+    Bam(bam::BamReader<Box<dyn Read + Send>>),
+    Sam(bam::SamReader<Box<dyn BufRead + Send>>),
+}
+
+// Reader of alignments in BAM, SAM or CRAM format from file or stdin. bam crate can not decode CRAM,
+// so it is decoded into SAM by samtools (it must be in PATH) with the reference. CRAM can not be read from stdin,
+// since its format is detected by the first bytes which would be already consumed before samtools is started.
+pub struct AlignmentReader {
+    reader: Reader,
+    decoder: Option<Child>,
+}
+
+impl AlignmentReader {
+    pub fn open(path: &Path, reference: Option<&Path>, threads: u16) -> io::Result<AlignmentReader> {
+        let mut stream: Box<dyn BufRead + Send> = if path.to_str() == Some(STDIN_PATH) {
+            Box::new(BufReader::new(io::stdin()))
+        } else {
+            Box::new(BufReader::new(File::open(path)?))
+        };
+
+        match InputFormat::detect(&mut stream)? {
+            InputFormat::Bam => {
+                info!("Reading alignments in bam format from {}...", path.display());
+                let stream: Box<dyn Read + Send> = Box::new(stream);
+                Ok(AlignmentReader { reader: Reader::Bam(bam::BamReader::from_stream(stream, threads)?), decoder: None })
+            },
+            InputFormat::Sam => {
+                info!("Reading alignments in sam format from {}...", path.display());
+                Ok(AlignmentReader { reader: Reader::Sam(bam::SamReader::from_stream(stream)?), decoder: None })
+            },
+            InputFormat::Cram => {
+                if path.to_str() == Some(STDIN_PATH) {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                        "Reading of cram from stdin ('-') is not supported, provide path to cram file or pipe alignments in sam/bam format."));
+                }
+                let reference = reference.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput,
+                                                                       "Reference must be provided for reading cram."))?;
+                info!("Reading alignments in cram format from {} with samtools...", path.display());
+                AlignmentReader::open_cram(path, reference, threads)
+            }
+        }
+    }
+
+    // Messages of samtools go to stderr, its exit status is checked if reading of its output fails and at the end.
+    fn open_cram(path: &Path, reference: &Path, threads: u16) -> io::Result<AlignmentReader> {
+        let mut decoder = Command::new(SAMTOOLS)
+            .arg("view").arg("-h")
+            .arg("-@").arg(threads.to_string())
+            .arg("-T").arg(reference)
+            .arg(path)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::NotFound => io::Error::new(io::ErrorKind::NotFound,
+                    "samtools is required for reading cram, but it is not found in PATH."),
+                _ => io::Error::new(e.kind(), format!("Can not start samtools for reading cram: {}", e))
+            })?;
+
+        let stdout = decoder.stdout.take()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Problem with output of samtools."))?;
+        let stream: Box<dyn BufRead + Send> = Box::new(BufReader::new(stdout));
+        match bam::SamReader::from_stream(stream) {
+            Ok(reader) => Ok(AlignmentReader { reader: Reader::Sam(reader), decoder: Some(decoder) }),
+            // output of samtools is closed at this point, so it finishes even if it is still running
+            Err(e) => Err(get_decoder_error(decoder.wait()?).unwrap_or(e))
+        }
+    }
+
+    pub fn header(&self) -> &bam::Header {
+        match &self.reader {
+            Reader::Bam(r) => r.header(),
+            Reader::Sam(r) => r.header(),
+        }
+    }
+
+    // Incomplete record at the end of output of failed samtools is reported as failure of samtools.
+    pub fn read_into(&mut self, record: &mut bam::Record) -> io::Result<bool> {
+        let res = match &mut self.reader {
+            Reader::Bam(r) => r.read_into(record),
+            Reader::Sam(r) => r.read_into(record),
+        };
+        match (res, &mut self.decoder) {
+            (Err(e), Some(decoder)) => match decoder.try_wait()? {
+                Some(status) => Err(get_decoder_error(status).unwrap_or(e)),
+                None => Err(e)
+            },
+            (res, _) => res
+        }
+    }
+
+    // Waits for decoder of cram (if it was used) and checks that it finished successfully.
+    pub fn finish(self) -> io::Result<()> {
+        if let Some(mut decoder) = self.decoder {
+            if let Some(e) = get_decoder_error(decoder.wait()?) {
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+// samtools killed by a signal (e.g. when its output is closed after an error of parsing) is not reported,
+// the error which caused it is more informative.
+fn get_decoder_error(status: ExitStatus) -> Option<io::Error> {
+    match status.code() {
+        Some(0) | None => None,
+        Some(code) => Some(io::Error::new(io::ErrorKind::Other,
+            format!("samtools failed to decode cram (exit code {}), see its messages above.", code)))
+    }
+}
+
+// Sort order declared with SO or GO tags of @HD line.
+#[derive(Clone, Copy, PartialEq)]
+pub enum SortOrder {
+    Coordinate,
+    QueryName,
+    Unknown
+}
+
+pub fn get_sort_order(header: &bam::Header) -> io::Result<SortOrder> {
+    let mut text = Vec::new();
+    header.write_text(&mut text)?;
+    let text = String::from_utf8_lossy(text.as_slice());
+
+    let hd_line = match text.lines().find(|line| line.starts_with("@HD")) {
+        Some(line) => line,
+        None => return Ok(SortOrder::Unknown)
+    };
+
+    for tag in hd_line.split('\t').skip(1) {
+        match tag {
+            "SO:coordinate" => return Ok(SortOrder::Coordinate),
+            "SO:queryname" | "GO:query" => return Ok(SortOrder::QueryName),
+            _ => {}
+        }
+    }
+    Ok(SortOrder::Unknown)
+}
+
+// Alignments must be grouped by read name, so coordinate sorted input is rejected before conversion.
+pub fn check_sort_order(header: &bam::Header) -> io::Result<()> {
+    match get_sort_order(header)? {
+        SortOrder::Coordinate => Err(io::Error::new(io::ErrorKind::InvalidInput,
+            "Alignments are sorted by coordinate, they must be grouped by read name (e.g. samtools sort -n or samtools collate).")),
+        SortOrder::QueryName => Ok(()),
+        SortOrder::Unknown => {
+            warn!("Sort order of alignments is not declared in header, grouping by read name will be checked during conversion.");
+            Ok(())
+        }
+    }
+}
+
+// Remembers hashes of names of recently finished read groups to detect reads whose alignments are interleaved
+// with alignments of other reads.
+pub struct ReadNameTracker {
+    window: usize,
+    order: VecDeque<u64>,
+    names: HashSet<u64>,
+}
+
+impl ReadNameTracker {
+    pub fn new() -> ReadNameTracker {
+        ReadNameTracker { window: RECENT_GROUPS_WINDOW, order: VecDeque::new(), names: HashSet::new() }
+    }
+
+    pub fn finish_group(&mut self, name: &[u8]) {
+        let hash = get_name_hash(name);
+        if self.names.insert(hash) {
+            self.order.push_back(hash);
+        }
+        if self.order.len() > self.window {
+            if let Some(old) = self.order.pop_front() {
+                self.names.remove(&old);
+            }
+        }
+    }
+
+    pub fn is_finished(&self, name: &[u8]) -> bool {
+        self.names.contains(&get_name_hash(name))
+    }
+}
+
+fn get_name_hash(name: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    name.hash(&mut hasher);
+    hasher.finish()
+}
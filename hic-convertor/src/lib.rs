@@ -1,31 +1,206 @@
-use std::path::Path;
-use std::io;
+use std::path::{Path, PathBuf};
+use std::io::{self, Write};
 use log::info;
 
 mod pair_record;
 pub mod convertor;
 mod sort;
 mod dedup;
+mod hash_dedup;
+mod select;
+mod xa_tag;
+mod pairs_format;
+mod digest;
+mod walks;
+mod stats;
+mod input;
+mod config;
+mod bgzf;
+mod pairs_index;
 
-pub fn convert_bam_to_pairs(bam_file: &Path, pairs_file: &Path,
-                            stat_file: &Path, _graph_file: Option<&Path>) -> io::Result<()> {
-    info!("Starting converting .bam to .pairs...");
-    let mut converter = convertor::Converter::new(bam_file, None, pairs_file);
+pub use xa_tag::{MultiMapPolicy, MultiMapMode};
+pub use pairs_format::ExtraColumn;
+pub use digest::{Enzyme, FragmentIndex};
+pub use walks::WalkPolicy;
+pub use input::UnpairedPolicy;
+pub use convertor::RescueStrategy;
+pub use config::ConverterConfig;
+pub use dedup::DedupConfig;
+pub use sort::parse_memory_size;
+
+// Optional inputs and outputs of conversion and resources for it. Duplicates file, memory and temporary
+// directory are used only by pipeline for sorting and deduplicating of converted pairs.
+pub struct ConvertOptions<'a> {
+    pub filtered_file: Option<&'a Path>,
+    pub out_bam: Option<&'a Path>,
+    pub reference: Option<&'a Path>,
+    pub fragments: Option<FragmentIndex>,
+    pub threads: usize,
+    pub dups_file: Option<&'a Path>,
+    pub mem: &'a str,
+    pub tmpdir: Option<&'a str>,
+}
+
+impl<'a> Default for ConvertOptions<'a> {
+    fn default() -> ConvertOptions<'a> {
+        ConvertOptions { filtered_file: None, out_bam: None, reference: None, fragments: None, threads: 1,
+                         dups_file: None, mem: "2G", tmpdir: None }
+    }
+}
+
+pub fn convert_bam_to_pairs(bam_file: &Path, pairs_file: &Path, stat_file: &Path, graph_file: Option<&Path>,
+                            config: &ConverterConfig, options: ConvertOptions) -> io::Result<()> {
+    info!("Starting converting alignments to .pairs...");
+    let converter = convertor::Converter::new(bam_file, graph_file.map(PathBuf::from), pairs_file);
+    let mut converter = setup_converter(converter, config, options);
     converter.convert()?;
-    converter.save_statistic(stat_file);
-    info!("Converting .bam to .pairs is complete.");
+    converter.save_statistic(stat_file)?;
+    let (output, _) = converter.into_parts()?;
+    output.finish()?;
+    info!("Converting alignments to .pairs is complete.");
     Ok(())
 }
 
+fn setup_converter<W: Write>(converter: convertor::Converter<W>, config: &ConverterConfig,
+                             options: ConvertOptions) -> convertor::Converter<W> {
+    let converter = convertor::Converter::update_config(converter, config);
+    let mut converter = convertor::Converter::update_threads(converter, options.threads);
+    if let Some(filtered_file) = options.filtered_file {
+        converter = convertor::Converter::update_filtered_file(converter, filtered_file);
+    }
+    if let Some(out_bam) = options.out_bam {
+        converter = convertor::Converter::update_bam_output(converter, out_bam);
+    }
+    if let Some(fragments) = options.fragments {
+        converter = convertor::Converter::update_fragments(converter, fragments);
+    }
+    if let Some(reference) = options.reference {
+        converter = convertor::Converter::update_reference(converter, reference);
+    }
+    converter
+}
+
 pub fn sort_pairs(in_file: &Path, out_file: &Path, nproc: u8, mem: &str, tmpdir: Option<&str>) -> io::Result<()> {
     info!("Starting sorting {}...", in_file.to_str().unwrap());
-    sort::sort_pairs(in_file.to_str().unwrap(), out_file.to_str().unwrap(), nproc, mem, tmpdir);
+    sort::sort_pairs(in_file.to_str().unwrap(), out_file.to_str().unwrap(), nproc, mem, tmpdir)?;
     info!("Sorting results saved into {}...", out_file.to_str().unwrap());
     Ok(())
 }
 
-pub fn deduplicate_pairs(in_file: &Path, out_file: &Path) {
+// Merges sorted pairs files (e.g. of different sequencing lanes) into a single sorted file.
+pub fn merge_pairs(in_files: &[&Path], out_file: &Path) -> io::Result<()> {
+    info!("Starting merging sorted pairs...");
+    sort::merge_sorted_pairs(in_files, out_file)?;
+    info!("Merged pairs saved into {}...", out_file.to_str().unwrap());
+    Ok(())
+}
+
+// Builds index of BGZF pairs file sorted by tig1, tig2 and pos1, index is saved next to pairs file.
+pub fn index_pairs(pairs_file: &Path) -> io::Result<()> {
+    info!("Starting indexing {}...", pairs_file.to_str().unwrap());
+    let index = pairs_index::PairsIndex::build(pairs_file)?;
+    let index_file = pairs_index::get_index_path(pairs_file);
+    index.save(index_file.as_path())?;
+    info!("Index saved into {}...", index_file.to_str().unwrap());
+    Ok(())
+}
+
+// Extracts pairs of two contigs (with pos1 within range if it is provided) from indexed pairs file.
+pub fn extract_pairs(pairs_file: &Path, out_file: &Path, tig1: &str, tig2: &str,
+                     start: Option<i64>, end: Option<i64>) -> io::Result<()> {
+    info!("Extracting pairs of {} and {}...", tig1, tig2);
+    let index = pairs_index::PairsIndex::from_file(pairs_index::get_index_path(pairs_file).as_path())?;
+    let mut output = bgzf::create_writer(out_file)?;
+    let n_pairs = pairs_index::extract_pairs(pairs_file, &index, &mut output, tig1, tig2, start, end)?;
+    output.finish()?;
+    info!("{} pairs saved into {}...", n_pairs, out_file.to_str().unwrap());
+    Ok(())
+}
+
+pub fn deduplicate_pairs(in_file: &Path, out_file: &Path, dups_file: Option<&Path>, stat_file: Option<&Path>,
+                         config: &DedupConfig) -> io::Result<()> {
     info!("Starting deduplicating pairs...");
-    dedup::deduplicate_pairs(in_file, out_file, 3);
+    let dedup_stats = dedup::deduplicate_pairs(in_file, out_file, dups_file, config)?;
+    if let Some(stat_file) = stat_file {
+        dedup_stats.dump_stats_to_file(stat_file)?;
+    }
+    info!("Deduplicating is complete.");
+    Ok(())
+}
+
+// Optional outputs of deduplication of unsorted pairs and resources for it. Pairs with positions in the same
+// bucket are duplicates, keys which do not fit into memory are partitioned in temporary directory.
+pub struct UnsortedDedupOptions<'a> {
+    pub dups_file: Option<&'a Path>,
+    pub stat_file: Option<&'a Path>,
+    pub bucket: i64,
+    pub mem: &'a str,
+    pub tmpdir: Option<&'a str>,
+}
+
+impl<'a> Default for UnsortedDedupOptions<'a> {
+    fn default() -> UnsortedDedupOptions<'a> {
+        UnsortedDedupOptions { dups_file: None, stat_file: None, bucket: 1, mem: "2G", tmpdir: None }
+    }
+}
+
+// Deduplicates pairs which are not sorted, positions are compared within buckets instead of tolerance.
+pub fn deduplicate_unsorted_pairs(in_file: &Path, out_file: &Path, config: &DedupConfig,
+                                  options: UnsortedDedupOptions) -> io::Result<()> {
+    info!("Starting deduplicating unsorted pairs...");
+    let mem_limit = sort::parse_memory_size(options.mem)?;
+    let tmp_dir = options.tmpdir.map(PathBuf::from).unwrap_or_else(std::env::temp_dir);
+    let dedup_stats = hash_dedup::deduplicate_unsorted_pairs(in_file, out_file, options.dups_file, config,
+                                                             options.bucket, mem_limit, tmp_dir.as_path())?;
+    if let Some(stat_file) = options.stat_file {
+        dedup_stats.dump_stats_to_file(stat_file)?;
+    }
     info!("Deduplicating is complete.");
+    Ok(())
+}
+
+pub fn select_pairs(in_file: &Path, out_file: &Path, rest_file: Option<&Path>, expr: &str) -> io::Result<()> {
+    info!("Starting selecting pairs with {}...", expr);
+    select::select_pairs(in_file, out_file, rest_file, expr)?;
+    info!("Selected pairs saved into {}.", out_file.to_str().unwrap());
+    Ok(())
+}
+
+pub fn merge_stats(in_files: &[&Path], out_file: &Path) -> io::Result<()> {
+    info!("Starting merging {} statistics files...", in_files.len());
+    stats::merge_stats_files(in_files, out_file)?;
+    info!("Merged statistics saved into {}.", out_file.to_str().unwrap());
+    Ok(())
+}
+
+pub fn run_pipeline(bam_file: &Path, out_file: &Path, stat_file: &Path, graph_file: Option<&Path>,
+                    config: &ConverterConfig, dedup_config: &DedupConfig, options: ConvertOptions) -> io::Result<()> {
+    info!("Starting converting alignments to sorted and deduplicated .pairs...");
+    let mem_limit = sort::parse_memory_size(options.mem)?;
+    let tmp_dir = options.tmpdir.map(PathBuf::from).unwrap_or_else(std::env::temp_dir);
+    let dups_file = options.dups_file;
+
+    let sorter = sort::ExternalSorter::update_threads(sort::ExternalSorter::new(mem_limit, tmp_dir.as_path()),
+                                                      options.threads)?;
+    let converter = convertor::Converter::from_writer(bam_file, graph_file.map(PathBuf::from), sorter);
+    let mut converter = setup_converter(converter, config, options);
+    converter.convert()?;
+    let (sorter, mut stats) = converter.into_parts()?;
+    info!("Converting is complete, sorting and deduplicating pairs...");
+
+    let output = bgzf::create_writer(out_file)?;
+    let mut deduplicator = dedup::Deduplicator::new(output, dedup_config);
+    if let Some(dups_file) = dups_file {
+        deduplicator = dedup::Deduplicator::update_dups_file(deduplicator, dups_file)?;
+    }
+    for line in sorter.into_sorted_lines()? {
+        deduplicator.push_line(line?.as_str())?;
+    }
+    let (output, dedup_stats) = deduplicator.finish()?;
+    output.finish()?;
+    stats.update_dedup_count(&dedup_stats);
+    stats.dump_stats_to_file(stat_file)?;
+
+    info!("Pipeline is complete.");
+    Ok(())
 }
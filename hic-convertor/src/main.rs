@@ -4,7 +4,7 @@ use std::path::Path;
 
 use fern;
 use clap::{Arg, App, SubCommand};
-use hic_convertor::{convert_bam_to_pairs, deduplicate_pairs, sort_pairs};
+use hic_convertor::{convert_bam_to_pairs, deduplicate_pairs, deduplicate_unsorted_pairs, sort_pairs, merge_pairs, index_pairs, extract_pairs, run_pipeline, merge_stats, select_pairs, WalkPolicy, UnpairedPolicy, RescueStrategy, ConverterConfig, ConvertOptions, UnsortedDedupOptions, MultiMapMode, ExtraColumn, DedupConfig, Enzyme, FragmentIndex};
 
 
 fn setup_logging(verbosity: u64, log_file: &Path) -> Result<(), fern::InitError> {
@@ -78,6 +78,260 @@ fn out_pairs_arg(hm: &'static str) -> Arg<'static, 'static> {
         .help(hm)
 }
 
+fn multimap_arg() -> Arg<'static, 'static> {
+    Arg::<'static, 'static>::with_name("multimap")
+        .long("multimap")
+        .possible_values(&["drop", "random", "all"])
+        .takes_value(true)
+        .required(false)
+        .help("Policy for alignments with alternative hits in XA tag: drop - skip them, \
+               random - pick one hit randomly, all - emit all hits with fractional weights")
+}
+
+fn walks_policy_arg() -> Arg<'static, 'static> {
+    Arg::<'static, 'static>::with_name("walks_policy")
+        .long("walks-policy")
+        .possible_values(&["outer", "adjacent", "all"])
+        .takes_value(true)
+        .required(false)
+        .help("Pairs reported for chimeric reads with ligation junctions: outer - the outermost segments, \
+               adjacent - consecutive segments, all - all pairs of segments")
+}
+
+fn seed_arg() -> Arg<'static, 'static> {
+    Arg::<'static, 'static>::with_name("seed")
+        .long("seed")
+        .value_name("NUM")
+        .takes_value(true)
+        .required(false)
+        .help("Seed for picking random hits from XA tag.")
+}
+
+fn columns_arg() -> Arg<'static, 'static> {
+    Arg::<'static, 'static>::with_name("columns")
+        .long("columns")
+        .multiple(true)
+        .use_delimiter(true)
+        .possible_values(&["mapq", "pair_type", "rescue", "walk_pair_index", "rfrag"])
+        .takes_value(true)
+        .required(false)
+        .help("Extra columns written after mandatory columns of pairs file.")
+}
+
+fn filtered_arg() -> Arg<'static, 'static> {
+    Arg::<'static, 'static>::with_name("filtered")
+        .short("f")
+        .long("filtered")
+        .value_name("FILE")
+        .takes_value(true)
+        .required(false)
+        .help("Path to file for filtered read pairs (unmapped, multimapping, walks, etc.).")
+}
+
+fn out_bam_arg() -> Arg<'static, 'static> {
+    Arg::<'static, 'static>::with_name("out_bam")
+        .long("out_bam")
+        .value_name("FILE")
+        .takes_value(true)
+        .required(false)
+        .help("Path to bam file for alignments of kept pairs annotated with pair type (Yt), rescue path (Yr), \
+               contig (Yc) and position (Yp) of ligation partner.")
+}
+
+fn enzyme_arg() -> Arg<'static, 'static> {
+    Arg::<'static, 'static>::with_name("enzyme")
+        .short("e")
+        .long("enzyme")
+        .value_name("STR")
+        .multiple(true)
+        .use_delimiter(true)
+        .takes_value(true)
+        .required(false)
+        .help("Restriction enzymes (e.g. DpnII, HinfI, Arima) or motifs with cut site (e.g. G^ANTC) \
+               for annotation of pairs with restriction fragments.")
+}
+
+fn reference_arg() -> Arg<'static, 'static> {
+    Arg::<'static, 'static>::with_name("reference")
+        .short("r")
+        .long("reference")
+        .value_name("FILE")
+        .takes_value(true)
+        .required(false)
+        .help("Path to contigs in fasta format for decoding of cram and for digestion \
+               (segments of graph are used for digestion if it is not provided).")
+}
+
+// Digests contigs from fasta file or from graph if enzymes are provided.
+fn load_fragments(matches: &clap::ArgMatches, config: &ConverterConfig) -> Result<Option<FragmentIndex>, Box<dyn Error>> {
+    if config.enzymes.is_empty() {
+        return Ok(None);
+    }
+
+    let mut enzymes = Vec::new();
+    for val in config.enzymes.iter() {
+        enzymes.extend(Enzyme::from_string(val).ok_or(format!("Unknown enzyme or incorrect motif {}", val))?);
+    }
+
+    match (matches.value_of("reference"), matches.value_of("graph")) {
+        (Some(fasta), _) => Ok(Some(FragmentIndex::from_fasta(Path::new(fasta), &enzymes)?)),
+        (None, Some(gfa)) => Ok(Some(FragmentIndex::from_gfa(Path::new(gfa), &enzymes)?)),
+        (None, None) => Err("Reference or graph must be provided for digestion.".into())
+    }
+}
+
+fn unpaired_arg() -> Arg<'static, 'static> {
+    Arg::<'static, 'static>::with_name("unpaired")
+        .long("unpaired")
+        .possible_values(&["skip", "error"])
+        .takes_value(true)
+        .required(false)
+        .help("Handling of single-end reads and reads without alignments of mate: skip - count and skip them, \
+               error - stop conversion")
+}
+
+fn threads_arg() -> Arg<'static, 'static> {
+    Arg::<'static, 'static>::with_name("threads")
+        .short("t")
+        .long("threads")
+        .value_name("NUM")
+        .takes_value(true)
+        .required(false)
+        .help("Number of threads for decompression of bam and processing of reads.")
+}
+
+fn config_arg() -> Arg<'static, 'static> {
+    Arg::<'static, 'static>::with_name("config")
+        .short("c")
+        .long("config")
+        .value_name("FILE")
+        .takes_value(true)
+        .required(false)
+        .help("Path to parameters of conversion in toml or yaml format (command line arguments override them).")
+}
+
+fn min_mapq_arg() -> Arg<'static, 'static> {
+    Arg::<'static, 'static>::with_name("min_mapq")
+        .long("min_mapq")
+        .value_name("NUM")
+        .takes_value(true)
+        .required(false)
+        .help("Minimal mapping quality of unique alignment.")
+}
+
+fn max_mol_size_arg() -> Arg<'static, 'static> {
+    Arg::<'static, 'static>::with_name("max_mol_size")
+        .long("max_mol_size")
+        .value_name("NUM")
+        .takes_value(true)
+        .required(false)
+        .help("Maximal size of Hi-C molecule, used to decide whether alignments of mates cover the same segment.")
+}
+
+fn matched_rate_arg() -> Arg<'static, 'static> {
+    Arg::<'static, 'static>::with_name("matched_rate")
+        .long("matched_rate")
+        .value_name("FLOAT")
+        .takes_value(true)
+        .required(false)
+        .help("Minimal fraction of matched bases of alignment with zero mapq to be rescued via graph.")
+}
+
+fn mapq_zero_rescue_arg() -> Arg<'static, 'static> {
+    Arg::<'static, 'static>::with_name("mapq_zero_rescue")
+        .long("mapq_zero_rescue")
+        .takes_value(false)
+        .required(false)
+        .help("Rescue alignments with zero mapq via overlaps in assembly graph (requires --graph).")
+}
+
+fn strategy_arg() -> Arg<'static, 'static> {
+    Arg::<'static, 'static>::with_name("strategy")
+        .long("strategy")
+        .possible_values(&["unique", "simple", "complex"])
+        .takes_value(true)
+        .required(false)
+        .help("Read pairs which are resolved: unique - only pairs of unique alignments, simple - also pairs \
+               where one of mates has a single alignment, complex - all pairs (as walks)")
+}
+
+fn optical_dist_arg() -> Arg<'static, 'static> {
+    Arg::<'static, 'static>::with_name("optical_dist")
+        .long("optical_dist")
+        .value_name("NUM")
+        .takes_value(true)
+        .required(false)
+        .help("Maximal distance (in pixels) between clusters on the same tile for optical duplicates \
+               (default 100, about 2500 is suitable for patterned flowcells).")
+}
+
+fn max_mismatch_arg() -> Arg<'static, 'static> {
+    Arg::<'static, 'static>::with_name("max_mismatch")
+        .long("max_mismatch")
+        .value_name("NUM")
+        .takes_value(true)
+        .required(false)
+        .help("Maximal difference of positions (on each side) of duplicated pairs (default 3), \
+               it is not used for unsorted pairs.")
+}
+
+fn ignore_strands_arg() -> Arg<'static, 'static> {
+    Arg::<'static, 'static>::with_name("ignore_strands")
+        .long("ignore_strands")
+        .takes_value(false)
+        .required(false)
+        .help("Consider pairs as duplicates regardless of their strands.")
+}
+
+fn out_dups_arg() -> Arg<'static, 'static> {
+    Arg::<'static, 'static>::with_name("out_dups")
+        .long("out_dups")
+        .value_name("FILE")
+        .takes_value(true)
+        .required(false)
+        .help("Path to file for duplicated pairs.")
+}
+
+fn mark_dups_arg() -> Arg<'static, 'static> {
+    Arg::<'static, 'static>::with_name("mark_dups")
+        .long("mark_dups")
+        .takes_value(false)
+        .required(false)
+        .help("Write duplicates with mark column (U - unique, P - PCR duplicate, O - optical duplicate) \
+               instead of dropping them.")
+}
+
+fn load_dedup_config(matches: &clap::ArgMatches) -> Result<DedupConfig, Box<dyn Error>> {
+    let mut config = DedupConfig::default();
+    if let Some(v) = matches.value_of("max_mismatch") { config.max_mismatch = v.parse()?; }
+    config.strand_aware = !matches.is_present("ignore_strands");
+    if let Some(v) = matches.value_of("optical_dist") { config.optical_dist = v.parse()?; }
+    config.mark_dups = matches.is_present("mark_dups");
+    Ok(config)
+}
+
+// Parameters of conversion from config file (or default ones) overridden by command line arguments.
+fn load_config(matches: &clap::ArgMatches) -> Result<ConverterConfig, Box<dyn Error>> {
+    let mut config = match matches.value_of("config") {
+        Some(path) => ConverterConfig::from_file(Path::new(path))?,
+        None => ConverterConfig::default()
+    };
+
+    if let Some(v) = matches.value_of("min_mapq") { config.min_mapq = v.parse()?; }
+    if let Some(v) = matches.value_of("max_mol_size") { config.max_molecule_size = v.parse()?; }
+    if let Some(v) = matches.value_of("matched_rate") { config.matched_rate_tresh = v.parse()?; }
+    if matches.is_present("mapq_zero_rescue") { config.mapq_zero_rescue = true; }
+    if let Some(v) = matches.value_of("strategy") { config.strategy = RescueStrategy::from_string(v); }
+    if let Some(v) = matches.value_of("walks_policy") { config.walks_policy = WalkPolicy::from_string(v); }
+    if let Some(v) = matches.value_of("multimap") { config.multimap = MultiMapMode::from_string(v); }
+    if let Some(v) = matches.value_of("seed") { config.seed = v.parse()?; }
+    if let Some(v) = matches.value_of("unpaired") { config.unpaired = UnpairedPolicy::from_option(Some(v)); }
+    if let Some(vals) = matches.values_of("columns") { config.columns = vals.filter_map(ExtraColumn::from_string).collect(); }
+    if let Some(vals) = matches.values_of("enzyme") { config.enzymes = vals.map(|e| e.to_string()).collect(); }
+
+    Ok(config)
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
     let matches = App::new("convertor")
         .version("0.1.0")
@@ -94,7 +348,8 @@ fn main() -> Result<(), Box<dyn Error>> {
                         .value_name("FILE")
                         .takes_value(true)
                         .required(true)
-                        .help("Path to alignments in bam format.")
+                        .help("Path to alignments in bam, sam or cram format ('-' for stdin). \
+                               Cram is decoded by samtools (it must be in PATH) and can not be read from stdin.")
                 )
                 .arg( pairs_arg("Path to file in pairs format.") )
                 .arg( Arg::with_name("stats")
@@ -103,7 +358,7 @@ fn main() -> Result<(), Box<dyn Error>> {
                     .value_name("FILE")
                     .takes_value(true)
                     .required(true)
-                    .help("Path to file with statistic.") )
+                    .help("Path to file with statistic (JSON if it has .json extension, otherwise TSV).") )
                 .arg(
                     Arg::with_name("graph")
                         .short("g")
@@ -113,11 +368,27 @@ fn main() -> Result<(), Box<dyn Error>> {
                         .required(false)
                         .help("Path to graph in gfa format.")
                 )
+                .arg( config_arg() )
+                .arg( min_mapq_arg() )
+                .arg( max_mol_size_arg() )
+                .arg( matched_rate_arg() )
+                .arg( mapq_zero_rescue_arg() )
+                .arg( strategy_arg() )
+                .arg( multimap_arg() )
+                .arg( seed_arg() )
+                .arg( walks_policy_arg() )
+                .arg( columns_arg() )
+                .arg( filtered_arg() )
+                .arg( out_bam_arg() )
+                .arg( unpaired_arg() )
+                .arg( threads_arg() )
+                .arg( enzyme_arg() )
+                .arg( reference_arg() )
                 .arg( log_level_arg() )
         )
         .subcommand(
             SubCommand::with_name("sort")
-                .about("Sort pairs file using sort command (see man sort).")
+                .about("Sort pairs file by contigs and positions using external merge sort.")
                 .arg( pairs_arg("Path to file with pairs.") )
                 .arg( out_pairs_arg("Path to file with sorted pairs.") )
                 .arg(
@@ -127,7 +398,7 @@ fn main() -> Result<(), Box<dyn Error>> {
                         .value_name("STR")
                         .takes_value(true)
                         .required(false)
-                        .help("The amount of RAM memory for sorting.")
+                        .help("The amount of RAM memory for sorting (e.g. 512M, 2G).")
                 )
                 .arg(
                     Arg::with_name("nproc")
@@ -136,7 +407,7 @@ fn main() -> Result<(), Box<dyn Error>> {
                         .value_name("NUM")
                         .takes_value(true)
                         .required(false)
-                        .help("Number of processes for sorting.")
+                        .help("Number of threads for sorting chunks of pairs (default 4).")
                 )
                 .arg(
                     Arg::with_name("tmpdir")
@@ -149,13 +420,246 @@ fn main() -> Result<(), Box<dyn Error>> {
                 )
                 .arg(log_level_arg() )
         )
+        .subcommand(
+            SubCommand::with_name("merge")
+                .about("Merge sorted pairs files (e.g. of different sequencing lanes) into a single sorted file.")
+                .arg(
+                    Arg::with_name("input")
+                        .short("i")
+                        .long("input")
+                        .value_name("FILE")
+                        .multiple(true)
+                        .takes_value(true)
+                        .required(true)
+                        .help("Paths to files with sorted pairs, they must have the same contigs and columns.")
+                )
+                .arg( out_pairs_arg("Path to file with merged sorted pairs.") )
+                .arg( log_level_arg() )
+        )
+        .subcommand(
+            SubCommand::with_name("index")
+                .about("Index sorted pairs file compressed with bgzip (.gz), index is saved into <pairs>.pxi.")
+                .arg( pairs_arg("Path to sorted and compressed file with pairs.") )
+                .arg( log_level_arg() )
+        )
+        .subcommand(
+            SubCommand::with_name("extract")
+                .about("Extract pairs of two contigs from indexed pairs file.")
+                .arg( pairs_arg("Path to indexed file with pairs.") )
+                .arg( out_pairs_arg("Path to file with extracted pairs.") )
+                .arg(
+                    Arg::with_name("tig1")
+                        .long("tig1")
+                        .value_name("STR")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The first contig of pairs.")
+                )
+                .arg(
+                    Arg::with_name("tig2")
+                        .long("tig2")
+                        .value_name("STR")
+                        .takes_value(true)
+                        .required(false)
+                        .help("The second contig of pairs (the same as the first one by default).")
+                )
+                .arg(
+                    Arg::with_name("start")
+                        .long("start")
+                        .value_name("NUM")
+                        .takes_value(true)
+                        .required(false)
+                        .help("The minimal position on the first contig.")
+                )
+                .arg(
+                    Arg::with_name("end")
+                        .long("end")
+                        .value_name("NUM")
+                        .takes_value(true)
+                        .required(false)
+                        .help("The maximal position on the first contig.")
+                )
+                .arg( log_level_arg() )
+        )
         .subcommand(
             SubCommand::with_name("dedup")
                 .about("Remove duplicated Hi-C reads from file.")
                 .arg( pairs_arg("Path to file with pairs.") )
                 .arg( out_pairs_arg("Path to file with deduplicated pairs.") )
+                .arg( out_dups_arg() )
+                .arg( Arg::with_name("stats")
+                    .short("s")
+                    .long("stats")
+                    .value_name("FILE")
+                    .takes_value(true)
+                    .required(false)
+                    .help("Path to file with statistic of deduplication (JSON if it has .json extension, otherwise TSV).") )
+                .arg( max_mismatch_arg() )
+                .arg( ignore_strands_arg() )
+                .arg( optical_dist_arg() )
+                .arg( mark_dups_arg() )
+                .arg(
+                    Arg::with_name("unsorted")
+                        .long("unsorted")
+                        .takes_value(false)
+                        .required(false)
+                        .help("Deduplicate pairs which are not sorted by hashing of their positions, \
+                               the result is the same as for sorted pairs with zero mismatch if bucket is 1.")
+                )
+                .arg(
+                    Arg::with_name("bucket")
+                        .long("bucket")
+                        .value_name("NUM")
+                        .takes_value(true)
+                        .required(false)
+                        .help("Size of bins of positions for unsorted pairs, pairs in the same bins are duplicates (default 1).")
+                )
+                .arg(
+                    Arg::with_name("mem")
+                        .short("m")
+                        .long("memory")
+                        .value_name("STR")
+                        .takes_value(true)
+                        .required(false)
+                        .help("The amount of RAM memory for deduplication of unsorted pairs (e.g. 512M, 2G).")
+                )
+                .arg(
+                    Arg::with_name("tmpdir")
+                        .short("d")
+                        .long("tmpdir")
+                        .value_name("PATH")
+                        .takes_value(true)
+                        .required(false)
+                        .help("Directory for storing temporary files.")
+                )
                 .arg(log_level_arg() )
         )
+        .subcommand(
+            SubCommand::with_name("pipeline")
+                .about("Convert bam to pairs, sort and deduplicate them in a single pass.")
+                .arg(
+                    Arg::with_name("bam")
+                        .short("b")
+                        .long("bam")
+                        .value_name("FILE")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Path to alignments in bam, sam or cram format ('-' for stdin). \
+                               Cram is decoded by samtools (it must be in PATH) and can not be read from stdin.")
+                )
+                .arg( out_pairs_arg("Path to file with sorted and deduplicated pairs.") )
+                .arg( out_dups_arg() )
+                .arg( max_mismatch_arg() )
+                .arg( ignore_strands_arg() )
+                .arg( optical_dist_arg() )
+                .arg( mark_dups_arg() )
+                .arg( Arg::with_name("stats")
+                    .short("s")
+                    .long("stats")
+                    .value_name("FILE")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Path to file with statistic (JSON if it has .json extension, otherwise TSV).") )
+                .arg(
+                    Arg::with_name("graph")
+                        .short("g")
+                        .long("graph")
+                        .value_name("FILE")
+                        .takes_value(true)
+                        .required(false)
+                        .help("Path to graph in gfa format.")
+                )
+                .arg( config_arg() )
+                .arg( min_mapq_arg() )
+                .arg( max_mol_size_arg() )
+                .arg( matched_rate_arg() )
+                .arg( mapq_zero_rescue_arg() )
+                .arg( strategy_arg() )
+                .arg( multimap_arg() )
+                .arg( seed_arg() )
+                .arg( walks_policy_arg() )
+                .arg( columns_arg() )
+                .arg( filtered_arg() )
+                .arg( out_bam_arg() )
+                .arg( unpaired_arg() )
+                .arg( threads_arg() )
+                .arg( enzyme_arg() )
+                .arg( reference_arg() )
+                .arg(
+                    Arg::with_name("mem")
+                        .short("m")
+                        .long("memory")
+                        .value_name("STR")
+                        .takes_value(true)
+                        .required(false)
+                        .help("The amount of RAM memory for sorting (e.g. 512M, 2G).")
+                )
+                .arg(
+                    Arg::with_name("tmpdir")
+                        .short("d")
+                        .long("tmpdir")
+                        .value_name("PATH")
+                        .takes_value(true)
+                        .required(false)
+                        .help("Directory for storing temporary files.")
+                )
+                .arg( log_level_arg() )
+        )
+        .subcommand(
+            SubCommand::with_name("select")
+                .about("Select pairs matching filter expression.")
+                .arg( pairs_arg("Path to file with pairs.") )
+                .arg( out_pairs_arg("Path to file with selected pairs.") )
+                .arg(
+                    Arg::with_name("expr")
+                        .short("e")
+                        .long("expr")
+                        .value_name("STR")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Filter expression over columns of pairs file, e.g. \
+                               \"chr1 == chr2 and abs(pos2 - pos1) >= 1000 and mapq1 >= 30 and mapq2 >= 30\". \
+                               Supported operators: or, and, not, ==, !=, <, <=, >, >=, +, -, abs(), \
+                               in [...] and in file('path').")
+                )
+                .arg(
+                    Arg::with_name("out_rest")
+                        .long("out_rest")
+                        .value_name("FILE")
+                        .takes_value(true)
+                        .required(false)
+                        .help("Path to file for pairs which do not match expression.")
+                )
+                .arg( log_level_arg() )
+        )
+        .subcommand(
+            SubCommand::with_name("stats")
+                .about("Manipulate statistics of conversion.")
+                .subcommand(
+                    SubCommand::with_name("merge")
+                        .about("Sum statistics of sharded conversions.")
+                        .arg(
+                            Arg::with_name("input")
+                                .short("i")
+                                .long("input")
+                                .value_name("FILE")
+                                .multiple(true)
+                                .takes_value(true)
+                                .required(true)
+                                .help("Paths to files with statistics.")
+                        )
+                        .arg(
+                            Arg::with_name("output")
+                                .short("o")
+                                .long("output")
+                                .value_name("FILE")
+                                .takes_value(true)
+                                .required(true)
+                                .help("Path to file with merged statistics.")
+                        )
+                        .arg( log_level_arg() )
+                )
+        )
         .get_matches();
 
     match matches.subcommand() {
@@ -164,10 +668,18 @@ fn main() -> Result<(), Box<dyn Error>> {
             let bam_file = convert_matches.value_of("bam").expect("Input bam file must be provided.");
             let pairs_file = convert_matches.value_of("pairs").expect("Output pairs file must be provided.");
             let stat_file = convert_matches.value_of("stats").expect("Output stat file must be provided.");
-            match convert_matches.value_of("graph") {
-                None =>  convert_bam_to_pairs(Path::new(bam_file), Path::new(pairs_file), Path::new(stat_file), None)?,
-                Some(_) => convert_bam_to_pairs(Path::new(bam_file), Path::new(pairs_file), Path::new(stat_file), None)?,
-            }
+            let graph_file = convert_matches.value_of("graph").map(Path::new);
+            let config = load_config(convert_matches)?;
+            let options = ConvertOptions {
+                filtered_file: convert_matches.value_of("filtered").map(Path::new),
+                out_bam: convert_matches.value_of("out_bam").map(Path::new),
+                reference: convert_matches.value_of("reference").map(Path::new),
+                fragments: load_fragments(convert_matches, &config)?,
+                threads: convert_matches.value_of("threads").unwrap_or("1").parse()?,
+                ..ConvertOptions::default()
+            };
+            convert_bam_to_pairs(Path::new(bam_file), Path::new(pairs_file), Path::new(stat_file), graph_file,
+                                 &config, options)?;
         },
         ("sort", Some(sort_matches)) => {
             setup_logging(1, "sort.log".as_ref()).expect("failed to initialize logging.");
@@ -179,12 +691,91 @@ fn main() -> Result<(), Box<dyn Error>> {
             sort_pairs(Path::new(in_file), Path::new(out_file), nproc, mem, tmpdir)?;
 
         },
+        ("merge", Some(merge_matches)) => {
+            setup_logging(1, "merge.log".as_ref()).expect("failed to initialize logging.");
+            let in_files: Vec<&Path> = merge_matches.values_of("input")
+                .expect("Input pairs files must be provided.")
+                .map(Path::new)
+                .collect();
+            let out_file = merge_matches.value_of("out_pairs").expect("Output pairs file must be provided.");
+            merge_pairs(&in_files, Path::new(out_file))?;
+        },
+        ("index", Some(index_matches)) => {
+            setup_logging(1, "index.log".as_ref()).expect("failed to initialize logging.");
+            let in_file = index_matches.value_of("pairs").expect("Input pairs file must be provided.");
+            index_pairs(Path::new(in_file))?;
+        },
+        ("extract", Some(extract_matches)) => {
+            setup_logging(1, "extract.log".as_ref()).expect("failed to initialize logging.");
+            let in_file = extract_matches.value_of("pairs").expect("Input pairs file must be provided.");
+            let out_file = extract_matches.value_of("out_pairs").expect("Output pairs file must be provided.");
+            let tig1 = extract_matches.value_of("tig1").expect("The first contig must be provided.");
+            let tig2 = extract_matches.value_of("tig2").unwrap_or(tig1);
+            let start: Option<i64> = extract_matches.value_of("start").map(|s| s.parse()).transpose()?;
+            let end: Option<i64> = extract_matches.value_of("end").map(|s| s.parse()).transpose()?;
+            extract_pairs(Path::new(in_file), Path::new(out_file), tig1, tig2, start, end)?;
+        },
         ("dedup", Some(dedup_matches)) => {
             setup_logging(1, "dedup.log".as_ref()).expect("failed to initialize logging.");
             let in_file = dedup_matches.value_of("pairs").expect("Input pairs file must be provided.");
             let out_file = dedup_matches.value_of("out_pairs").expect("Output pairs file must be provided.");
-            deduplicate_pairs(Path::new(in_file), Path::new(out_file));
+            let dups_file = dedup_matches.value_of("out_dups").map(Path::new);
+            let stat_file = dedup_matches.value_of("stats").map(Path::new);
+            let dedup_config = load_dedup_config(dedup_matches)?;
+            if dedup_matches.is_present("unsorted") {
+                let options = UnsortedDedupOptions {
+                    dups_file,
+                    stat_file,
+                    bucket: dedup_matches.value_of("bucket").unwrap_or("1").parse()?,
+                    mem: dedup_matches.value_of("mem").unwrap_or("2G"),
+                    tmpdir: dedup_matches.value_of("tmpdir"),
+                };
+                deduplicate_unsorted_pairs(Path::new(in_file), Path::new(out_file), &dedup_config, options)?;
+            } else {
+                deduplicate_pairs(Path::new(in_file), Path::new(out_file), dups_file, stat_file, &dedup_config)?;
+            }
         }
+        ("pipeline", Some(pipeline_matches)) => {
+            setup_logging(1, "pipeline.log".as_ref()).expect("failed to initialize logging.");
+            let bam_file = pipeline_matches.value_of("bam").expect("Input bam file must be provided.");
+            let out_file = pipeline_matches.value_of("out_pairs").expect("Output pairs file must be provided.");
+            let stat_file = pipeline_matches.value_of("stats").expect("Output stat file must be provided.");
+            let graph_file = pipeline_matches.value_of("graph").map(Path::new);
+            let config = load_config(pipeline_matches)?;
+            let dedup_config = load_dedup_config(pipeline_matches)?;
+            let options = ConvertOptions {
+                filtered_file: pipeline_matches.value_of("filtered").map(Path::new),
+                out_bam: pipeline_matches.value_of("out_bam").map(Path::new),
+                reference: pipeline_matches.value_of("reference").map(Path::new),
+                fragments: load_fragments(pipeline_matches, &config)?,
+                threads: pipeline_matches.value_of("threads").unwrap_or("1").parse()?,
+                dups_file: pipeline_matches.value_of("out_dups").map(Path::new),
+                mem: pipeline_matches.value_of("mem").unwrap_or("2G"),
+                tmpdir: pipeline_matches.value_of("tmpdir"),
+            };
+            run_pipeline(Path::new(bam_file), Path::new(out_file), Path::new(stat_file), graph_file,
+                         &config, &dedup_config, options)?;
+        }
+        ("select", Some(select_matches)) => {
+            setup_logging(1, "select.log".as_ref()).expect("failed to initialize logging.");
+            let in_file = select_matches.value_of("pairs").expect("Input pairs file must be provided.");
+            let out_file = select_matches.value_of("out_pairs").expect("Output pairs file must be provided.");
+            let expr = select_matches.value_of("expr").expect("Filter expression must be provided.");
+            let rest_file = select_matches.value_of("out_rest").map(Path::new);
+            select_pairs(Path::new(in_file), Path::new(out_file), rest_file, expr)?;
+        },
+        ("stats", Some(stats_matches)) => match stats_matches.subcommand() {
+            ("merge", Some(merge_matches)) => {
+                setup_logging(1, "stats.log".as_ref()).expect("failed to initialize logging.");
+                let in_files: Vec<&Path> = merge_matches.values_of("input")
+                    .expect("Input stat files must be provided.")
+                    .map(Path::new)
+                    .collect();
+                let out_file = merge_matches.value_of("output").expect("Output stat file must be provided.");
+                merge_stats(&in_files, Path::new(out_file))?;
+            },
+            _ => eprintln!("No stats subcommands were provided. See help for available one."),
+        },
         ("", None) => eprintln!("No subcommands were provided. See help for available one."),
         _ => unreachable!(),
     };
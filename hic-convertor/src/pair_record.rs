@@ -1,16 +1,23 @@
 use ascii::AsciiString;
 use std::fmt;
 use std::str::FromStr;
+use bam::record::cigar::Operation;
+
+use super::xa_tag::MultiMapResolution;
+use super::pairs_format::ExtraColumn;
+use super::digest::{Fragment, FragmentIndex};
 
 const FIELD_SEP: char = '\t';
-// pub const COL_READID: usize = 0;
+const NULL_TIG: &str = "!";
+pub const COL_READID: usize = 0;
 pub const COL_TIG1: usize = 1;
 pub const COL_POS1: usize = 2;
 pub const COL_TIG2: usize = 3;
 pub const COL_POS2: usize = 4;
-// pub const COL_STRAND1: usize = 5;
-// pub const COL_STRAND2: usize = 6;
+pub const COL_STRAND1: usize = 5;
+pub const COL_STRAND2: usize = 6;
 
+#[derive(Clone, Copy)]
 pub enum Strand {
     Forward,
     Reverse
@@ -25,6 +32,86 @@ impl fmt::Display for Strand {
     }
 }
 
+// Type of one side of Hi-C pair in terms of pairtools: U - unique, R - rescued from chimeric read,
+// M - multimapping or low mapq, N - unmapped, W - unresolved chimeric read (walk), X - corrupted record.
+#[derive(Clone, Copy, PartialEq)]
+pub enum SideType {
+    Unique,
+    Rescued,
+    Multi,
+    Null,
+    Walk,
+    Corrupt
+}
+
+impl fmt::Display for SideType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SideType::Unique => write!(f, "U"),
+            SideType::Rescued => write!(f, "R"),
+            SideType::Multi => write!(f, "M"),
+            SideType::Null => write!(f, "N"),
+            SideType::Walk => write!(f, "W"),
+            SideType::Corrupt => write!(f, "X"),
+        }
+    }
+}
+
+// pairtools-compatible pair type code, e.g. UU, UR, NM, WW.
+#[derive(Clone, Copy, PartialEq)]
+pub struct PairType(pub SideType, pub SideType);
+
+impl fmt::Display for PairType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.0, self.1)
+    }
+}
+
+// How the pair was obtained from alignments of read pair: linear - 1&1 alignments, simple - 1&2 alignments,
+// complex - 2&2 alignments, walk - longer walks, none - pair was filtered.
+#[derive(Clone, Copy, PartialEq)]
+pub enum RescuePath {
+    Linear,
+    Simple,
+    Complex,
+    Walk,
+    None
+}
+
+impl fmt::Display for RescuePath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RescuePath::Linear => write!(f, "linear"),
+            RescuePath::Simple => write!(f, "simple"),
+            RescuePath::Complex => write!(f, "complex"),
+            RescuePath::Walk => write!(f, "walk"),
+            RescuePath::None => write!(f, "none"),
+        }
+    }
+}
+
+// One side of Hi-C pair: reference, position (middle of alignment), strand, mapq and side type.
+#[derive(Clone)]
+pub struct Alignment {
+    pub ref_id: u32,
+    pub pos: i64,
+    pub strand: Strand,
+    pub mapq: u8,
+    pub side_type: SideType
+}
+
+impl Alignment {
+    pub fn from_bam(rec: &bam::Record, side_type: SideType) -> Alignment {
+        Alignment {
+            ref_id: rec.ref_id() as u32,
+            pos: get_alignment_pos(rec),
+            strand: if rec.flag().is_reverse_strand() {Strand::Reverse} else {Strand::Forward},
+            mapq: rec.mapq(),
+            side_type,
+        }
+    }
+}
+
 pub struct PairRecord {
     pub qname: AsciiString,
     pub name1: AsciiString,
@@ -32,7 +119,15 @@ pub struct PairRecord {
     pub strand1: Strand,
     pub name2: AsciiString,
     pub pos2: i64,
-    pub strand2: Strand
+    pub strand2: Strand,
+    pub mapq1: u8,
+    pub mapq2: u8,
+    pub pair_type: PairType,
+    pub rescue: RescuePath,
+    pub walk_pair_index: u32,
+    pub multimap: Option<(MultiMapResolution, f64)>,
+    pub frag1: Option<Fragment>,
+    pub frag2: Option<Fragment>
 }
 
 impl PairRecord {
@@ -45,6 +140,14 @@ impl PairRecord {
             name2: AsciiString::default(),
             pos2: -1,
             strand2: Strand::Forward,
+            mapq1: 0,
+            mapq2: 0,
+            pair_type: PairType(SideType::Null, SideType::Null),
+            rescue: RescuePath::None,
+            walk_pair_index: 1,
+            multimap: None,
+            frag1: None,
+            frag2: None,
         }
     }
 
@@ -53,21 +156,77 @@ impl PairRecord {
             return PairRecord::new(); // TODO thorw exception
         }
 
-        let (r1, r2) = get_ordered_alignments(r1, r2);
-        let name1 = header.reference_name(r1.ref_id() as u32).unwrap(); //TODO throw exception
-        let name2 = header.reference_name(r2.ref_id() as u32).unwrap();
+        PairRecord::from_alignments(r1.name(), &Alignment::from_bam(r1, SideType::Unique), &Alignment::from_bam(r2, SideType::Unique), header)
+    }
+
+    // Pair for filtered read pair. Unmapped sides are reported with '!' as contig name and go first.
+    pub fn from_filtered(qname: &[u8], a1: Option<Alignment>, a2: Option<Alignment>,
+                         null_types: (SideType, SideType), header: &bam::Header) -> PairRecord {
+        match (a1, a2) {
+            (Some(a1), Some(a2)) => {
+                let mut rec = PairRecord::from_alignments(qname, &a1, &a2, header);
+                rec.rescue = RescuePath::None;
+                rec
+            },
+            (a1, a2) => {
+                let (null_type, algn) = match (a1, a2) {
+                    (Some(a), None) => (null_types.1, Some(a)),
+                    (None, Some(a)) => (null_types.0, Some(a)),
+                    _ => (null_types.0, None),
+                };
+
+                let mut rec = PairRecord::new();
+                rec.qname = AsciiString::from_ascii(qname).unwrap();
+                rec.name1 = AsciiString::from_str(NULL_TIG).unwrap();
+                rec.strand1 = Strand::Reverse;
+                match algn {
+                    Some(a) => {
+                        rec.name2 = AsciiString::from_ascii(header.reference_name(a.ref_id).unwrap()).unwrap();
+                        rec.pos2 = a.pos;
+                        rec.strand2 = a.strand;
+                        rec.mapq2 = a.mapq;
+                        rec.pair_type = PairType(null_type, a.side_type);
+                    },
+                    None => {
+                        rec.name2 = AsciiString::from_str(NULL_TIG).unwrap();
+                        rec.strand2 = Strand::Reverse;
+                        rec.pair_type = PairType(null_types.0, null_types.1);
+                    }
+                }
+                rec
+            }
+        }
+    }
+
+    pub fn from_alignments(qname: &[u8], a1: &Alignment, a2: &Alignment, header: &bam::Header) -> PairRecord {
+        let (a1, a2) = if (a1.ref_id, a1.pos) <= (a2.ref_id, a2.pos) { (a1, a2) } else { (a2, a1) };
+        let name1 = header.reference_name(a1.ref_id).unwrap(); //TODO throw exception
+        let name2 = header.reference_name(a2.ref_id).unwrap();
 
         PairRecord {
-            qname: AsciiString::from_ascii(r1.name()).unwrap(),
+            qname: AsciiString::from_ascii(qname).unwrap(),
             name1: AsciiString::from_ascii(name1).unwrap(),
-            pos1: get_alignment_pos(r1),
-            strand1: if r1.flag().is_reverse_strand() {Strand::Reverse} else {Strand::Forward},
+            pos1: a1.pos,
+            strand1: a1.strand,
             name2: AsciiString::from_ascii(name2).unwrap(),
-            pos2: get_alignment_pos(r2),
-            strand2: if r2.flag().is_reverse_strand() {Strand::Reverse} else {Strand::Forward},
+            pos2: a2.pos,
+            strand2: a2.strand,
+            mapq1: a1.mapq,
+            mapq2: a2.mapq,
+            pair_type: PairType(a1.side_type, a2.side_type),
+            rescue: RescuePath::Linear,
+            walk_pair_index: 1,
+            multimap: None,
+            frag1: None,
+            frag2: None,
         }
     }
 
+    pub fn annotate_fragments(&mut self, index: &FragmentIndex) {
+        self.frag1 = index.find_fragment(self.name1.as_str(), self.pos1);
+        self.frag2 = index.find_fragment(self.name2.as_str(), self.pos2);
+    }
+
     // pub fn get_ordered_coordinates(&self) -> Option<(i64, i64)> {
     //     if self.name1 == self.name2 {
     //         if self.pos1 < self.pos2 {
@@ -78,13 +237,47 @@ impl PairRecord {
     //     } else { None }
     // }
 
-    pub fn to_string(&self) -> AsciiString {
-        AsciiString::from_str(
-            format!("{0}{7}{1}{7}{2}{7}{3}{7}{4}{7}{5}{7}{6}", self.qname, self.name1, self.pos1, self.name2, self.pos2, self.strand1, self.strand2, FIELD_SEP).as_str()
-        ).unwrap()
+    // Positions are written 1-based as required by 4DN pairs format.
+    pub fn to_string(&self, extra_columns: &[ExtraColumn]) -> AsciiString {
+        let mut line = format!("{0}{7}{1}{7}{2}{7}{3}{7}{4}{7}{5}{7}{6}", self.qname, self.name1, self.pos1 + 1, self.name2, self.pos2 + 1, self.strand1, self.strand2, FIELD_SEP);
+        for col in extra_columns {
+            let value = match col {
+                ExtraColumn::Mapq => format!("{}{}{}", self.mapq1, FIELD_SEP, self.mapq2),
+                ExtraColumn::PairType => self.pair_type.to_string(),
+                ExtraColumn::Rescue => self.rescue.to_string(),
+                ExtraColumn::WalkPairIndex => self.walk_pair_index.to_string(),
+                ExtraColumn::MultiMap => match &self.multimap {
+                    Some((resolution, weight)) => format!("{}{}{}", resolution, FIELD_SEP, weight),
+                    None => format!("{}{}{}", MultiMapResolution::Unique, FIELD_SEP, 1.0),
+                },
+                ExtraColumn::Fragments => format!("{}{}{}", fragment_to_string(self.frag1), FIELD_SEP, fragment_to_string(self.frag2)),
+            };
+            line.push(FIELD_SEP);
+            line.push_str(value.as_str());
+        }
+        AsciiString::from_str(line.as_str()).unwrap()
     }
 }
 
+// Index, start and end of fragment (1-based, inclusive) or nulls if the side was not annotated.
+fn fragment_to_string(frag: Option<Fragment>) -> String {
+    match frag {
+        Some(f) => format!("{1}{0}{2}{0}{3}", FIELD_SEP, f.index, f.start + 1, f.end),
+        None => format!("{1}{0}{1}{0}{1}", FIELD_SEP, NULL_TIG),
+    }
+}
+
+pub fn get_matched_proportion(rec: &bam::Record) -> f64 {
+    let query_len = rec.cigar().calculate_query_len();
+    if query_len == 0 { return 0.0; }
+
+    let matched: u32 = rec.cigar().iter()
+        .filter(|(_, op)| matches!(op, Operation::AlnMatch | Operation::SeqMatch))
+        .map(|(len, _)| len)
+        .sum();
+    matched as f64 / query_len as f64
+}
+
 pub fn get_alignment_pos(rec: &bam::Record) -> i64 {
     (rec.start() + (rec.calculate_end() - rec.start()) / 2) as i64
 }
@@ -106,16 +299,3 @@ pub fn is_opposite_pair(fa: &bam::Record, sa: &bam::Record) -> bool {
     }
     can_rescue
 }
-
-pub fn get_ordered_alignments<'a>(fa: &'a bam::Record, sa: &'a bam::Record) -> (&'a bam::Record, &'a bam::Record) {
-    if fa.ref_id() == sa.ref_id() {
-        if get_alignment_pos(fa) < get_alignment_pos(sa) { (fa, sa) } else { (sa, fa) }
-    } else if fa.ref_id() < sa.ref_id() {
-        (fa, sa)
-    } else {
-        (sa, fa)
-    }
-}
-
-
-
@@ -0,0 +1,164 @@
+use std::fmt;
+use std::io::{self, Write};
+
+use serde::{Deserialize, Serialize};
+
+// Header of pairs file in 4DN format (https://github.com/4dn-dcic/pairix/blob/master/pairs_format_specification.md)
+pub const FORMAT_LINE: &str = "## pairs format v1.0";
+pub const SORTED_PREFIX: &str = "#sorted:";
+pub const SHAPE_PREFIX: &str = "#shape:";
+pub const CHROMSIZE_PREFIX: &str = "#chromsize:";
+pub const COLUMNS_PREFIX: &str = "#columns:";
+
+pub const SORTED_ORDER: &str = "chr1-chr2-pos1-pos2";
+pub const UPPER_TRIANGLE: &str = "upper triangle";
+
+pub const COLUMNS: [&str; 7] = ["readID", "chr1", "pos1", "chr2", "pos2", "strand1", "strand2"];
+
+// Optional columns which can follow the mandatory ones.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExtraColumn {
+    Mapq,
+    PairType,
+    Rescue,
+    WalkPairIndex,
+    #[serde(rename = "multimap")]
+    MultiMap,
+    #[serde(rename = "rfrag")]
+    Fragments,
+}
+
+impl ExtraColumn {
+    pub fn from_string(s: &str) -> Option<ExtraColumn> {
+        match s {
+            "mapq" => Some(ExtraColumn::Mapq),
+            "pair_type" => Some(ExtraColumn::PairType),
+            "rescue" => Some(ExtraColumn::Rescue),
+            "walk_pair_index" => Some(ExtraColumn::WalkPairIndex),
+            "multimap" => Some(ExtraColumn::MultiMap),
+            "rfrag" => Some(ExtraColumn::Fragments),
+            _ => None
+        }
+    }
+
+    pub fn names(&self) -> &'static [&'static str] {
+        match *self {
+            ExtraColumn::Mapq => &["mapq1", "mapq2"],
+            ExtraColumn::PairType => &["pair_type"],
+            ExtraColumn::Rescue => &["rescue"],
+            ExtraColumn::WalkPairIndex => &["walk_pair_index"],
+            ExtraColumn::MultiMap => &["multimap", "weight"],
+            ExtraColumn::Fragments => &["rfrag1", "rfrag_start1", "rfrag_end1", "rfrag2", "rfrag_start2", "rfrag_end2"],
+        }
+    }
+}
+
+impl fmt::Display for ExtraColumn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExtraColumn::Mapq => write!(f, "mapq"),
+            ExtraColumn::PairType => write!(f, "pair_type"),
+            ExtraColumn::Rescue => write!(f, "rescue"),
+            ExtraColumn::WalkPairIndex => write!(f, "walk_pair_index"),
+            ExtraColumn::MultiMap => write!(f, "multimap"),
+            ExtraColumn::Fragments => write!(f, "rfrag"),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct PairsHeader {
+    pub sorted: Option<String>,
+    pub shape: Option<String>,
+    pub chromsizes: Vec<(String, u64)>,
+    pub columns: Vec<String>,
+    pub other: Vec<String>,
+}
+
+impl PairsHeader {
+    pub fn from_bam_header(header: &bam::Header, extra_columns: &[ExtraColumn]) -> PairsHeader {
+        let chromsizes = header.reference_names().iter()
+            .enumerate()
+            .map(|(i, name)| (name.clone(), header.reference_len(i as u32).unwrap_or(0) as u64))
+            .collect();
+
+        let mut columns: Vec<String> = COLUMNS.iter().map(|c| c.to_string()).collect();
+        for col in extra_columns {
+            columns.extend(col.names().iter().map(|c| c.to_string()));
+        }
+
+        PairsHeader {
+            sorted: None,
+            shape: Some(UPPER_TRIANGLE.to_string()),
+            chromsizes,
+            columns,
+            other: Vec::new(),
+        }
+    }
+
+    // Parses header lines, lines without known prefixes are kept as is.
+    pub fn from_lines<'a>(lines: impl Iterator<Item = &'a str>) -> io::Result<PairsHeader> {
+        let mut header = PairsHeader::default();
+
+        for line in lines {
+            if line.starts_with(FORMAT_LINE) {
+                continue;
+            } else if let Some(v) = line.strip_prefix(SORTED_PREFIX) {
+                header.sorted = Some(v.trim().to_string());
+            } else if let Some(v) = line.strip_prefix(SHAPE_PREFIX) {
+                header.shape = Some(v.trim().to_string());
+            } else if let Some(v) = line.strip_prefix(CHROMSIZE_PREFIX) {
+                let fields: Vec<&str> = v.split_whitespace().collect();
+                if fields.len() != 2 {
+                    return Err(invalid_header(line));
+                }
+                let size = fields[1].parse().map_err(|_| invalid_header(line))?;
+                header.chromsizes.push((fields[0].to_string(), size));
+            } else if let Some(v) = line.strip_prefix(COLUMNS_PREFIX) {
+                header.columns = v.split_whitespace().map(|c| c.to_string()).collect();
+            } else {
+                header.other.push(line.to_string());
+            }
+        }
+
+        Ok(header)
+    }
+
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        if self.columns.is_empty() {
+            COLUMNS.iter().position(|c| *c == name)
+        } else {
+            self.columns.iter().position(|c| c == name)
+        }
+    }
+
+    pub fn to_lines(&self) -> Vec<String> {
+        let mut lines = vec![FORMAT_LINE.to_string()];
+        if let Some(sorted) = &self.sorted {
+            lines.push(format!("{} {}", SORTED_PREFIX, sorted));
+        }
+        if let Some(shape) = &self.shape {
+            lines.push(format!("{} {}", SHAPE_PREFIX, shape));
+        }
+        for (name, size) in self.chromsizes.iter() {
+            lines.push(format!("{} {} {}", CHROMSIZE_PREFIX, name, size));
+        }
+        lines.extend(self.other.iter().cloned());
+        if !self.columns.is_empty() {
+            lines.push(format!("{} {}", COLUMNS_PREFIX, self.columns.join(" ")));
+        }
+        lines
+    }
+
+    pub fn write(&self, output: &mut impl Write) -> io::Result<()> {
+        for line in self.to_lines() {
+            writeln!(output, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+fn invalid_header(line: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Incorrect line in pairs header: {}", line))
+}
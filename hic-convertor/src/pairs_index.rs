@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use log::info;
+
+use super::bgzf::BgzfReader;
+use super::pair_record;
+
+const FIELD_SEP: char = '\t';
+const COMMENT_SYMBOL: char = '#';
+// Index is saved next to pairs file with this extension appended.
+pub const INDEX_EXT: &str = "pxi";
+// Virtual offset of the first record is stored for each window of pos1 of every pair of contigs.
+const WINDOW_SIZE: i64 = 1 << 14;
+
+// Index of BGZF pairs file sorted by tig1, tig2 and pos1 (similar to pairix), it allows to read pairs of
+// a single pair of contigs (and range of pos1) without scanning the whole file.
+pub struct PairsIndex {
+    windows: HashMap<(String, String), Vec<(i64, u64)>>,
+    order: Vec<(String, String)>
+}
+
+pub fn get_index_path(pairs_file: &Path) -> PathBuf {
+    let mut name = pairs_file.as_os_str().to_os_string();
+    name.push(".");
+    name.push(INDEX_EXT);
+    PathBuf::from(name)
+}
+
+impl PairsIndex {
+    pub fn build(pairs_file: &Path) -> io::Result<PairsIndex> {
+        let mut reader = BgzfReader::new(BufReader::new(File::open(pairs_file)?));
+        let mut index = PairsIndex { windows: HashMap::new(), order: Vec::new() };
+        let mut line = String::new();
+        let mut prev_pos1 = 0;
+
+        loop {
+            reader.fill_buf()?;
+            let offset = reader.virtual_offset();
+            line.clear();
+            if reader.read_line(&mut line)? == 0 { break; }
+            let record = line.trim_end_matches(&['\n', '\r'][..]);
+            if record.is_empty() || record.starts_with(COMMENT_SYMBOL) { continue; }
+
+            let (tig1, tig2, pos1) = parse_key(record)?;
+            let is_same_tigs = matches!(index.order.last(), Some((t1, t2)) if t1 == tig1 && t2 == tig2);
+            if is_same_tigs {
+                if pos1 < prev_pos1 {
+                    return Err(unsorted_error(record));
+                }
+                let windows = index.windows.get_mut(&(tig1.to_string(), tig2.to_string())).unwrap();
+                if !matches!(windows.last(), Some(&(w, _)) if w >= pos1 / WINDOW_SIZE) {
+                    windows.push((pos1 / WINDOW_SIZE, offset));
+                }
+            } else {
+                let key = (tig1.to_string(), tig2.to_string());
+                if index.windows.contains_key(&key) {
+                    return Err(unsorted_error(record));
+                }
+                index.windows.insert(key.clone(), vec![(pos1 / WINDOW_SIZE, offset)]);
+                index.order.push(key);
+            }
+            prev_pos1 = pos1;
+        }
+
+        info!("Index of {} contains {} pairs of contigs", pairs_file.display(), index.order.len());
+        Ok(index)
+    }
+
+    // Tab-separated lines with tig1, tig2, window of pos1 and virtual offset.
+    pub fn save(&self, index_file: &Path) -> io::Result<()> {
+        let mut f = BufWriter::new(File::create(index_file)?);
+        for key in self.order.iter() {
+            for (window, offset) in self.windows[key].iter() {
+                writeln!(f, "{1}{0}{2}{0}{3}{0}{4}", FIELD_SEP, key.0, key.1, window, offset)?;
+            }
+        }
+        f.flush()
+    }
+
+    pub fn from_file(index_file: &Path) -> io::Result<PairsIndex> {
+        let reader = BufReader::new(File::open(index_file)?);
+        let mut index = PairsIndex { windows: HashMap::new(), order: Vec::new() };
+        for line in reader.lines() {
+            let line = line?;
+            let fields: Vec<&str> = line.split(FIELD_SEP).collect();
+            if fields.len() != 4 {
+                return Err(invalid_index(line.as_str()));
+            }
+            let window: i64 = fields[2].parse().map_err(|_| invalid_index(line.as_str()))?;
+            let offset: u64 = fields[3].parse().map_err(|_| invalid_index(line.as_str()))?;
+
+            let key = (fields[0].to_string(), fields[1].to_string());
+            if !index.windows.contains_key(&key) {
+                index.order.push(key.clone());
+            }
+            index.windows.entry(key).or_default().push((window, offset));
+        }
+        Ok(index)
+    }
+
+    // Virtual offset to start reading pairs of contigs with pos1 >= start.
+    fn get_offset(&self, tig1: &str, tig2: &str, start: i64) -> Option<u64> {
+        let windows = self.windows.get(&(tig1.to_string(), tig2.to_string()))?;
+        let n_before = windows.iter().take_while(|&&(w, _)| w <= start / WINDOW_SIZE).count();
+        Some(windows[n_before.max(1) - 1].1)
+    }
+}
+
+// Writes header and pairs of tig1 and tig2 with pos1 in [start, end] into output, returns number of written pairs.
+pub fn extract_pairs(pairs_file: &Path, index: &PairsIndex, output: &mut impl Write, tig1: &str, tig2: &str,
+                     start: Option<i64>, end: Option<i64>) -> io::Result<u64> {
+    let mut reader = BgzfReader::new(BufReader::new(File::open(pairs_file)?));
+    for line in (&mut reader).lines() {
+        let line = line?;
+        if !line.starts_with(COMMENT_SYMBOL) { break; }
+        writeln!(output, "{}", line)?;
+    }
+
+    let start = start.unwrap_or(0);
+    let end = end.unwrap_or(i64::MAX);
+    let offset = match index.get_offset(tig1, tig2, start) {
+        Some(offset) => offset,
+        None => return Ok(0)
+    };
+
+    let mut n_pairs = 0;
+    reader.seek_virtual(offset)?;
+    for line in reader.lines() {
+        let line = line?;
+        if line.is_empty() { continue; }
+        let (t1, t2, pos1) = parse_key(line.as_str())?;
+        if t1 != tig1 || t2 != tig2 || pos1 > end { break; }
+        if pos1 >= start {
+            writeln!(output, "{}", line)?;
+            n_pairs += 1;
+        }
+    }
+    Ok(n_pairs)
+}
+
+fn parse_key(line: &str) -> io::Result<(&str, &str, i64)> {
+    let fields: Vec<&str> = line.split(FIELD_SEP).collect();
+    if fields.len() <= pair_record::COL_POS2 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Incorrect pairs record: {}", line)));
+    }
+    let pos1 = fields[pair_record::COL_POS1].parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("Incorrect pairs record: {}", line)))?;
+    Ok((fields[pair_record::COL_TIG1], fields[pair_record::COL_TIG2], pos1))
+}
+
+fn unsorted_error(line: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData,
+                   format!("Pairs must be sorted by tig1, tig2 and pos1 for indexing, unsorted record: {}", line))
+}
+
+fn invalid_index(line: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Incorrect line of pairs index: {}", line))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::bgzf;
+
+    fn tmp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("hic_convertor_index_{}_{}", std::process::id(), name))
+    }
+
+    fn write_pairs(path: &Path, lines: &[String]) {
+        let mut writer = bgzf::create_writer(path).unwrap();
+        writeln!(writer, "## pairs format v1.0").unwrap();
+        writeln!(writer, "#columns: readID chr1 pos1 chr2 pos2 strand1 strand2").unwrap();
+        for line in lines {
+            writeln!(writer, "{}", line).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    // Sorted pairs of three pairs of contigs, they span many windows and BGZF blocks.
+    fn sorted_pairs() -> Vec<String> {
+        let mut lines = Vec::new();
+        for &(tig1, tig2) in [("ctg1", "ctg1"), ("ctg1", "ctg2"), ("ctg2", "ctg2")].iter() {
+            for i in 0..6000 {
+                lines.push(format!("r{}\t{}\t{}\t{}\t{}\t+\t-", i, tig1, i * 13, tig2, i * 13 + 500));
+            }
+        }
+        lines
+    }
+
+    fn extract(pairs_file: &Path, index: &PairsIndex, tig1: &str, tig2: &str, start: Option<i64>,
+               end: Option<i64>) -> (u64, Vec<String>) {
+        let mut output = Vec::new();
+        let n_pairs = extract_pairs(pairs_file, index, &mut output, tig1, tig2, start, end).unwrap();
+        let lines = String::from_utf8(output).unwrap().lines()
+            .filter(|l| !l.starts_with(COMMENT_SYMBOL))
+            .map(|l| l.to_string())
+            .collect();
+        (n_pairs, lines)
+    }
+
+    #[test]
+    fn test_build_and_extract() {
+        let pairs_file = tmp_path("sorted.pairs.gz");
+        let index_file = get_index_path(&pairs_file);
+        let lines = sorted_pairs();
+        write_pairs(&pairs_file, &lines);
+
+        PairsIndex::build(&pairs_file).unwrap().save(&index_file).unwrap();
+        let index = PairsIndex::from_file(&index_file).unwrap();
+        assert_eq!(index.order.len(), 3);
+
+        let expected = |tig1: &str, tig2: &str, start: i64, end: i64| -> Vec<String> {
+            lines.iter().filter(|l| {
+                let (t1, t2, pos1) = parse_key(l).unwrap();
+                t1 == tig1 && t2 == tig2 && pos1 >= start && pos1 <= end
+            }).cloned().collect()
+        };
+        let (n_pairs, extracted) = extract(&pairs_file, &index, "ctg1", "ctg2", None, None);
+        assert_eq!(n_pairs, 6000);
+        assert_eq!(extracted, expected("ctg1", "ctg2", 0, i64::MAX));
+
+        let (n_pairs, extracted) = extract(&pairs_file, &index, "ctg2", "ctg2", Some(40000), Some(60000));
+        assert_eq!(extracted, expected("ctg2", "ctg2", 40000, 60000));
+        assert_eq!(n_pairs as usize, extracted.len());
+
+        let (_, extracted) = extract(&pairs_file, &index, "ctg1", "ctg1", Some(77999), None);
+        assert_eq!(extracted, expected("ctg1", "ctg1", 77999, i64::MAX));
+        assert_eq!(extract(&pairs_file, &index, "ctg2", "ctg1", None, None).0, 0);
+
+        fs::remove_file(&pairs_file).unwrap();
+        fs::remove_file(&index_file).unwrap();
+    }
+
+    #[test]
+    fn test_unsorted_pairs() {
+        let pairs_file = tmp_path("unsorted.pairs.gz");
+        let mut lines = sorted_pairs();
+        lines.swap(100, 101);
+        write_pairs(&pairs_file, &lines);
+        assert!(PairsIndex::build(&pairs_file).is_err());
+
+        let mut lines = sorted_pairs();
+        let last = lines.pop().unwrap();
+        lines.insert(0, last);
+        write_pairs(&pairs_file, &lines);
+        assert!(PairsIndex::build(&pairs_file).is_err());
+        fs::remove_file(&pairs_file).unwrap();
+    }
+}
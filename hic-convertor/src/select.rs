@@ -0,0 +1,513 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::iter::Peekable;
+use std::path::Path;
+use std::str::Chars;
+
+use log::info;
+
+use super::bgzf;
+use super::pairs_format::PairsHeader;
+
+const FIELD_SEP: char = '\t';
+const COMMENT_SYMBOL: char = '#';
+
+// Filter of pairs records given by expression over columns of pairs file, e.g.
+//   chr1 == chr2 and abs(pos2 - pos1) >= 1000
+//   mapq1 >= 30 && mapq2 >= 30 && pair_type in ['UU', 'UR', 'RU']
+//   chr1 in file('contigs.txt') and not (strand1 == strand2)
+// Columns are referred by names from header (chrom1/chrom2 are aliases of chr1/chr2), values are compared
+// as numbers if both of them are numbers, otherwise as strings. Lists for `in` are given either in brackets
+// or by file('path') with one value per line.
+pub struct Selector {
+    expr: Expr,
+}
+
+impl Selector {
+    pub fn new(expr: &str, header: &PairsHeader) -> io::Result<Selector> {
+        let tokens = tokenize(expr)?;
+        let mut parser = Parser { tokens: tokens.as_slice(), pos: 0, header };
+        let expr = parser.parse_or()?;
+        if parser.pos != tokens.len() {
+            return Err(invalid_expr(format!("unexpected {:?}", tokens[parser.pos])));
+        }
+        Ok(Selector { expr })
+    }
+
+    pub fn is_selected(&self, fields: &[&str]) -> io::Result<bool> {
+        self.expr.eval(fields)?.as_bool()
+    }
+}
+
+// Streams records matching expression into output file, other records are written into rest file if it is provided.
+pub fn select_pairs(inp_file: &Path, out_file: &Path, rest_file: Option<&Path>, expr: &str) -> io::Result<()> {
+    let mut lines = bgzf::open_reader(inp_file)?.lines();
+    let mut output = bgzf::create_writer(out_file)?;
+    let mut rest = match rest_file {
+        Some(path) => Some(bgzf::create_writer(path)?),
+        None => None
+    };
+
+    let mut header_lines = Vec::new();
+    let mut first_record = None;
+    for line in &mut lines {
+        let line = line?;
+        if line.starts_with(COMMENT_SYMBOL) {
+            header_lines.push(line);
+        } else if !line.is_empty() {
+            first_record = Some(line);
+            break;
+        }
+    }
+
+    let header = PairsHeader::from_lines(header_lines.iter().map(|l| l.as_str()))?;
+    let selector = Selector::new(expr, &header)?;
+    for line in header_lines.iter() {
+        writeln!(output, "{}", line)?;
+        if let Some(f) = &mut rest {
+            writeln!(f, "{}", line)?;
+        }
+    }
+
+    let (mut total, mut selected) = (0_u64, 0_u64);
+    for line in first_record.into_iter().map(Ok).chain(lines) {
+        let line = line?;
+        if line.is_empty() { continue; }
+        total += 1;
+
+        let fields: Vec<&str> = line.split(FIELD_SEP).collect();
+        if selector.is_selected(fields.as_slice())? {
+            selected += 1;
+            writeln!(output, "{}", line)?;
+        } else if let Some(f) = &mut rest {
+            writeln!(f, "{}", line)?;
+        }
+    }
+
+    output.finish()?;
+    if let Some(f) = rest {
+        f.finish()?;
+    }
+    info!("{} of {} pairs were selected.", selected, total);
+    Ok(())
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge
+}
+
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Cmp(CmpOp, Box<Expr>, Box<Expr>),
+    In(Box<Expr>, HashSet<String>),
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+    Abs(Box<Expr>),
+    Column(usize),
+    Num(f64),
+    Str(String),
+    Bool(bool),
+}
+
+enum Value<'a> {
+    Num(f64),
+    Str(&'a str),
+    Bool(bool),
+}
+
+impl<'a> Value<'a> {
+    fn as_bool(&self) -> io::Result<bool> {
+        match self {
+            Value::Bool(b) => Ok(*b),
+            _ => Err(invalid_expr("expression is not a condition".to_string()))
+        }
+    }
+
+    fn as_num(&self) -> Option<f64> {
+        match self {
+            Value::Num(n) => Some(*n),
+            Value::Str(s) => s.parse().ok(),
+            Value::Bool(_) => None,
+        }
+    }
+
+    fn as_str(&self) -> String {
+        match self {
+            Value::Num(n) => n.to_string(),
+            Value::Str(s) => s.to_string(),
+            Value::Bool(b) => b.to_string(),
+        }
+    }
+}
+
+impl Expr {
+    fn eval<'a>(&'a self, fields: &[&'a str]) -> io::Result<Value<'a>> {
+        let value = match self {
+            Expr::Or(a, b) => Value::Bool(a.eval(fields)?.as_bool()? || b.eval(fields)?.as_bool()?),
+            Expr::And(a, b) => Value::Bool(a.eval(fields)?.as_bool()? && b.eval(fields)?.as_bool()?),
+            Expr::Not(a) => Value::Bool(!a.eval(fields)?.as_bool()?),
+            Expr::Cmp(op, a, b) => Value::Bool(compare(*op, &a.eval(fields)?, &b.eval(fields)?)),
+            Expr::In(a, values) => Value::Bool(values.contains(&a.eval(fields)?.as_str())),
+            Expr::Add(a, b) => Value::Num(eval_num(a, fields)? + eval_num(b, fields)?),
+            Expr::Sub(a, b) => Value::Num(eval_num(a, fields)? - eval_num(b, fields)?),
+            Expr::Abs(a) => Value::Num(eval_num(a, fields)?.abs()),
+            Expr::Column(i) => match fields.get(*i) {
+                Some(field) => Value::Str(*field),
+                None => return Err(invalid_expr(format!("record has no column {}", i + 1)))
+            },
+            Expr::Num(n) => Value::Num(*n),
+            Expr::Str(s) => Value::Str(s.as_str()),
+            Expr::Bool(b) => Value::Bool(*b),
+        };
+        Ok(value)
+    }
+}
+
+fn eval_num<'a>(expr: &'a Expr, fields: &[&'a str]) -> io::Result<f64> {
+    let value = expr.eval(fields)?;
+    value.as_num().ok_or_else(|| invalid_expr(format!("{} is not a number", value.as_str())))
+}
+
+fn compare(op: CmpOp, a: &Value, b: &Value) -> bool {
+    let ord = match (a.as_num(), b.as_num()) {
+        (Some(x), Some(y)) => x.partial_cmp(&y),
+        _ => Some(a.as_str().cmp(&b.as_str())),
+    };
+
+    match ord {
+        Some(ord) => match op {
+            CmpOp::Eq => ord == Ordering::Equal,
+            CmpOp::Ne => ord != Ordering::Equal,
+            CmpOp::Lt => ord == Ordering::Less,
+            CmpOp::Le => ord != Ordering::Greater,
+            CmpOp::Gt => ord == Ordering::Greater,
+            CmpOp::Ge => ord != Ordering::Less,
+        },
+        None => op == CmpOp::Ne
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Ident(String),
+    Num(f64),
+    Str(String),
+    Cmp(CmpOp),
+    And,
+    Or,
+    Not,
+    Plus,
+    Minus,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Comma,
+}
+
+fn tokenize(expr: &str) -> io::Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = expr.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let token = if c.is_ascii_alphabetic() || c == '_' {
+            let ident = take_while(&mut chars, |c| c.is_ascii_alphanumeric() || c == '_');
+            match ident.as_str() {
+                "and" => Token::And,
+                "or" => Token::Or,
+                "not" => Token::Not,
+                _ => Token::Ident(ident)
+            }
+        } else if c.is_ascii_digit() || c == '.' {
+            let num = take_while(&mut chars, |c| c.is_ascii_digit() || c == '.' || c == 'e' || c == 'E');
+            Token::Num(num.parse().map_err(|_| invalid_expr(format!("incorrect number {}", num)))?)
+        } else if c == '\'' || c == '"' {
+            chars.next();
+            let s = take_while(&mut chars, |x| x != c);
+            if chars.next() != Some(c) {
+                return Err(invalid_expr(format!("string {} is not closed", s)));
+            }
+            Token::Str(s)
+        } else {
+            chars.next();
+            let next = chars.peek().cloned();
+            let mut pair = |token: Token| { chars.next(); token };
+            match (c, next) {
+                ('=', Some('=')) => pair(Token::Cmp(CmpOp::Eq)),
+                ('!', Some('=')) => pair(Token::Cmp(CmpOp::Ne)),
+                ('<', Some('=')) => pair(Token::Cmp(CmpOp::Le)),
+                ('>', Some('=')) => pair(Token::Cmp(CmpOp::Ge)),
+                ('&', Some('&')) => pair(Token::And),
+                ('|', Some('|')) => pair(Token::Or),
+                ('<', _) => Token::Cmp(CmpOp::Lt),
+                ('>', _) => Token::Cmp(CmpOp::Gt),
+                ('!', _) => Token::Not,
+                ('+', _) => Token::Plus,
+                ('-', _) => Token::Minus,
+                ('(', _) => Token::LParen,
+                (')', _) => Token::RParen,
+                ('[', _) => Token::LBracket,
+                (']', _) => Token::RBracket,
+                (',', _) => Token::Comma,
+                _ => return Err(invalid_expr(format!("unexpected symbol {}", c)))
+            }
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+fn take_while(chars: &mut Peekable<Chars>, pred: impl Fn(char) -> bool) -> String {
+    let mut s = String::new();
+    while let Some(&c) = chars.peek() {
+        if !pred(c) { break; }
+        s.push(c);
+        chars.next();
+    }
+    s
+}
+
+// Recursive descent parser, precedence from lowest: or, and, not, comparison and `in`, + and -, operands.
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    header: &'a PairsHeader,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> io::Result<()> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            token => Err(invalid_expr(format!("expected {:?}, found {:?}", expected, token)))
+        }
+    }
+
+    fn parse_or(&mut self) -> io::Result<Expr> {
+        let mut expr = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> io::Result<Expr> {
+        let mut expr = self.parse_not()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_not()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_not(&mut self) -> io::Result<Expr> {
+        if self.peek() == Some(&Token::Not) {
+            self.next();
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_cmp()
+    }
+
+    fn parse_cmp(&mut self) -> io::Result<Expr> {
+        let expr = self.parse_sum()?;
+        match self.peek().cloned() {
+            Some(Token::Cmp(op)) => {
+                self.next();
+                Ok(Expr::Cmp(op, Box::new(expr), Box::new(self.parse_sum()?)))
+            },
+            Some(Token::Ident(ref kw)) if kw == "in" => {
+                self.next();
+                Ok(Expr::In(Box::new(expr), self.parse_list()?))
+            },
+            _ => Ok(expr)
+        }
+    }
+
+    fn parse_sum(&mut self) -> io::Result<Expr> {
+        let mut expr = self.parse_operand()?;
+        loop {
+            match self.peek() {
+                Some(Token::Plus) => {
+                    self.next();
+                    expr = Expr::Add(Box::new(expr), Box::new(self.parse_operand()?));
+                },
+                Some(Token::Minus) => {
+                    self.next();
+                    expr = Expr::Sub(Box::new(expr), Box::new(self.parse_operand()?));
+                },
+                _ => return Ok(expr)
+            }
+        }
+    }
+
+    fn parse_operand(&mut self) -> io::Result<Expr> {
+        match self.next() {
+            Some(Token::Num(n)) => Ok(Expr::Num(n)),
+            Some(Token::Str(s)) => Ok(Expr::Str(s)),
+            Some(Token::Minus) => Ok(Expr::Sub(Box::new(Expr::Num(0.0)), Box::new(self.parse_operand()?))),
+            Some(Token::LParen) => {
+                let expr = self.parse_or()?;
+                self.expect(Token::RParen)?;
+                Ok(expr)
+            },
+            Some(Token::Ident(name)) => match name.as_str() {
+                "true" => Ok(Expr::Bool(true)),
+                "false" => Ok(Expr::Bool(false)),
+                "abs" => {
+                    self.expect(Token::LParen)?;
+                    let expr = self.parse_sum()?;
+                    self.expect(Token::RParen)?;
+                    Ok(Expr::Abs(Box::new(expr)))
+                },
+                _ => self.parse_column(name.as_str())
+            },
+            token => Err(invalid_expr(format!("unexpected {:?}", token)))
+        }
+    }
+
+    fn parse_column(&self, name: &str) -> io::Result<Expr> {
+        let column = match name {
+            "chrom1" => "chr1",
+            "chrom2" => "chr2",
+            _ => name
+        };
+        self.header.column_index(column)
+            .map(Expr::Column)
+            .ok_or_else(|| invalid_expr(format!("unknown column {}", name)))
+    }
+
+    fn parse_list(&mut self) -> io::Result<HashSet<String>> {
+        match self.next() {
+            Some(Token::LBracket) => {
+                let mut values = HashSet::new();
+                loop {
+                    match self.next() {
+                        Some(Token::Str(s)) => { values.insert(s); },
+                        Some(Token::Num(n)) => { values.insert(n.to_string()); },
+                        Some(Token::Ident(s)) => { values.insert(s); },
+                        Some(Token::RBracket) if values.is_empty() => return Ok(values),
+                        token => return Err(invalid_expr(format!("unexpected {:?} in list", token)))
+                    }
+                    match self.next() {
+                        Some(Token::Comma) => continue,
+                        Some(Token::RBracket) => return Ok(values),
+                        token => return Err(invalid_expr(format!("unexpected {:?} in list", token)))
+                    }
+                }
+            },
+            Some(Token::Ident(ref f)) if f == "file" => {
+                self.expect(Token::LParen)?;
+                let path = match self.next() {
+                    Some(Token::Str(path)) => path,
+                    token => return Err(invalid_expr(format!("expected path to file, found {:?}", token)))
+                };
+                self.expect(Token::RParen)?;
+                let text = fs::read_to_string(path.as_str())?;
+                Ok(text.lines().map(|l| l.trim()).filter(|l| !l.is_empty()).map(|l| l.to_string()).collect())
+            },
+            token => Err(invalid_expr(format!("expected list, found {:?}", token)))
+        }
+    }
+}
+
+fn invalid_expr(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("Incorrect select expression: {}", msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RECORD: [&str; 10] = ["r1", "ctg10", "9", "ctg9", "10", "+", "-", "UU", "30", "0"];
+
+    fn select(expr: &str) -> io::Result<bool> {
+        let columns = "#columns: readID chr1 pos1 chr2 pos2 strand1 strand2 pair_type mapq1 mapq2";
+        let header = PairsHeader::from_lines(vec![columns].into_iter())?;
+        Selector::new(expr, &header)?.is_selected(&RECORD)
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert!(select("true or false and false").unwrap());
+        assert!(select("false and false or true").unwrap());
+        assert!(!select("not true and false").unwrap());
+        assert!(select("not false or false").unwrap());
+        assert!(!select("not strand1 == '+'").unwrap());
+        assert!(select("pos2 - pos1 + 1 == 2").unwrap());
+        assert!(select("mapq1 >= 30 && mapq2 < 30 || pair_type == 'RR'").unwrap());
+    }
+
+    #[test]
+    fn test_parentheses() {
+        assert!(!select("(true or false) and false").unwrap());
+        assert!(select("true or (false and false)").unwrap());
+        assert!(select("not (true and false)").unwrap());
+        assert!(select("abs(pos1 - pos2) == 1").unwrap());
+        assert!(select("pos2 - (pos1 + 1) == 0").unwrap());
+    }
+
+    #[test]
+    fn test_in_list() {
+        assert!(select("pair_type in ['UU', 'UR', 'RU']").unwrap());
+        assert!(!select("pair_type in ['RR']").unwrap());
+        assert!(select("mapq1 in [30, 60]").unwrap());
+
+        let list_file = std::env::temp_dir().join(format!("hic_select_test_{}.txt", std::process::id()));
+        fs::write(&list_file, "ctg1\n\n ctg10 \n").unwrap();
+        let path = list_file.to_str().unwrap();
+        let tig1_selected = select(format!("chr1 in file('{}')", path).as_str());
+        let tig2_selected = select(format!("chrom2 in file(\"{}\")", path).as_str());
+        fs::remove_file(&list_file).unwrap();
+
+        assert!(tig1_selected.unwrap());
+        assert!(!tig2_selected.unwrap());
+        assert!(select("chr1 in file('no_such_file_of_contigs.txt')").is_err());
+    }
+
+    #[test]
+    fn test_comparison() {
+        // numbers are compared by value, other values and mixed ones as strings
+        assert!(select("pos1 < pos2").unwrap());
+        assert!(select("pos1 == 9.0").unwrap());
+        assert!(select("chr1 < chr2").unwrap());
+        assert!(select("chr1 != 'ctg9'").unwrap());
+        assert!(select("chr1 > 5").unwrap());
+        assert!(select("mapq2 <= -0").unwrap());
+    }
+
+    #[test]
+    fn test_malformed() {
+        for expr in ["", "chr1 ==", "(true", "true)", "chr1 == chr2 chr1", "pos1 @ 1", "chr1 == 'ctg1",
+                     "tig == 'ctg1'", "chr1 in ['a' 'b']", "chr1 in ctg1", "abs pos1", "pos1 + 1", "chr1 + 1 > 0",
+                     "1.2.3 > 0"].iter() {
+            let err = select(expr).err();
+            assert!(err.is_some(), "{} is accepted", expr);
+            assert_eq!(err.unwrap().kind(), io::ErrorKind::InvalidInput, "{}", expr);
+        }
+    }
+}
@@ -1,45 +1,613 @@
-use std::process::Command;
-use std::io::{self, Write};
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{self, AtomicUsize};
+use std::{mem, process, vec};
 
-use log::info;
+use log::{info, trace, warn};
+use rayon::prelude::*;
 
+use super::bgzf;
 use super::pair_record;
+use super::pairs_format::{self, PairsHeader};
 
+const FIELD_SEP: char = '\t';
+const COMMENT_SYMBOL: char = '#';
+// Rough per-line overhead of SortLine (strings headers, key, vector slot) used to respect memory budget.
+const LINE_OVERHEAD: usize = 96;
+// Number of sorters created by the process, it makes names of their temporary files unique.
+static N_SORTERS: AtomicUsize = AtomicUsize::new(0);
+
+// Sorts pairs file by tig1, tig2, pos1 and pos2 (the same key as `sort -k2,2 -k4,4 -k3,3n -k5,5n --stable`).
+// Lines are sorted in chunks that fit into memory budget, chunks are spilled into temporary directory
+// as sorted runs and then merged into output file. Comment lines are moved to the top of output file,
+// #sorted field is updated if the file has pairs header.
 pub fn sort_pairs(pairs_path: &str, output_path: &str, nproc: u8, memory: &str, tmpdir: Option<&str>) -> io::Result<()> {
     info!("Sorting pairs in file {}", pairs_path);
 
+    let mem_limit = parse_memory_size(memory)?;
+    let tmp_dir = tmpdir.map(PathBuf::from).unwrap_or_else(std::env::temp_dir);
+    trace!("Memory limit is {} bytes, {} threads are requested, temporary directory is {}",
+           mem_limit, nproc, tmp_dir.display());
+
     info!("Starting sorting....");
+    let mut sorter = ExternalSorter::update_threads(ExternalSorter::new(mem_limit, tmp_dir.as_path()), nproc as usize)?;
+    let reader = bgzf::open_reader(Path::new(pairs_path))?;
+    for line in reader.lines() {
+        sorter.push(line?)?;
+    }
+
+    let mut output = bgzf::create_writer(Path::new(output_path))?;
+    for line in sorter.into_sorted_lines()? {
+        writeln!(output, "{}", line?)?;
+    }
+    output.finish()?;
+
+    info!("Done with sorting pairs.");
+
+    Ok(())
+}
+
+// Merges pairs files which are already sorted by tig1, tig2, pos1 and pos2 (e.g. converted from different lanes)
+// into a single sorted file. Files must have the same contigs and columns, header of the first file is used.
+pub fn merge_sorted_pairs(in_files: &[&Path], out_file: &Path) -> io::Result<()> {
+    info!("Merging {} sorted pairs files", in_files.len());
 
-    let mut sort_c = Command::new("sort");
+    let mut header: Option<PairsHeader> = None;
+    let mut readers = Vec::with_capacity(in_files.len());
+    let mut heap = BinaryHeap::with_capacity(in_files.len());
+    for (run, path) in in_files.iter().enumerate() {
+        let mut reader = bgzf::open_reader(path)?.lines();
+        let mut comments = Vec::new();
+        let mut first_line = None;
+        for line in &mut reader {
+            let line = line?;
+            if line.starts_with(COMMENT_SYMBOL) {
+                comments.push(line);
+            } else if !line.is_empty() {
+                first_line = Some(line);
+                break;
+            }
+        }
 
-    sort_c.arg("-k")
-            .arg(format!("{0},{0}", pair_record::COL_TIG1 + 1))
-            .arg("-k")
-            .arg(format!("{0},{0}", pair_record::COL_TIG2 + 1))
-            .arg("-k")
-            .arg(format!("{0},{0}n", pair_record::COL_POS1 + 1))
-            .arg("-k")
-            .arg(format!("{0},{0}n", pair_record::COL_POS2 + 1))
-            .arg("--stable")
-            .arg("--field-separator=\t")
-            .arg(format!("--parallel={}", nproc));
+        let file_header = PairsHeader::from_lines(comments.iter().map(|l| l.as_str()))?;
+        if file_header.sorted.as_deref() != Some(pairs_format::SORTED_ORDER) {
+            warn!("File {} is not declared as sorted by {}, order is checked during merging.",
+                  path.display(), pairs_format::SORTED_ORDER);
+        }
+        match &header {
+            Some(h) => check_compatible_headers(h, &file_header, path)?,
+            None => header = Some(file_header),
+        }
 
-    if let Some(td) = tmpdir {
-        sort_c.arg(format!("--temporary-directory={}", td));
+        if let Some(line) = first_line {
+            heap.push(Reverse(MergeItem { line: SortLine::new(line)?, run }));
+        }
+        readers.push(reader);
     }
 
-    sort_c.arg("-S")
-            .arg(memory)
-            .arg("-o")
-            .arg(output_path)
-            .arg(pairs_path);
+    let mut header = header.unwrap_or_default();
+    header.sorted = Some(pairs_format::SORTED_ORDER.to_string());
 
-    let sort_c = sort_c.output()?;
-    io::stdout().write_all(&sort_c.stdout).unwrap();
-    io::stderr().write_all(&sort_c.stderr).unwrap();
-    info!("Command status: {}", sort_c.status);
+    let mut merger = RunsMerger { readers, heap, _runs: SortedRuns { paths: Vec::new() } };
+    let mut output = bgzf::create_writer(out_file)?;
+    header.write(&mut output)?;
+    while let Some(line) = merger.next_line() {
+        writeln!(output, "{}", line?)?;
+    }
+    output.finish()?;
 
-    info!("Done with sorting pairs.");
-    
+    info!("Merged pairs saved into {}", out_file.display());
+    Ok(())
+}
+
+fn check_compatible_headers(header: &PairsHeader, other: &PairsHeader, path: &Path) -> io::Result<()> {
+    if header.chromsizes != other.chromsizes {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  format!("Contigs of {} differ from contigs of other files.", path.display())));
+    }
+    if header.columns != other.columns {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  format!("Columns of {} differ from columns of other files.", path.display())));
+    }
     Ok(())
 }
+
+// Converts size in format of sort -S (e.g. 2G, 512M, 100K or number of bytes) into bytes.
+pub fn parse_memory_size(memory: &str) -> io::Result<usize> {
+    let memory = memory.trim();
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, format!("Incorrect memory size {}", memory));
+
+    let (digits, multiplier) = match memory.chars().last() {
+        Some('K') | Some('k') => (&memory[..memory.len() - 1], 1_usize << 10),
+        Some('M') | Some('m') => (&memory[..memory.len() - 1], 1_usize << 20),
+        Some('G') | Some('g') => (&memory[..memory.len() - 1], 1_usize << 30),
+        Some('T') | Some('t') => (&memory[..memory.len() - 1], 1_usize << 40),
+        Some('B') | Some('b') => (&memory[..memory.len() - 1], 1_usize),
+        Some(c) if c.is_ascii_digit() => (memory, 1_usize),
+        _ => return Err(invalid()),
+    };
+
+    let value: usize = digits.parse().map_err(|_| invalid())?;
+    value.checked_mul(multiplier).filter(|&v| v > 0).ok_or_else(invalid)
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct SortKey {
+    tig1: String,
+    tig2: String,
+    pos1: i64,
+    pos2: i64,
+}
+
+impl SortKey {
+    fn from_line(line: &str) -> io::Result<SortKey> {
+        let fields: Vec<&str> = line.split(FIELD_SEP).collect();
+        let max_col = pair_record::COL_TIG1.max(pair_record::COL_TIG2)
+            .max(pair_record::COL_POS1).max(pair_record::COL_POS2);
+
+        if fields.len() <= max_col {
+            return Err(invalid_line(line));
+        }
+
+        Ok(SortKey {
+            tig1: fields[pair_record::COL_TIG1].to_string(),
+            tig2: fields[pair_record::COL_TIG2].to_string(),
+            pos1: fields[pair_record::COL_POS1].parse().map_err(|_| invalid_line(line))?,
+            pos2: fields[pair_record::COL_POS2].parse().map_err(|_| invalid_line(line))?,
+        })
+    }
+}
+
+struct SortLine {
+    key: SortKey,
+    line: String
+}
+
+impl SortLine {
+    fn new(line: String) -> io::Result<SortLine> {
+        Ok(SortLine { key: SortKey::from_line(line.as_str())?, line })
+    }
+
+    fn mem_size(&self) -> usize {
+        self.line.len() + self.key.tig1.len() + self.key.tig2.len() + LINE_OVERHEAD
+    }
+}
+
+// Element of k-way merge. Ties are resolved by index of run, so merge is stable.
+struct MergeItem {
+    line: SortLine,
+    run: usize
+}
+
+impl PartialEq for MergeItem {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for MergeItem {}
+
+impl PartialOrd for MergeItem {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for MergeItem {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.line.key.cmp(&other.line.key).then(self.run.cmp(&other.run))
+    }
+}
+
+// Temporary files with sorted runs. Files are removed when runs are not needed anymore (runs which are
+// not temporary files, e.g. merged input files, are not registered).
+struct SortedRuns {
+    paths: Vec<PathBuf>
+}
+
+impl Drop for SortedRuns {
+    fn drop(&mut self) {
+        for run in self.paths.iter() {
+            if let Err(e) = fs::remove_file(run) {
+                trace!("Can not remove temporary file {}: {}", run.display(), e);
+            }
+        }
+    }
+}
+
+// Accumulates pairs records (either line by line or as a text stream via Write) and sorts them
+// within memory budget spilling sorted runs into temporary directory. Chunks are sorted on a thread pool
+// if more than one thread is requested.
+pub struct ExternalSorter {
+    mem_limit: usize,
+    tmp_dir: PathBuf,
+    tmp_prefix: String,
+    comments: Vec<String>,
+    chunk: Vec<SortLine>,
+    chunk_size: usize,
+    runs: SortedRuns,
+    pending: Vec<u8>,
+    pool: Option<rayon::ThreadPool>,
+}
+
+impl ExternalSorter {
+    pub fn new(mem_limit: usize, tmp_dir: &Path) -> ExternalSorter {
+        ExternalSorter {
+            mem_limit,
+            tmp_dir: PathBuf::from(tmp_dir),
+            tmp_prefix: format!("hic_pairs_sort.{}.{}", process::id(), N_SORTERS.fetch_add(1, atomic::Ordering::Relaxed)),
+            comments: Vec::new(),
+            chunk: Vec::new(),
+            chunk_size: 0,
+            runs: SortedRuns { paths: Vec::new() },
+            pending: Vec::new(),
+            pool: None,
+        }
+    }
+
+    pub fn update_threads(mut sorter: ExternalSorter, threads: usize) -> io::Result<ExternalSorter> {
+        sorter.pool = match threads {
+            0 | 1 => None,
+            _ => Some(rayon::ThreadPoolBuilder::new().num_threads(threads).build()
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?)
+        };
+        Ok(sorter)
+    }
+
+    pub fn push(&mut self, line: String) -> io::Result<()> {
+        if line.is_empty() { return Ok(()); }
+
+        if line.starts_with(COMMENT_SYMBOL) {
+            self.comments.push(line);
+            return Ok(());
+        }
+
+        let line = SortLine::new(line)?;
+        self.chunk_size += line.mem_size();
+        self.chunk.push(line);
+
+        if self.chunk_size >= self.mem_limit {
+            self.spill_chunk()?;
+        }
+        Ok(())
+    }
+
+    pub fn into_sorted_lines(mut self) -> io::Result<SortedLines> {
+        if !self.pending.is_empty() {
+            let line = bytes_to_line(mem::take(&mut self.pending))?;
+            self.push(line)?;
+        }
+
+        if self.comments.first().map_or(false, |l| l.starts_with(pairs_format::FORMAT_LINE)) {
+            let mut header = PairsHeader::from_lines(self.comments.iter().map(|l| l.as_str()))?;
+            header.sorted = Some(pairs_format::SORTED_ORDER.to_string());
+            self.comments = header.to_lines();
+        }
+
+        if self.runs.paths.is_empty() {
+            trace!("All pairs fit into memory, sorting without temporary files.");
+            self.sort_chunk();
+            return Ok(SortedLines {
+                comments: self.comments.into_iter(),
+                source: LineSource::Memory(self.chunk.into_iter())
+            });
+        }
+
+        self.spill_chunk()?;
+        info!("Merging {} sorted runs", self.runs.paths.len());
+
+        let mut readers = Vec::with_capacity(self.runs.paths.len());
+        for run in self.runs.paths.iter() {
+            let reader: Box<dyn BufRead> = Box::new(BufReader::new(File::open(run)?));
+            readers.push(reader.lines());
+        }
+
+        let mut heap = BinaryHeap::with_capacity(readers.len());
+        for (run, reader) in readers.iter_mut().enumerate() {
+            if let Some(line) = reader.next() {
+                heap.push(Reverse(MergeItem { line: SortLine::new(line?)?, run }));
+            }
+        }
+
+        Ok(SortedLines {
+            comments: self.comments.into_iter(),
+            source: LineSource::Runs(RunsMerger { readers, heap, _runs: self.runs })
+        })
+    }
+
+    // Both sorts are stable, so pairs with equal keys keep the order of input.
+    fn sort_chunk(&mut self) {
+        let chunk = &mut self.chunk;
+        match &self.pool {
+            Some(pool) => pool.install(|| chunk.par_sort_by(|a, b| a.key.cmp(&b.key))),
+            None => chunk.sort_by(|a, b| a.key.cmp(&b.key))
+        }
+    }
+
+    fn spill_chunk(&mut self) -> io::Result<()> {
+        if self.chunk.is_empty() { return Ok(()); }
+
+        let run_path = self.tmp_dir.join(format!("{}.{}.tmp", self.tmp_prefix, self.runs.paths.len()));
+        trace!("Spilling {} sorted pairs into {}", self.chunk.len(), run_path.display());
+        self.sort_chunk();
+
+        // run is registered before writing, so it is removed even if writing fails
+        self.runs.paths.push(run_path.clone());
+        let mut run = BufWriter::new(File::create(run_path)?);
+        for rec in self.chunk.drain(..) {
+            writeln!(run, "{}", rec.line)?;
+        }
+        run.flush()?;
+
+        self.chunk_size = 0;
+        Ok(())
+    }
+}
+
+impl Write for ExternalSorter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &b in buf {
+            if b == b'\n' {
+                let line = bytes_to_line(mem::take(&mut self.pending))?;
+                self.push(line)?;
+            } else {
+                self.pending.push(b);
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Sorted pairs records. Comment lines come first.
+pub struct SortedLines {
+    comments: vec::IntoIter<String>,
+    source: LineSource
+}
+
+enum LineSource {
+    Memory(vec::IntoIter<SortLine>),
+    Runs(RunsMerger)
+}
+
+struct RunsMerger {
+    readers: Vec<io::Lines<Box<dyn BufRead>>>,
+    heap: BinaryHeap<Reverse<MergeItem>>,
+    _runs: SortedRuns
+}
+
+impl RunsMerger {
+    // Lines of each run must be sorted, otherwise merged output would not be sorted.
+    fn next_line(&mut self) -> Option<io::Result<String>> {
+        let Reverse(item) = self.heap.pop()?;
+        if let Some(line) = self.readers[item.run].next() {
+            match line.and_then(SortLine::new) {
+                Ok(line) if line.key < item.line.key => return Some(Err(unsorted_error(line.line.as_str()))),
+                Ok(line) => self.heap.push(Reverse(MergeItem { line, run: item.run })),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        Some(Ok(item.line.line))
+    }
+}
+
+impl Iterator for SortedLines {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(line) = self.comments.next() {
+            return Some(Ok(line));
+        }
+
+        match &mut self.source {
+            LineSource::Memory(lines) => lines.next().map(|rec| Ok(rec.line)),
+            LineSource::Runs(merger) => merger.next_line(),
+        }
+    }
+}
+
+fn bytes_to_line(bytes: Vec<u8>) -> io::Result<String> {
+    String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn unsorted_error(line: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Pairs are not sorted at record: {}", line))
+}
+
+fn invalid_line(line: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Incorrect pairs record: {}", line))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: [&str; 4] = ["## pairs format v1.0", "#chromsize: ctg1 100000", "#chromsize: ctg2 50000",
+                               "#columns: readID chr1 pos1 chr2 pos2 strand1 strand2"];
+
+    fn tmp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hic_convertor_sort_{}_{}", name, process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn n_tmp_files(dir: &Path) -> usize {
+        fs::read_dir(dir).unwrap().count()
+    }
+
+    // Pairs in shuffled order, many of them share keys. Read names are unique, so order of ties is checked.
+    fn shuffled_pairs(n: usize) -> Vec<String> {
+        (0..n).map(|i| {
+            let j = (i * 7919) % n;
+            let (tig1, tig2) = [("ctg1", "ctg2"), ("ctg2", "ctg2"), ("ctg1", "ctg1")][j % 3];
+            format!("r{}\t{}\t{}\t{}\t{}\t+\t-", i, tig1, (j % 50) * 10, tig2, (j % 7) * 100)
+        }).collect()
+    }
+
+    // Expected order is the order of stable sort by key.
+    fn stable_sorted(lines: &[String]) -> Vec<String> {
+        let mut lines: Vec<SortLine> = lines.iter().map(|l| SortLine::new(l.clone()).unwrap()).collect();
+        lines.sort_by(|a, b| a.key.cmp(&b.key));
+        lines.into_iter().map(|l| l.line).collect()
+    }
+
+    fn sort_lines(mut sorter: ExternalSorter, lines: &[String]) -> (usize, Vec<String>) {
+        for line in HEADER.iter() {
+            sorter.push(line.to_string()).unwrap();
+        }
+        for line in lines {
+            sorter.push(line.clone()).unwrap();
+        }
+        let n_runs = sorter.runs.paths.len();
+        (n_runs, sorter.into_sorted_lines().unwrap().map(|l| l.unwrap()).collect())
+    }
+
+    #[test]
+    fn test_sort_in_memory() {
+        let dir = tmp_dir("memory");
+        let lines = shuffled_pairs(300);
+        let (n_runs, sorted) = sort_lines(ExternalSorter::new(1 << 20, &dir), &lines);
+        assert_eq!(n_runs, 0);
+        assert_eq!(sorted[..5], ["## pairs format v1.0", "#sorted: chr1-chr2-pos1-pos2", "#chromsize: ctg1 100000",
+                                 "#chromsize: ctg2 50000", "#columns: readID chr1 pos1 chr2 pos2 strand1 strand2"]);
+        assert_eq!(sorted[5..], stable_sorted(&lines)[..]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_spill_and_merge() {
+        let dir = tmp_dir("spill");
+        let lines = shuffled_pairs(3000);
+        let expected = stable_sorted(&lines);
+        for &threads in [1, 3].iter() {
+            let sorter = ExternalSorter::update_threads(ExternalSorter::new(8 << 10, &dir), threads).unwrap();
+            let (n_runs, sorted) = sort_lines(sorter, &lines);
+            assert!(n_runs > 10);
+            assert_eq!(sorted.len(), HEADER.len() + 1 + lines.len());
+            assert_eq!(sorted[HEADER.len() + 1..], expected[..]);
+            assert_eq!(n_tmp_files(&dir), 0);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // Records come as a text stream split at arbitrary bytes, the last line has no line break.
+    #[test]
+    fn test_write_stream() {
+        let dir = tmp_dir("stream");
+        let lines = shuffled_pairs(500);
+        let text = format!("{}\n{}", HEADER.join("\n"), lines.join("\n"));
+        let mut sorter = ExternalSorter::new(4 << 10, &dir);
+        for chunk in text.as_bytes().chunks(37) {
+            sorter.write_all(chunk).unwrap();
+        }
+        let sorted: Vec<String> = sorter.into_sorted_lines().unwrap().map(|l| l.unwrap()).collect();
+        assert_eq!(sorted[HEADER.len() + 1..], stable_sorted(&lines)[..]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_unique_tmp_files() {
+        let dir = tmp_dir("unique");
+        let lines = shuffled_pairs(1000);
+        let mut sorters = vec![ExternalSorter::new(8 << 10, &dir), ExternalSorter::new(8 << 10, &dir)];
+        for sorter in sorters.iter_mut() {
+            for line in lines.iter() {
+                sorter.push(line.clone()).unwrap();
+            }
+        }
+        let n_runs: usize = sorters.iter().map(|s| s.runs.paths.len()).sum();
+        assert_eq!(n_tmp_files(&dir), n_runs);
+
+        let expected = stable_sorted(&lines);
+        for sorter in sorters {
+            let sorted: Vec<String> = sorter.into_sorted_lines().unwrap().map(|l| l.unwrap()).collect();
+            assert_eq!(sorted, expected);
+        }
+        assert_eq!(n_tmp_files(&dir), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn write_pairs(path: &Path, header: &[&str], lines: &[String]) {
+        let mut writer = bgzf::create_writer(path).unwrap();
+        for line in header.iter() {
+            writeln!(writer, "{}", line).unwrap();
+        }
+        for line in lines {
+            writeln!(writer, "{}", line).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    fn merge_files(dir: &Path, files: &[(&[&str], Vec<String>)]) -> io::Result<Vec<String>> {
+        let paths: Vec<PathBuf> = (0..files.len()).map(|i| dir.join(format!("in{}.pairs.gz", i))).collect();
+        for (path, (header, lines)) in paths.iter().zip(files.iter()) {
+            write_pairs(path, header, lines);
+        }
+        let out_file = dir.join("merged.pairs.gz");
+        let in_files: Vec<&Path> = paths.iter().map(|p| p.as_path()).collect();
+        merge_sorted_pairs(&in_files, &out_file)?;
+        Ok(bgzf::open_reader(&out_file)?.lines().map(|l| l.unwrap()).collect())
+    }
+
+    // Pairs of a lane are sorted, lanes share keys, so ties are resolved by order of files.
+    #[test]
+    fn test_merge_sorted_pairs() {
+        let dir = tmp_dir("merge");
+        let pairs = shuffled_pairs(900);
+        let lanes: Vec<Vec<String>> = (0..3).map(|k| stable_sorted(&pairs[(k * 300)..((k + 1) * 300)])).collect();
+        let mut header = HEADER.to_vec();
+        header.insert(1, "#sorted: chr1-chr2-pos1-pos2");
+        header.insert(2, "#samheader: @PG ID:lane0");
+        let other_header = ["## pairs format v1.0", "#chromsize: ctg1 100000", "#chromsize: ctg2 50000",
+                            "#samheader: @PG ID:lane1", "#columns: readID chr1 pos1 chr2 pos2 strand1 strand2"];
+
+        let merged = merge_files(&dir, &[(&header, lanes[0].clone()), (&other_header, lanes[1].clone()),
+                                         (&other_header, lanes[2].clone())]).unwrap();
+        // header of the first file is kept
+        assert_eq!(merged[..6], ["## pairs format v1.0", "#sorted: chr1-chr2-pos1-pos2", "#chromsize: ctg1 100000",
+                                 "#chromsize: ctg2 50000", "#samheader: @PG ID:lane0",
+                                 "#columns: readID chr1 pos1 chr2 pos2 strand1 strand2"]);
+        let concatenated: Vec<String> = lanes.concat();
+        assert_eq!(merged[6..], stable_sorted(&concatenated)[..]);
+
+        // sorted field is set even if the first file has no one
+        let merged = merge_files(&dir, &[(&other_header, lanes[0].clone()), (&header, Vec::new())]).unwrap();
+        assert_eq!(merged[1], "#sorted: chr1-chr2-pos1-pos2");
+        assert_eq!(merged[6..], lanes[0][..]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_merge_incompatible_pairs() {
+        let dir = tmp_dir("incompatible");
+        let lines = stable_sorted(&shuffled_pairs(100));
+        let contigs = ["## pairs format v1.0", "#chromsize: ctg1 100000", "#chromsize: ctg3 50000",
+                       "#columns: readID chr1 pos1 chr2 pos2 strand1 strand2"];
+        let columns = ["## pairs format v1.0", "#chromsize: ctg1 100000", "#chromsize: ctg2 50000",
+                       "#columns: readID chr1 pos1 chr2 pos2 strand1 strand2 mapq1 mapq2"];
+        assert!(merge_files(&dir, &[(&HEADER, lines.clone()), (&contigs, lines.clone())]).is_err());
+        assert!(merge_files(&dir, &[(&HEADER, lines.clone()), (&columns, lines.clone())]).is_err());
+
+        // unsorted file is detected during merging
+        let mut unsorted = lines.clone();
+        unsorted.swap(10, 90);
+        assert!(merge_files(&dir, &[(&HEADER, lines), (&HEADER, unsorted)]).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_incorrect_record() {
+        let dir = tmp_dir("incorrect");
+        let mut sorter = ExternalSorter::new(1 << 20, &dir);
+        assert!(sorter.push("r1\tctg1\t10\tctg2".to_string()).is_err());
+        assert!(sorter.push("r1\tctg1\tten\tctg2\t20\t+\t-".to_string()).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
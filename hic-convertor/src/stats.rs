@@ -0,0 +1,618 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use super::pair_record::{PairRecord, PairType, RescuePath};
+use super::digest::FragmentStat;
+use super::walks::{WalkPolicy, WalkStat};
+use super::config::ConverterConfig;
+use super::dedup::DedupStat;
+
+const KEY_SEP: char = '/';
+const CONFIG_KEY_PREFIX: &str = "#config/";
+const VALUE_SEP: char = '\t';
+
+// Thresholds of pairtools for cis contacts (cis_1kb+ etc.).
+const CIS_THRESHOLDS: [u64; 6] = [1000, 2000, 4000, 10000, 20000, 40000];
+
+// Statistics are written in JSON if file has .json extension, otherwise in pairtools-compatible TSV.
+#[derive(Clone, Copy, PartialEq)]
+pub enum StatsFormat {
+    Json,
+    Tsv
+}
+
+impl StatsFormat {
+    pub fn from_path(path: &Path) -> StatsFormat {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => StatsFormat::Json,
+            _ => StatsFormat::Tsv
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ConverterStat {
+    pub read_counter: u64,  // total number of reads
+    pub alignment_counter: u64,  // total number of alignment records
+    pub pairs_counter: u64, // total amount of pairs
+
+    // n = non-mapped, m = mapped, each letter is for a read in pair
+    pub nn_counter: u64,
+    pub nm_counter: u64,
+    pub mm_counter: u64,
+
+    // supp = supplementary, sec = secondary, prime = prime
+    pub prime_counter: u64,
+    pub supp_counter: u64,
+    pub sec_counter: u64,
+
+    // mapq = 0 is interesting since it may have perfect alignments but with XA tag
+    pub mq00_counter: u64,
+    pub mq01_counter: u64,
+    pub mq11_counter: u64,
+
+    // cis or trans hic pairs
+    pub intra_counter: u64,
+    pub inter_counter: u64,
+
+    // cis pairs with separation at least 1kb, 2kb, 4kb, 10kb, 20kb and 40kb
+    pub cis_dist_counters: Vec<u64>,
+
+    // cis pairs by log-binned separation (bins are the same as in pairtools)
+    pub dist_freq: Vec<u64>,
+    #[serde(skip, default = "get_dist_bins")]
+    dist_bins: Vec<u64>,
+
+    // pairs for each pair of contigs
+    pub chrom_freq: BTreeMap<String, u64>,
+
+    // how hic pairs were obtained from alignments
+    pub uu_pair_counter: u64,
+    pub uw_pair_counter: u64,
+    pub ww_pair_counter: u64,
+    pub walk_pair_counter: u64,
+
+    // alignments with zero mapq rescued via overlaps in assembly graph and positions suggested for them
+    pub mq0_rescued_counter: u64,
+    pub mq0_alt_pos_counter: u64,
+
+    // alignments with alternative hits in XA tag and total number of their hits
+    pub xa_multimap_counter: u64,
+    pub xa_hits_counter: u64,
+
+    // pairtools pair types of all read pairs (including filtered ones)
+    pub pair_type_counter: BTreeMap<String, u64>,
+
+    // same-fragment, adjacent-fragment and distal contacts w.r.t. restriction fragments
+    pub fragment_stats: FragmentStat,
+
+    // lengths of walks and pairs reported from them w.r.t. walk policy
+    pub walk_stats: WalkStat,
+
+    // skipped records of single-end reads and read pairs where one of mates has no alignments
+    pub unpaired_counter: u64,
+    pub singleton_counter: u64,
+
+    // duplicated and unique pairs (filled only if pairs were deduplicated), duplicates are either PCR or optical
+    pub dups_counter: u64,
+    pub nodups_counter: u64,
+    #[serde(default)]
+    pub pcr_dups_counter: u64,
+    #[serde(default)]
+    pub optical_dups_counter: u64,
+
+    // parameters of conversion
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config: Option<ConverterConfig>,
+}
+
+impl ConverterStat {
+    pub fn new() -> ConverterStat {
+        ConverterStat {
+            read_counter: 0,
+            alignment_counter: 0,
+            pairs_counter: 0,
+            nn_counter: 0,
+            nm_counter: 0,
+            mm_counter: 0,
+            prime_counter: 0,
+            supp_counter: 0,
+            sec_counter: 0,
+            mq00_counter: 0,
+            mq01_counter: 0,
+            mq11_counter: 0,
+            intra_counter: 0,
+            inter_counter: 0,
+            cis_dist_counters: vec![0; CIS_THRESHOLDS.len()],
+            dist_freq: vec![0; get_dist_bins().len()],
+            dist_bins: get_dist_bins(),
+            chrom_freq: BTreeMap::new(),
+            uu_pair_counter: 0,
+            uw_pair_counter: 0,
+            ww_pair_counter: 0,
+            walk_pair_counter: 0,
+            mq0_rescued_counter: 0,
+            mq0_alt_pos_counter: 0,
+            xa_multimap_counter: 0,
+            xa_hits_counter: 0,
+            pair_type_counter: BTreeMap::new(),
+            fragment_stats: FragmentStat::new(),
+            walk_stats: WalkStat::new(WalkPolicy::Outer),
+            unpaired_counter: 0,
+            singleton_counter: 0,
+            dups_counter: 0,
+            nodups_counter: 0,
+            pcr_dups_counter: 0,
+            optical_dups_counter: 0,
+            config: None,
+        }
+    }
+
+    pub fn update_align_count(&mut self) {
+        self.alignment_counter += 1;
+    }
+
+    pub fn update_read_count(&mut self) {
+        self.read_counter += 1
+    }
+
+    pub fn update_pair_count(&mut self, path: RescuePath, count: u64) {
+        match path {
+            RescuePath::Linear => { self.uu_pair_counter += count; },
+            RescuePath::Simple => { self.uw_pair_counter += count; }
+            RescuePath::Complex => { self.ww_pair_counter += count; }
+            RescuePath::Walk => { self.walk_pair_counter += count; }
+            RescuePath::None => {}
+        }
+    }
+
+    pub fn update_pair_type_count(&mut self, tp: PairType) {
+        *self.pair_type_counter.entry(tp.to_string()).or_insert(0) += 1;
+    }
+
+    pub fn update_walk_count(&mut self, n_segments: usize, n_pairs: usize) {
+        self.walk_stats.update(n_segments, n_pairs);
+    }
+
+    pub fn update_fragment_count(&mut self, rec: &PairRecord) {
+        self.fragment_stats.update(rec.name1 == rec.name2, rec.frag1, rec.frag2);
+    }
+
+    pub fn update_rescue_count(&mut self, n_positions: u64) {
+        self.mq0_rescued_counter += 1;
+        self.mq0_alt_pos_counter += n_positions;
+    }
+
+    pub fn update_multimap_count(&mut self, n_hits: u64) {
+        self.xa_multimap_counter += 1;
+        self.xa_hits_counter += n_hits;
+    }
+
+    pub fn update_unpaired_count(&mut self) {
+        self.unpaired_counter += 1;
+    }
+
+    pub fn update_singleton_count(&mut self) {
+        self.singleton_counter += 1;
+    }
+
+    pub fn update_dedup_count(&mut self, dedup_stats: &DedupStat) {
+        self.dups_counter += dedup_stats.duplicates;
+        self.nodups_counter += dedup_stats.unique;
+        self.pcr_dups_counter += dedup_stats.pcr_duplicates;
+        self.optical_dups_counter += dedup_stats.optical_duplicates;
+    }
+
+    pub fn update_alignment_count(&mut self, records: &[bam::Record]) {
+        for rec in records {
+            if rec.flag().is_secondary() {
+                self.sec_counter += 1;
+            } else if rec.flag().is_supplementary() {
+                self.supp_counter += 1;
+            } else {
+                self.prime_counter += 1;
+            }
+        }
+    }
+
+    pub fn update_mapping_count(&mut self, rec1: &bam::Record, rec2: &bam::Record) {
+        if !rec1.flag().is_mapped() && !rec2.flag().is_mapped() {
+            self.nn_counter += 1;
+        } else if rec1.flag().is_mapped() != rec2.flag().is_mapped() {
+            self.nm_counter += 1;
+        } else {
+            self.mm_counter += 1;
+        }
+    }
+
+    pub fn update_mapq_count(&mut self, rec1: &bam::Record, rec2: &bam::Record) {
+        if rec1.mapq() == 0 && rec2.mapq() == 0 { // Both of alignments are not unique
+            self.mq00_counter += 1;
+        } else if (rec1.mapq() == 0) != (rec2.mapq() == 0) { // One of alignments unique, another one is not
+            self.mq01_counter += 1;
+        } else {
+            self.mq11_counter += 1;
+        }
+    }
+
+    pub fn update_cis_trans_count(&mut self, recs: &[PairRecord]) {
+        self.pairs_counter += recs.len() as u64;
+        for rec in recs {
+            *self.chrom_freq.entry(format!("{}{}{}", rec.name1, KEY_SEP, rec.name2)).or_insert(0) += 1;
+
+            if rec.name1 == rec.name2 {
+                self.intra_counter += 1;
+                let dist = (rec.pos2 - rec.pos1).abs() as u64;
+                for (counter, &thr) in self.cis_dist_counters.iter_mut().zip(CIS_THRESHOLDS.iter()) {
+                    if dist >= thr { *counter += 1; }
+                }
+                let bin = match self.dist_bins.binary_search(&dist) {
+                    Ok(i) => i,
+                    Err(i) => i - 1
+                };
+                self.dist_freq[bin] += 1;
+            } else {
+                self.inter_counter += 1
+            }
+        }
+    }
+
+    // Sums statistics of sharded conversions.
+    pub fn merge(&mut self, other: &ConverterStat) {
+        self.read_counter += other.read_counter;
+        self.alignment_counter += other.alignment_counter;
+        self.pairs_counter += other.pairs_counter;
+        self.nn_counter += other.nn_counter;
+        self.nm_counter += other.nm_counter;
+        self.mm_counter += other.mm_counter;
+        self.prime_counter += other.prime_counter;
+        self.supp_counter += other.supp_counter;
+        self.sec_counter += other.sec_counter;
+        self.mq00_counter += other.mq00_counter;
+        self.mq01_counter += other.mq01_counter;
+        self.mq11_counter += other.mq11_counter;
+        self.intra_counter += other.intra_counter;
+        self.inter_counter += other.inter_counter;
+        add_counters(&mut self.cis_dist_counters, &other.cis_dist_counters);
+        add_counters(&mut self.dist_freq, &other.dist_freq);
+        add_map_counters(&mut self.chrom_freq, &other.chrom_freq);
+        self.uu_pair_counter += other.uu_pair_counter;
+        self.uw_pair_counter += other.uw_pair_counter;
+        self.ww_pair_counter += other.ww_pair_counter;
+        self.walk_pair_counter += other.walk_pair_counter;
+        self.mq0_rescued_counter += other.mq0_rescued_counter;
+        self.mq0_alt_pos_counter += other.mq0_alt_pos_counter;
+        self.xa_multimap_counter += other.xa_multimap_counter;
+        self.xa_hits_counter += other.xa_hits_counter;
+        add_map_counters(&mut self.pair_type_counter, &other.pair_type_counter);
+        self.fragment_stats.merge(&other.fragment_stats);
+        self.walk_stats.merge(&other.walk_stats);
+        self.unpaired_counter += other.unpaired_counter;
+        self.singleton_counter += other.singleton_counter;
+        self.dups_counter += other.dups_counter;
+        self.nodups_counter += other.nodups_counter;
+        self.pcr_dups_counter += other.pcr_dups_counter;
+        self.optical_dups_counter += other.optical_dups_counter;
+        if self.config.is_none() {
+            self.config = other.config.clone();
+        }
+    }
+
+    // Flat list of statistics with pairtools-like keys.
+    pub fn to_key_values(&self) -> Vec<(String, u64)> {
+        let mut values = vec![
+            ("total".to_string(), self.read_counter),
+            ("total_alignments".to_string(), self.alignment_counter),
+            ("total_unmapped".to_string(), self.nn_counter),
+            ("total_single_sided_mapped".to_string(), self.nm_counter),
+            ("total_mapped".to_string(), self.mm_counter),
+            ("total_pairs".to_string(), self.pairs_counter),
+            ("total_dups".to_string(), self.dups_counter),
+            ("total_nodups".to_string(), self.nodups_counter),
+            ("cis".to_string(), self.intra_counter),
+            ("trans".to_string(), self.inter_counter),
+        ];
+
+        for (thr, count) in CIS_THRESHOLDS.iter().zip(self.cis_dist_counters.iter()) {
+            values.push((format!("cis_{}kb+", thr / 1000), *count));
+        }
+
+        values.push(("skipped/unpaired_records".to_string(), self.unpaired_counter));
+        values.push(("skipped/singletons".to_string(), self.singleton_counter));
+
+        values.push(("dups/pcr".to_string(), self.pcr_dups_counter));
+        values.push(("dups/optical".to_string(), self.optical_dups_counter));
+
+        values.push(("alignments/primary".to_string(), self.prime_counter));
+        values.push(("alignments/supplementary".to_string(), self.supp_counter));
+        values.push(("alignments/secondary".to_string(), self.sec_counter));
+
+        values.push(("mapq/both_zero".to_string(), self.mq00_counter));
+        values.push(("mapq/one_zero".to_string(), self.mq01_counter));
+        values.push(("mapq/both_nonzero".to_string(), self.mq11_counter));
+
+        values.push(("rescue/linear".to_string(), self.uu_pair_counter));
+        values.push(("rescue/simple".to_string(), self.uw_pair_counter));
+        values.push(("rescue/complex".to_string(), self.ww_pair_counter));
+        values.push(("rescue/walk".to_string(), self.walk_pair_counter));
+
+        values.push(("mq0_rescue/alignments".to_string(), self.mq0_rescued_counter));
+        values.push(("mq0_rescue/positions".to_string(), self.mq0_alt_pos_counter));
+
+        values.push(("multimap/alignments".to_string(), self.xa_multimap_counter));
+        values.push(("multimap/hits".to_string(), self.xa_hits_counter));
+
+        values.push(("rfrag/same".to_string(), self.fragment_stats.same_counter));
+        values.push(("rfrag/adjacent".to_string(), self.fragment_stats.adjacent_counter));
+        values.push(("rfrag/distal".to_string(), self.fragment_stats.distal_counter));
+        values.push(("rfrag/unannotated".to_string(), self.fragment_stats.unannotated_counter));
+
+        for (n_segments, count) in self.walk_stats.segments_counter.iter() {
+            values.push((format!("walks/segments/{}", n_segments), *count));
+        }
+        values.push(("walks/pairs".to_string(), self.walk_stats.pairs_counter));
+        values.push(("walks/unresolved".to_string(), self.walk_stats.unresolved_counter));
+
+        for (tp, count) in self.pair_type_counter.iter() {
+            values.push((format!("pair_types/{}", tp), *count));
+        }
+        for (tigs, count) in self.chrom_freq.iter() {
+            values.push((format!("chrom_freq/{}", tigs), *count));
+        }
+
+        let bins = &self.dist_bins;
+        for (i, count) in self.dist_freq.iter().enumerate() {
+            let key = match bins.get(i + 1) {
+                Some(end) => format!("dist_freq/{}-{}", bins[i], end),
+                None => format!("dist_freq/{}+", bins[i]),
+            };
+            values.push((key, *count));
+        }
+
+        values
+    }
+
+    // Unknown keys are ignored.
+    pub fn from_key_values(values: impl Iterator<Item = (String, u64)>) -> ConverterStat {
+        let mut stats = ConverterStat::new();
+        let bins = get_dist_bins();
+
+        for (key, value) in values {
+            match key.as_str() {
+                "total" => stats.read_counter = value,
+                "total_alignments" => stats.alignment_counter = value,
+                "total_unmapped" => stats.nn_counter = value,
+                "total_single_sided_mapped" => stats.nm_counter = value,
+                "total_mapped" => stats.mm_counter = value,
+                "total_pairs" => stats.pairs_counter = value,
+                "total_dups" => stats.dups_counter = value,
+                "total_nodups" => stats.nodups_counter = value,
+                "cis" => stats.intra_counter = value,
+                "trans" => stats.inter_counter = value,
+                "skipped/unpaired_records" => stats.unpaired_counter = value,
+                "skipped/singletons" => stats.singleton_counter = value,
+                "dups/pcr" => stats.pcr_dups_counter = value,
+                "dups/optical" => stats.optical_dups_counter = value,
+                "alignments/primary" => stats.prime_counter = value,
+                "alignments/supplementary" => stats.supp_counter = value,
+                "alignments/secondary" => stats.sec_counter = value,
+                "mapq/both_zero" => stats.mq00_counter = value,
+                "mapq/one_zero" => stats.mq01_counter = value,
+                "mapq/both_nonzero" => stats.mq11_counter = value,
+                "rescue/linear" => stats.uu_pair_counter = value,
+                "rescue/simple" => stats.uw_pair_counter = value,
+                "rescue/complex" => stats.ww_pair_counter = value,
+                "rescue/walk" => stats.walk_pair_counter = value,
+                "mq0_rescue/alignments" => stats.mq0_rescued_counter = value,
+                "mq0_rescue/positions" => stats.mq0_alt_pos_counter = value,
+                "multimap/alignments" => stats.xa_multimap_counter = value,
+                "multimap/hits" => stats.xa_hits_counter = value,
+                "rfrag/same" => stats.fragment_stats.same_counter = value,
+                "rfrag/adjacent" => stats.fragment_stats.adjacent_counter = value,
+                "rfrag/distal" => stats.fragment_stats.distal_counter = value,
+                "rfrag/unannotated" => stats.fragment_stats.unannotated_counter = value,
+                "walks/pairs" => stats.walk_stats.pairs_counter = value,
+                "walks/unresolved" => stats.walk_stats.unresolved_counter = value,
+                _ => {
+                    if let Some(thr) = key.strip_prefix("cis_").and_then(|k| k.strip_suffix("kb+")) {
+                        if let Some(i) = CIS_THRESHOLDS.iter().position(|t| (t / 1000).to_string() == thr) {
+                            stats.cis_dist_counters[i] = value;
+                        }
+                    } else if let Some(n) = key.strip_prefix("walks/segments/").and_then(|n| n.parse().ok()) {
+                        stats.walk_stats.segments_counter.insert(n, value);
+                    } else if let Some(tp) = key.strip_prefix("pair_types/") {
+                        stats.pair_type_counter.insert(tp.to_string(), value);
+                    } else if let Some(tigs) = key.strip_prefix("chrom_freq/") {
+                        stats.chrom_freq.insert(tigs.to_string(), value);
+                    } else if let Some(bin) = key.strip_prefix("dist_freq/") {
+                        let start = bin.split(|c| c == '-' || c == '+').next().and_then(|s| s.parse::<u64>().ok());
+                        if let Some(i) = start.and_then(|s| bins.iter().position(|b| *b == s)) {
+                            stats.dist_freq[i] = value;
+                        }
+                    }
+                }
+            }
+        }
+        stats
+    }
+
+    pub fn from_file(file_path: &Path) -> io::Result<ConverterStat> {
+        let reader = BufReader::new(File::open(file_path)?);
+        match StatsFormat::from_path(file_path) {
+            StatsFormat::Json => serde_json::from_reader(reader)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
+            StatsFormat::Tsv => {
+                let mut values = Vec::new();
+                let mut config_values = Vec::new();
+                for line in reader.lines() {
+                    let line = line?;
+                    if let Some(param) = line.strip_prefix(CONFIG_KEY_PREFIX) {
+                        let (key, value) = param.split_once(VALUE_SEP).ok_or_else(|| invalid_line(line.as_str()))?;
+                        config_values.push((key.to_string(), value.to_string()));
+                        continue;
+                    }
+                    if line.is_empty() || line.starts_with('#') { continue; }
+                    let (key, value) = line.rsplit_once(VALUE_SEP).ok_or_else(|| invalid_line(line.as_str()))?;
+                    values.push((key.to_string(), value.trim().parse().map_err(|_| invalid_line(line.as_str()))?));
+                }
+
+                let mut stats = ConverterStat::from_key_values(values.into_iter());
+                if !config_values.is_empty() {
+                    stats.config = Some(ConverterConfig::from_key_values(config_values.into_iter())?);
+                }
+                Ok(stats)
+            }
+        }
+    }
+
+    pub fn dump_stats_to_file(&self, file_path: &Path) -> io::Result<()> {
+        let mut f = BufWriter::new(File::create(file_path)?);
+
+        match StatsFormat::from_path(file_path) {
+            StatsFormat::Json => {
+                serde_json::to_writer_pretty(&mut f, self)
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+                writeln!(f)?;
+            },
+            StatsFormat::Tsv => {
+                // parameters are written as comments, so that file is still readable by pairtools
+                if let Some(config) = &self.config {
+                    for (key, value) in config.to_key_values() {
+                        writeln!(f, "{}{}{}{}", CONFIG_KEY_PREFIX, key, VALUE_SEP, value)?;
+                    }
+                }
+                for (key, value) in self.to_key_values() {
+                    writeln!(f, "{}{}{}", key, VALUE_SEP, value)?;
+                }
+            }
+        }
+
+        f.flush()
+    }
+}
+
+// Bins for separation of cis pairs: 0 and rounded powers of 10 with step 0.25 up to 10^9.
+pub fn get_dist_bins() -> Vec<u64> {
+    let mut bins = vec![0];
+    bins.extend((0..=36).map(|i| 10f64.powf(i as f64 * 0.25).round() as u64));
+    bins
+}
+
+fn add_counters(counters: &mut Vec<u64>, other: &[u64]) {
+    if counters.len() < other.len() {
+        counters.resize(other.len(), 0);
+    }
+    for (c, o) in counters.iter_mut().zip(other.iter()) {
+        *c += *o;
+    }
+}
+
+fn add_map_counters(counters: &mut BTreeMap<String, u64>, other: &BTreeMap<String, u64>) {
+    for (key, value) in other.iter() {
+        *counters.entry(key.clone()).or_insert(0) += *value;
+    }
+}
+
+fn invalid_line(line: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Incorrect line in statistics file: {}", line))
+}
+
+// Sums statistics of several conversions and saves result (formats are defined by file extensions).
+// Conversions must be run with the same parameters, files without saved parameters are not checked.
+pub fn merge_stats_files(in_files: &[&Path], out_file: &Path) -> io::Result<()> {
+    let mut merged: Option<ConverterStat> = None;
+    for path in in_files {
+        let stats = ConverterStat::from_file(path)?;
+        let merged_config = merged.as_ref().and_then(|m| m.config.as_ref());
+        if matches!((merged_config, &stats.config), (Some(config), Some(other)) if config != other) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                "Parameters of {} differ from parameters of other files.", path.display())));
+        }
+        match &mut merged {
+            Some(m) => m.merge(&stats),
+            None => merged = Some(stats),
+        }
+    }
+    merged.unwrap_or_else(ConverterStat::new).dump_stats_to_file(out_file)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn tmp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("hic_convertor_stats_{}_{}", std::process::id(), name))
+    }
+
+    fn shard_stats(n_reads: u64, tig: &str, config: Option<ConverterConfig>) -> ConverterStat {
+        let mut stats = ConverterStat::new();
+        stats.read_counter = n_reads;
+        stats.pairs_counter = n_reads / 2;
+        stats.intra_counter = n_reads / 4;
+        stats.cis_dist_counters[0] = n_reads / 8;
+        stats.chrom_freq.insert(format!("{}/{}", tig, tig), n_reads / 4);
+        stats.config = config;
+        stats
+    }
+
+    fn merge_shards(name: &str, shards: &[ConverterStat], out_name: &str) -> io::Result<ConverterStat> {
+        let paths: Vec<PathBuf> = (0..shards.len())
+            .map(|i| tmp_path(format!("{}_{}_{}", name, i, out_name).as_str()))
+            .collect();
+        for (stats, path) in shards.iter().zip(paths.iter()) {
+            stats.dump_stats_to_file(path).unwrap();
+        }
+        let out_file = tmp_path(format!("{}_merged_{}", name, out_name).as_str());
+        let in_files: Vec<&Path> = paths.iter().map(|p| p.as_path()).collect();
+        let merged = merge_stats_files(&in_files, &out_file).and_then(|_| ConverterStat::from_file(&out_file));
+
+        for path in paths.iter() {
+            fs::remove_file(path).unwrap();
+        }
+        if out_file.exists() {
+            fs::remove_file(&out_file).unwrap();
+        }
+        merged
+    }
+
+    // Shards are written in TSV and JSON, merged statistics are read back from the output of the same format.
+    #[test]
+    fn test_merge_stats_files() {
+        let config = ConverterConfig { seed: 7, ..ConverterConfig::default() };
+        let shards = vec![shard_stats(800, "ctg1", Some(config.clone())),
+                          shard_stats(160, "ctg2", Some(config.clone()))];
+        for &ext in ["tsv", "json"].iter() {
+            let merged = merge_shards("ok", &shards, ext).unwrap();
+            assert_eq!(merged.read_counter, 960);
+            assert_eq!(merged.pairs_counter, 480);
+            assert_eq!(merged.intra_counter, 240);
+            assert_eq!(merged.cis_dist_counters[0], 120);
+            assert_eq!(merged.chrom_freq.get("ctg1/ctg1"), Some(&200));
+            assert_eq!(merged.chrom_freq.get("ctg2/ctg2"), Some(&40));
+            assert!(merged.config == Some(config.clone()));
+        }
+
+        // statistics without saved parameters are merged with any others
+        let shards = vec![shard_stats(800, "ctg1", None), shard_stats(160, "ctg1", Some(config.clone()))];
+        let merged = merge_shards("no_config", &shards, "tsv").unwrap();
+        assert_eq!(merged.chrom_freq.get("ctg1/ctg1"), Some(&240));
+        assert!(merged.config == Some(config));
+    }
+
+    #[test]
+    fn test_merge_different_configs() {
+        let shards = vec![shard_stats(800, "ctg1", Some(ConverterConfig::default())),
+                          shard_stats(160, "ctg1", Some(ConverterConfig { seed: 7, ..ConverterConfig::default() }))];
+        for &ext in ["tsv", "json"].iter() {
+            let e = merge_shards("different", &shards, ext).err().unwrap();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
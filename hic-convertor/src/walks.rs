@@ -0,0 +1,224 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use bam::record::cigar::Operation;
+use serde::{Deserialize, Serialize};
+
+use super::pair_record;
+
+// Policy for reporting contacts from walks (several ligation junctions in one read pair), similar to
+// `--walks-policy` of pairtools: outer - pair of the outermost segments, adjacent - pairs of all consecutive
+// segments (one pair for each ligation junction), all - all pairs of segments.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WalkPolicy {
+    Outer,
+    Adjacent,
+    All
+}
+
+impl WalkPolicy {
+    pub fn from_string(s: &str) -> WalkPolicy {
+        match s {
+            "adjacent" => WalkPolicy::Adjacent,
+            "all" => WalkPolicy::All,
+            _ => WalkPolicy::Outer
+        }
+    }
+
+    // Indices of segments of the walk which are reported as Hi-C pairs.
+    pub fn get_pairs(&self, n_segments: usize) -> Vec<(usize, usize)> {
+        if n_segments < 2 {
+            return Vec::new();
+        }
+
+        match *self {
+            WalkPolicy::Outer => vec![(0, n_segments - 1)],
+            WalkPolicy::Adjacent => (1..n_segments).map(|i| (i - 1, i)).collect(),
+            WalkPolicy::All => (0..n_segments)
+                .flat_map(|i| (i + 1..n_segments).map(move |j| (i, j)))
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for WalkPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WalkPolicy::Outer => write!(f, "outer"),
+            WalkPolicy::Adjacent => write!(f, "adjacent"),
+            WalkPolicy::All => write!(f, "all"),
+        }
+    }
+}
+
+// Piece of DNA molecule between two ligation junctions. It is covered by one alignment or by two alignments
+// (one from each mate) when mates are read through the same piece from its opposite ends.
+pub type Segment<'a> = Vec<&'a bam::Record>;
+
+// Orders segments of both mates along the molecule: mate 1 from 5' to 3' end and then mate 2 from 3' to 5' end.
+// The 3' ends of mates are merged in one segment if they cover the same piece of molecule, otherwise there is
+// an unsequenced ligation junction between them.
+pub fn build_walk<'a>(recs1: &'a [bam::Record], recs2: &'a [bam::Record], max_molecule_size: u64) -> Vec<Segment<'a>> {
+    let mate1 = get_ordered_segments(recs1);
+    let mut mate2 = get_ordered_segments(recs2);
+    mate2.reverse();
+
+    let mut walk: Vec<Segment> = mate1.into_iter().map(|rec| vec![rec]).collect();
+    for (i, rec) in mate2.into_iter().enumerate() {
+        if i == 0 {
+            if let Some(last) = walk.last_mut() {
+                if is_same_segment(last[0], rec, max_molecule_size) {
+                    last.push(rec);
+                    continue;
+                }
+            }
+        }
+        walk.push(vec![rec]);
+    }
+    walk
+}
+
+fn is_same_segment(algn1: &bam::Record, algn2: &bam::Record, max_molecule_size: u64) -> bool {
+    pair_record::calc_dist(algn1, algn2) < max_molecule_size && pair_record::is_opposite_pair(algn1, algn2)
+}
+
+// Mapped non-secondary alignments of the mate ordered by their position in the read.
+fn get_ordered_segments(recs: &[bam::Record]) -> Vec<&bam::Record> {
+    let mut segments: Vec<&bam::Record> = recs.iter()
+        .filter(|rec| rec.flag().is_mapped() && !rec.flag().is_secondary())
+        .collect();
+    segments.sort_by_key(|rec| get_read_start(rec));
+    segments
+}
+
+// Start of the alignment in coordinates of the read (clipping at its 5' end).
+pub fn get_read_start(rec: &bam::Record) -> u32 {
+    let clips: Vec<u32> = rec.cigar().iter()
+        .map(|(len, op)| if matches!(op, Operation::Soft | Operation::Hard) { len } else { 0 })
+        .collect();
+
+    let clip = if rec.flag().is_reverse_strand() { clips.last() } else { clips.first() };
+    clip.cloned().unwrap_or(0)
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct WalkStat {
+    pub policy: WalkPolicy,
+    pub segments_counter: BTreeMap<usize, u64>, // number of read pairs for each number of segments in walk
+    pub pairs_counter: u64, // number of pairs reported from walks
+    pub unresolved_counter: u64, // walks without reported pairs
+}
+
+impl WalkStat {
+    pub fn new(policy: WalkPolicy) -> WalkStat {
+        WalkStat {
+            policy,
+            segments_counter: BTreeMap::new(),
+            pairs_counter: 0,
+            unresolved_counter: 0,
+        }
+    }
+
+    pub fn merge(&mut self, other: &WalkStat) {
+        for (n_segments, count) in other.segments_counter.iter() {
+            *self.segments_counter.entry(*n_segments).or_insert(0) += *count;
+        }
+        self.pairs_counter += other.pairs_counter;
+        self.unresolved_counter += other.unresolved_counter;
+    }
+
+    pub fn update(&mut self, n_segments: usize, n_pairs: usize) {
+        *self.segments_counter.entry(n_segments).or_insert(0) += 1;
+        self.pairs_counter += n_pairs as u64;
+        if n_pairs == 0 {
+            self.unresolved_counter += 1;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX_MOLECULE_SIZE: u64 = 2000;
+
+    fn alignment(ref_id: i32, start: i32, cigar: &str, forward: bool, first_mate: bool) -> bam::Record {
+        let mut rec = bam::Record::new();
+        rec.set_flag(0);
+        rec.flag_mut().set_paired(true);
+        rec.flag_mut().set_mapped(true);
+        rec.flag_mut().set_strand(forward);
+        rec.flag_mut().set_first_in_pair(first_mate);
+        rec.flag_mut().set_last_in_pair(!first_mate);
+        rec.set_ref_id(ref_id);
+        rec.set_start(start);
+        rec.set_cigar(cigar.bytes()).unwrap();
+        rec
+    }
+
+    // Molecule of three pieces A (ref 0), B (ref 1) and C (ref 2). Mate 1 reads A and then B, mate 2 reads C
+    // and then B from the opposite end, so B is covered by both mates. Alignments are given out of read order.
+    fn chimera(b2_start: i32) -> (Vec<bam::Record>, Vec<bam::Record>) {
+        let recs1 = vec![alignment(1, 5000, "60H40M", true, true), alignment(0, 1000, "60M40S", true, true)];
+        let recs2 = vec![alignment(1, b2_start, "50M50S", false, false), alignment(2, 9000, "50M50S", true, false)];
+        (recs1, recs2)
+    }
+
+    fn ref_ids(walk: &[Segment]) -> Vec<Vec<i32>> {
+        walk.iter().map(|segment| segment.iter().map(|rec| rec.ref_id()).collect()).collect()
+    }
+
+    #[test]
+    fn test_read_start() {
+        assert_eq!(get_read_start(&alignment(0, 0, "60M40S", true, true)), 0);
+        assert_eq!(get_read_start(&alignment(0, 0, "60H40M", true, true)), 60);
+        assert_eq!(get_read_start(&alignment(0, 0, "50M50S", false, true)), 50);
+        assert_eq!(get_read_start(&alignment(0, 0, "20S80M", false, true)), 0);
+    }
+
+    #[test]
+    fn test_build_walk() {
+        let (recs1, recs2) = chimera(5100);
+        let walk = build_walk(&recs1, &recs2, MAX_MOLECULE_SIZE);
+        assert_eq!(ref_ids(&walk), vec![vec![0], vec![1, 1], vec![2]]);
+
+        // 3' ends of mates are far from each other, there is an unsequenced junction between them
+        let (recs1, recs2) = chimera(5000 + MAX_MOLECULE_SIZE as i32 + 100);
+        let walk = build_walk(&recs1, &recs2, MAX_MOLECULE_SIZE);
+        assert_eq!(ref_ids(&walk), vec![vec![0], vec![1], vec![1], vec![2]]);
+    }
+
+    #[test]
+    fn test_skipped_alignments() {
+        let (mut recs1, mut recs2) = chimera(5100);
+        let mut secondary = alignment(2, 100, "60H40M", true, true);
+        secondary.flag_mut().set_secondary(true);
+        recs1.push(secondary);
+        let mut unmapped = alignment(0, 100, "100M", true, false);
+        unmapped.flag_mut().set_mapped(false);
+        recs2.push(unmapped);
+
+        let walk = build_walk(&recs1, &recs2, MAX_MOLECULE_SIZE);
+        assert_eq!(ref_ids(&walk), vec![vec![0], vec![1, 1], vec![2]]);
+    }
+
+    // Pairs of segments reported from the 3-segment walk by each policy.
+    #[test]
+    fn test_walk_policies() {
+        let (recs1, recs2) = chimera(5100);
+        let walk = build_walk(&recs1, &recs2, MAX_MOLECULE_SIZE);
+        let pairs = |policy: WalkPolicy| -> Vec<(i32, i32)> {
+            policy.get_pairs(walk.len()).into_iter().map(|(i, j)| (walk[i][0].ref_id(), walk[j][0].ref_id())).collect()
+        };
+        assert_eq!(pairs(WalkPolicy::Outer), vec![(0, 2)]);
+        assert_eq!(pairs(WalkPolicy::Adjacent), vec![(0, 1), (1, 2)]);
+        assert_eq!(pairs(WalkPolicy::All), vec![(0, 1), (0, 2), (1, 2)]);
+
+        for &policy in [WalkPolicy::Outer, WalkPolicy::Adjacent, WalkPolicy::All].iter() {
+            assert!(policy.get_pairs(1).is_empty());
+            assert!(WalkPolicy::from_string(policy.to_string().as_str()) == policy);
+        }
+    }
+}
@@ -0,0 +1,242 @@
+use std::fmt;
+
+use bam::record::tags::TagValue;
+use serde::{Deserialize, Serialize};
+
+use super::pair_record::Strand;
+
+// bwa-mem stores alternative hits in XA tag as `chr,+pos,CIGAR,NM;` (pos is 1-based and leftmost).
+const XA_TAG: &[u8; 2] = b"XA";
+const NM_TAG: &[u8; 2] = b"NM";
+const HIT_SEP: char = ';';
+const FIELD_SEP: char = ',';
+
+// Policy for reads whose alignment is not unique, but alternative hits are listed in XA tag.
+#[derive(Clone, Copy)]
+pub enum MultiMapPolicy {
+    Drop,
+    Random(u64),
+    All
+}
+
+impl MultiMapPolicy {
+    pub fn new(mode: MultiMapMode, seed: u64) -> MultiMapPolicy {
+        match mode {
+            MultiMapMode::Drop => MultiMapPolicy::Drop,
+            MultiMapMode::Random => MultiMapPolicy::Random(seed),
+            MultiMapMode::All => MultiMapPolicy::All
+        }
+    }
+
+    pub fn get_mode(&self) -> MultiMapMode {
+        match *self {
+            MultiMapPolicy::Drop => MultiMapMode::Drop,
+            MultiMapPolicy::Random(_) => MultiMapMode::Random,
+            MultiMapPolicy::All => MultiMapMode::All
+        }
+    }
+}
+
+// Policy as a parameter of conversion, seed of random picking is a separate parameter.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MultiMapMode {
+    Drop,
+    Random,
+    All
+}
+
+impl MultiMapMode {
+    pub fn from_string(s: &str) -> MultiMapMode {
+        match s {
+            "random" => MultiMapMode::Random,
+            "all" => MultiMapMode::All,
+            _ => MultiMapMode::Drop
+        }
+    }
+}
+
+// How a Hi-C pair was obtained w.r.t. multimapping: from unique alignments, from randomly picked hit
+// or as one of all combinations of hits (in the latter case the pair has fractional weight).
+#[derive(Clone, Copy, PartialEq)]
+pub enum MultiMapResolution {
+    Unique,
+    Random,
+    All
+}
+
+impl fmt::Display for MultiMapResolution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MultiMapResolution::Unique => write!(f, "U"),
+            MultiMapResolution::Random => write!(f, "R"),
+            MultiMapResolution::All => write!(f, "A"),
+        }
+    }
+}
+
+pub struct AltHit {
+    pub ref_name: String,
+    pub start: i64,
+    pub strand: Strand,
+    pub cigar: String,
+    pub nm: u32
+}
+
+impl AltHit {
+    pub fn get_alignment_pos(&self) -> i64 {
+        self.start + get_ref_len(self.cigar.as_str()) / 2
+    }
+}
+
+// Hits of XA tag are within score threshold of bwa-mem, so only hits with no more mismatches (NM) than
+// the primary alignment are kept as equally good. All hits are kept if alignment has no NM tag.
+pub fn parse_xa_tag(rec: &bam::Record) -> Vec<AltHit> {
+    let hits = match rec.tags().get(XA_TAG) {
+        Some(TagValue::String(s, _)) => std::str::from_utf8(s).map(parse_xa_string).unwrap_or_default(),
+        _ => Vec::new()
+    };
+    match rec.tags().get(NM_TAG) {
+        Some(TagValue::Int(nm, _)) => hits.into_iter().filter(|hit| hit.nm as i64 <= nm).collect(),
+        _ => hits
+    }
+}
+
+// Malformed hits are skipped.
+pub fn parse_xa_string(s: &str) -> Vec<AltHit> {
+    s.split(HIT_SEP)
+        .filter(|hit| !hit.is_empty())
+        .filter_map(parse_hit)
+        .collect()
+}
+
+fn parse_hit(hit: &str) -> Option<AltHit> {
+    let fields: Vec<&str> = hit.split(FIELD_SEP).collect();
+    if fields.len() != 4 || fields[1].len() < 2 { return None; }
+
+    let (strand, pos) = fields[1].split_at(1);
+    let strand = match strand {
+        "+" => Strand::Forward,
+        "-" => Strand::Reverse,
+        _ => return None
+    };
+    let pos: i64 = pos.parse().ok()?;
+
+    Some(AltHit {
+        ref_name: fields[0].to_string(),
+        start: pos - 1,
+        strand,
+        cigar: fields[2].to_string(),
+        nm: fields[3].parse().ok()?
+    })
+}
+
+fn get_ref_len(cigar: &str) -> i64 {
+    let mut len = 0;
+    let mut num = 0;
+    for c in cigar.chars() {
+        match c.to_digit(10) {
+            Some(d) => num = num * 10 + d as i64,
+            None => {
+                if matches!(c, 'M' | 'D' | 'N' | '=' | 'X') { len += num; }
+                num = 0;
+            }
+        }
+    }
+    len
+}
+
+// Small deterministic generator (splitmix64), so that picking of random hits is reproducible with the same seed.
+pub struct SeededRng {
+    state: u64
+}
+
+impl SeededRng {
+    pub fn new(seed: u64) -> SeededRng {
+        SeededRng { state: seed }
+    }
+
+    pub fn next_index(&mut self, n: usize) -> usize {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        (z % n as u64) as usize
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(xa: Option<&str>, nm: Option<i32>) -> bam::Record {
+        let mut rec = bam::Record::new();
+        if let Some(xa) = xa {
+            rec.tags_mut().push_string(XA_TAG, xa.as_bytes());
+        }
+        if let Some(nm) = nm {
+            rec.tags_mut().push_num(NM_TAG, nm);
+        }
+        rec
+    }
+
+    fn hit_names(hits: &[AltHit]) -> Vec<&str> {
+        hits.iter().map(|hit| hit.ref_name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_parse_hits() {
+        let hits = parse_xa_string("ctg2,+1001,50M,1;ctg3,-21,20M5D30M,0;");
+        assert_eq!(hit_names(&hits), vec!["ctg2", "ctg3"]);
+        assert!(matches!((hits[0].strand, hits[1].strand), (Strand::Forward, Strand::Reverse)));
+        assert_eq!((hits[0].start, hits[0].nm), (1000, 1));
+        assert_eq!(hits[0].get_alignment_pos(), 1025);
+        assert_eq!(hits[1].get_alignment_pos(), 20 + 27);
+    }
+
+    // Malformed hits are skipped, well-formed hits of the same tag are kept.
+    #[test]
+    fn test_malformed_tag() {
+        let hits = parse_xa_string("ctg1,1001,50M,1;ctg2,+,50M,0;ctg3,*100,50M,0;ctg4,+x,50M,0;ctg5,+10,50M;\
+                                    ctg6,+10,50M,x;ctg7,+10,50M,0,1;;ctg8,-10,50M,2");
+        assert_eq!(hit_names(&hits), vec!["ctg8"]);
+        assert!(parse_xa_string("").is_empty());
+        assert!(parse_xa_string("garbage").is_empty());
+    }
+
+    #[test]
+    fn test_nm_filter() {
+        let xa = "ctg2,+100,50M,0;ctg3,-200,50M,2;ctg4,+300,50M,3";
+        assert_eq!(hit_names(&parse_xa_tag(&record(Some(xa), Some(2)))), vec!["ctg2", "ctg3"]);
+        assert_eq!(hit_names(&parse_xa_tag(&record(Some(xa), Some(0)))), vec!["ctg2"]);
+        // all hits are kept without NM of the alignment
+        assert_eq!(hit_names(&parse_xa_tag(&record(Some(xa), None))), vec!["ctg2", "ctg3", "ctg4"]);
+        assert!(parse_xa_tag(&record(None, Some(0))).is_empty());
+    }
+
+    // The same seed gives the same picks, every hit can be picked.
+    #[test]
+    fn test_seeded_random_pick() {
+        let picks = |seed: u64| -> Vec<usize> {
+            let mut rng = SeededRng::new(seed);
+            (0..100).map(|_| rng.next_index(3)).collect()
+        };
+        assert_eq!(picks(7), picks(7));
+        assert_ne!(picks(7), picks(8));
+        assert!(picks(7).iter().all(|&i| i < 3));
+        assert!((0..3).all(|i| picks(7).contains(&i)));
+
+        let mut rng = SeededRng::new(0);
+        assert_eq!(rng.next_index(1), 0);
+    }
+
+    #[test]
+    fn test_policy_and_mode() {
+        assert!(matches!(MultiMapPolicy::new(MultiMapMode::Random, 5), MultiMapPolicy::Random(5)));
+        assert!(MultiMapPolicy::new(MultiMapMode::All, 5).get_mode() == MultiMapMode::All);
+        assert!(MultiMapMode::from_string("random") == MultiMapMode::Random);
+        assert!(MultiMapMode::from_string("unknown") == MultiMapMode::Drop);
+    }
+}
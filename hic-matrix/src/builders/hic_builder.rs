@@ -0,0 +1,51 @@
+use ndarray::{ArrayView1, Array1};
+use std::error::Error;
+
+use super::super::hic_format::HicFile;
+use super::res_grp_builder::{ResGrpBuilder, PixelIter};
+
+// Builds resolution group from matrix of .hic file with the same resolution.
+pub struct HicBuilder<'a> {
+    hic_file: &'a HicFile,
+    rsltn: u32,
+    bin_table: (Array1<u32>, Array1<u64>, Array1<u64>),
+    tig_offsets: Array1<u32>
+}
+
+impl<'a> HicBuilder<'a> {
+    pub fn new(hic_file: &'a HicFile, rsltn: u32) -> HicBuilder<'a> {
+        let tig_offsets = HicBuilder::build_tig_offsets(rsltn, hic_file.lengths_view());
+        let n_bins = if !tig_offsets.is_empty() {tig_offsets[tig_offsets.len() - 1] as usize} else {0};
+        let bin_table = HicBuilder::build_bin_table_from_lengths(n_bins, rsltn as u64, hic_file.lengths_view());
+
+        HicBuilder {
+            hic_file,
+            rsltn,
+            bin_table,
+            tig_offsets,
+        }
+    }
+
+    pub fn get_weights(&self) -> Result<Option<Array1<f64>>, Box<dyn Error>> {
+        self.hic_file.read_weights(self.rsltn, self.tig_offsets.view())
+    }
+}
+
+impl<'a> ResGrpBuilder for HicBuilder<'a> {
+    fn get_resolution(&self) -> u32 {
+        self.rsltn
+    }
+
+    fn get_tig_offsets_view(&self) -> ArrayView1<u32> {
+        self.tig_offsets.view()
+    }
+
+    fn get_bin_table(&self) -> (ArrayView1<u32>, ArrayView1<u64>, ArrayView1<u64>) {
+        (self.bin_table.0.view(), self.bin_table.1.view(), self.bin_table.2.view())
+    }
+
+    fn get_pixels(&self) -> Result<PixelIter, Box<dyn Error>> {
+        let pixels = self.hic_file.read_pixels(self.rsltn, self.tig_offsets.view())?;
+        Ok(Box::new(pixels.into_iter().map(Ok)))
+    }
+}
@@ -4,12 +4,17 @@ use std::error::Error;
 use ahash::AHashSet;
 
 use super::super::Matrix;
+use super::super::hic_format::HicFile;
 use super::pair_builder::PairsBuilder;
-use super::super::writer::MatrixWriter;
+use super::hic_builder::HicBuilder;
+use super::zoom_builder::ZoomBuilder;
+use super::super::writer::{MatrixWriter, Compression};
 use super::super::balancer::Strategy;
 use std::iter::FromIterator;
 use itertools::Itertools;
 
+const IMPORT_CHUNKSIZE: usize = 30_000_000;
+
 // pub fn build_from_pairs(pairs_file: &Path, matrix_file: &Path,
 //                         ord_tig_lengths: &[(AsciiString, u64)],
 //                         resolution: u32
@@ -24,10 +29,13 @@ use itertools::Itertools;
 pub fn build_from_pairs(pairs_file: &Path, matrix_file: &Path,
                                        ord_tig_lengths: &[(AsciiString, u64)],
                                        resolution: u32,
-                                       strategy: &Strategy
+                                       strategy: &Strategy,
+                                       mem_limit: usize,
+                                       tmp_dir: &Path,
+                                       compression: &Compression
 ) -> Result<(), Box<dyn Error>> {
-    let writer = MatrixWriter::new_in_writing_mode(matrix_file)?;
-    let builder = PairsBuilder::new(pairs_file, ord_tig_lengths, resolution);
+    let writer = MatrixWriter::update_compression(MatrixWriter::new_in_writing_mode(matrix_file)?, *compression);
+    let builder = PairsBuilder::new(pairs_file, ord_tig_lengths, resolution, mem_limit, tmp_dir)?;
     writer.write_matrix(&builder)?;
     balance(matrix_file, &vec![resolution], strategy)?;
     Ok(())
@@ -36,10 +44,13 @@ pub fn build_from_pairs(pairs_file: &Path, matrix_file: &Path,
 pub fn build_from_pairs_multi_res(pairs_file: &Path, matrix_file: &Path,
                                   ord_tig_lengths: &[(AsciiString, u64)],
                                   rslns: &[u32],
-                                  strategy: &Strategy
+                                  strategy: &Strategy,
+                                  mem_limit: usize,
+                                  tmp_dir: &Path,
+                                  compression: &Compression
 ) -> Result<(), Box<dyn Error>> {
-    build_from_pairs(pairs_file, matrix_file, &ord_tig_lengths, rslns[0], strategy)?;
-    zoom(matrix_file, &rslns[1..])?;
+    build_from_pairs(pairs_file, matrix_file, &ord_tig_lengths, rslns[0], strategy, mem_limit, tmp_dir, compression)?;
+    zoom(matrix_file, &rslns[1..], compression)?;
     balance(matrix_file, &rslns[1..], strategy)?;
     Ok(())
 }
@@ -50,22 +61,69 @@ pub fn balance(matrix_file: &Path, rslns: &[u32], strategy: &Strategy) -> Result
     Ok(matrix)
 }
 
-pub fn zoom(matrix_file: &Path, new_rslns: &[u32]) -> Result<Matrix, Box<dyn Error>> {
+pub fn zoom(matrix_file: &Path, new_rslns: &[u32], compression: &Compression) -> Result<Matrix, Box<dyn Error>> {
     let mut matrix = Matrix::from_hdf_file(matrix_file)?;
 
-    let mut rsltns = get_zooming_order(&matrix.get_resolutions(), new_rslns);
+    let rsltns = get_zooming_order(&matrix.get_resolutions(), new_rslns);
     assert!(!rsltns.is_empty());
 
     for res in rsltns.iter() {
         if res.1 != -1 {
             let prev_res = rsltns[res.1 as usize].0;
-            matrix.zoom(prev_res, res.0)?;
+            matrix.zoom(prev_res, res.0, compression)?;
         }
     }
 
     Ok(matrix)
 }
 
+// Copies all resolutions of .hic file, normalization vectors become balancing weights.
+pub fn import_from_hic(hic_file: &Path, matrix_file: &Path) -> Result<(), Box<dyn Error>> {
+    let hic_file = HicFile::open(hic_file)?;
+    let mut weights = Vec::new();
+    {
+        let writer = MatrixWriter::new_in_writing_mode(matrix_file)?;
+        writer.write_tigs(hic_file.tig_order_view(), hic_file.lengths_view())?;
+        for rsltn in hic_file.get_resolutions() {
+            println!("Importing resolution {} of .hic file", rsltn);
+            let builder = HicBuilder::new(&hic_file, rsltn);
+            writer.write_resolution_group(&builder)?;
+            if let Some(wghs) = builder.get_weights()? { weights.push((rsltn, wghs)); }
+        }
+    }
+
+    let writer = MatrixWriter::new_in_appending_mode(matrix_file)?;
+    for (rsltn, wghs) in weights.iter() {
+        writer.write_balancing_weights(*rsltn, wghs.view())?;
+    }
+    Ok(())
+}
+
+// Copies all resolutions of cooler file written by another tool, bins and dtypes are converted to the
+// layout of our builder (pixels are regrouped by zooming into the same resolution), weights are kept.
+pub fn import_from_cooler(cooler_file: &Path, matrix_file: &Path) -> Result<(), Box<dyn Error>> {
+    let cooler = Matrix::from_hdf_file_in_reading_mode(cooler_file)?;
+    let mut rslns = cooler.get_resolutions();
+    rslns.sort_unstable();
+    {
+        let writer = MatrixWriter::new_in_writing_mode(matrix_file)?;
+        writer.write_tigs(cooler.tig_order_view(), cooler.lengths_view())?;
+        for &rsltn in rslns.iter() {
+            println!("Importing resolution {} of cooler file", rsltn);
+            let grp = cooler.get_local_matrix(rsltn).unwrap();
+            let builder = ZoomBuilder::new(grp, cooler.lengths_view(), rsltn, IMPORT_CHUNKSIZE);
+            writer.write_resolution_group(&builder)?;
+        }
+    }
+
+    let writer = MatrixWriter::new_in_appending_mode(matrix_file)?;
+    for &rsltn in rslns.iter() {
+        if let Ok(wghs) = cooler.get_local_matrix(rsltn).unwrap().get_weights() {
+            writer.write_balancing_weights(rsltn, wghs.view())?;
+        }
+    }
+    Ok(())
+}
 
 fn get_zooming_order(resolutions: &[u32], new_resolutions: &[u32]) -> Vec<(u32, i32)> {
     if resolutions.is_empty() || new_resolutions.is_empty() { return vec![]; }
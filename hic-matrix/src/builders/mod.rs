@@ -2,4 +2,6 @@ pub mod matrix_builder;
 
 pub mod res_grp_builder;
 pub mod pair_builder;
-pub mod zoom_builder;
\ No newline at end of file
+pub mod zoom_builder;
+pub mod hic_builder;
+pub mod pixel_aggregator;
\ No newline at end of file
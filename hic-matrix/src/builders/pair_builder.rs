@@ -4,32 +4,31 @@ use ascii::{AsciiString, AsAsciiStr, AsAsciiStrError, AsciiStr};
 use ndarray::{Array1, ArrayView1};
 use std::iter::FromIterator;
 use std::error::Error;
-use std::fs::File;
-use serde::Deserialize;
 
-use super::res_grp_builder::ResGrpBuilder;
-use super::super::reader::PixelT;
+use super::res_grp_builder::{ResGrpBuilder, PixelIter};
+use super::pixel_aggregator::PixelAggregator;
+use super::super::utils::{self, PairsLayout};
+use super::super::errors::PairsHeaderError;
 
 pub struct PairsBuilder {
     rsltn: u32,
-    n_bins: usize,
     name2order: AHashMap<AsciiString, usize>,
     tig_order: Array1<AsciiString>,
     tig_lengths: Array1<u64>,
     bin_table: (Array1<u32>, Array1<u64>, Array1<u64>),
     tig_offsets: Array1<u32>,
     pairs_file: PathBuf,
+    layout: PairsLayout,
+    mem_limit: usize,
+    tmp_dir: PathBuf,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug)]
 struct PairRecord<'a> {
-    read_name: &'a str,
     tig1: &'a str,
     pos1: u64,
     tig2: &'a str,
     pos2: u64,
-    strand1: char,
-    strand2: char,
 }
 
 impl ResGrpBuilder for PairsBuilder {
@@ -45,13 +44,11 @@ impl ResGrpBuilder for PairsBuilder {
         (self.bin_table.0.view(), self.bin_table.1.view(), self.bin_table.2.view())
     }
 
-    fn get_bin_offsets(&self, pixels: &[PixelT]) -> Array1<u32> {
-        PairsBuilder::build_bin_offsets_from_pixels(self.n_bins, pixels)
-    }
-
-    fn get_pixels(&self) -> Result<Vec<PixelT>, Box<dyn Error>> {
-        let mut pixels:AHashMap<(u32, u32), u32> = AHashMap::default();
-        let file = File::open(self.pairs_file.as_path())?;
+    // Pixels are aggregated within memory limit with spilling into temporary directory. If pairs are sorted,
+    // buffer is spilled between blocks of the first contig, so that a block is not split between runs.
+    fn get_pixels(&self) -> Result<PixelIter, Box<dyn Error>> {
+        let mut aggregator = PixelAggregator::new(self.mem_limit, self.tmp_dir.as_path());
+        let file = utils::open_pairs_file(self.pairs_file.as_path())?;
 
         let mut rdr = csv::ReaderBuilder::new()
             .delimiter(b'\t')
@@ -60,14 +57,19 @@ impl ResGrpBuilder for PairsBuilder {
             .from_reader(file);
         let mut raw_record = csv::ByteRecord::new();
         let mut total: u32 = 0;
+        let mut prev_tig1 = String::new();
 
         while rdr.read_byte_record(&mut raw_record)? {
             total += 1;
-            let record: PairRecord = raw_record.deserialize(None)?;
+            let record = self.parse_pair_record(&raw_record)?;
+            if self.layout.is_sorted && record.tig1 != prev_tig1 {
+                aggregator.end_band()?;
+                prev_tig1.clear();
+                prev_tig1.push_str(record.tig1);
+            }
 
-            if let Some(bin_rec) = self.pair_to_bin_rec(&record)? {
-                let count = pixels.entry(bin_rec).or_insert(0);
-                *count += 1;
+            if let Some((bin1, bin2)) = self.pair_to_bin_rec(&record)? {
+                aggregator.add(bin1, bin2, 1)?;
             } else {
                 println!("There is a problem with pair record.");
             }
@@ -77,24 +79,22 @@ impl ResGrpBuilder for PairsBuilder {
             }
         }
 
-        let mut pixels: Vec<PixelT> = pixels.into_iter().map(|x| (x.0.0, x.0.1, x.1)).collect();
-        pixels.sort_by_key(|rec| { (rec.0, rec.1) });
-
-        Ok(pixels)
+        Ok(Box::new(aggregator.into_sorted_pixels()?))
     }
 
 }
 
 impl PairsBuilder {
-    pub fn new(pairs_file: &Path, ord_tig_lengths: &[(AsciiString, u64)], rsltn: u32) -> PairsBuilder {
+    // Pixels are aggregated within mem_limit bytes, the rest is spilled into tmp_dir.
+    pub fn new(pairs_file: &Path, ord_tig_lengths: &[(AsciiString, u64)], rsltn: u32, mem_limit: usize,
+               tmp_dir: &Path) -> Result<PairsBuilder, Box<dyn Error>> {
         let tig_lengths: Array1<u64> = Array1::from_iter(ord_tig_lengths.iter().map(|x| x.1));
         let tig_offsets = PairsBuilder::build_tig_offsets(rsltn,tig_lengths.view());
         let n_bins = if !tig_offsets.is_empty() {tig_offsets[tig_offsets.len() - 1] as usize} else {0};
         let bin_table = PairsBuilder::build_bin_table_from_lengths(n_bins, rsltn as u64, tig_lengths.view());
 
-        PairsBuilder {
+        Ok(PairsBuilder {
             rsltn,
-            n_bins,
             name2order: ord_tig_lengths.iter().enumerate()
                 .map(|(i, x)| (x.0.clone(), i) )
                 .collect(),
@@ -104,7 +104,10 @@ impl PairsBuilder {
             bin_table,
             tig_offsets,
             pairs_file: PathBuf::from(pairs_file),
-        }
+            layout: utils::parse_pairs_header(pairs_file)?,
+            mem_limit,
+            tmp_dir: PathBuf::from(tmp_dir),
+        })
     }
 
     pub fn tig_names_view(&self) -> ArrayView1<AsciiString> {
@@ -115,6 +118,21 @@ impl PairsBuilder {
         self.tig_lengths.view()
     }
 
+    fn parse_pair_record<'a>(&self, raw_record: &'a csv::ByteRecord) -> Result<PairRecord<'a>, Box<dyn Error>> {
+        let get_field = |col: usize| raw_record.get(col).ok_or(PairsHeaderError);
+        let parse_pos = |col: usize| -> Result<u64, Box<dyn Error>> {
+            let pos: u64 = std::str::from_utf8(get_field(col)?)?.parse()?;
+            Ok(pos.saturating_sub(self.layout.pos_offset))
+        };
+
+        Ok(PairRecord {
+            tig1: std::str::from_utf8(get_field(self.layout.col_tig1)?)?,
+            pos1: parse_pos(self.layout.col_pos1)?,
+            tig2: std::str::from_utf8(get_field(self.layout.col_tig2)?)?,
+            pos2: parse_pos(self.layout.col_pos2)?,
+        })
+    }
+
     fn pair_to_bin_rec(&self, record: &PairRecord) -> Result<Option<(u32, u32)>, AsAsciiStrError> {
         let get_anchor = |anchor: u64, tig: &AsciiStr| -> Option<u64> {
             self.get_tig_length_by_name(tig).map(|max_len| max_len.min(anchor))
@@ -0,0 +1,292 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ahash::AHashMap;
+
+use super::super::reader::PixelT;
+
+// Approximate memory of a pixel in hash map (with its overhead) and in sorted vector during spilling.
+const PIXEL_MEM: usize = 48;
+const PIXEL_BYTES: usize = 12;
+// Number of aggregators created by the process, it makes names of their temporary files unique.
+static N_AGGREGATORS: AtomicUsize = AtomicUsize::new(0);
+
+// Sums counts of pixels within memory budget. When the buffer is full, pixels are sorted and spilled into
+// a temporary run file, runs are merged at the end into a stream of pixels sorted by bin1 and bin2.
+// Spilled pixels which go after the last spilled pixel are appended to the last run. Sorted pairs come in bands
+// of the first contig ordered by names, while bins follow the order of contigs by length, so a band continues
+// the last run only if its contig goes after the contig of the previous band in the order of bins.
+pub struct PixelAggregator {
+    max_pixels: usize,
+    tmp_dir: PathBuf,
+    tmp_prefix: String,
+    buffer: AHashMap<(u32, u32), u32>,
+    runs: TempRuns,
+    last_run: Option<(BufWriter<File>, PixelT)>,
+}
+
+impl PixelAggregator {
+    pub fn new(mem_limit: usize, tmp_dir: &Path) -> PixelAggregator {
+        PixelAggregator {
+            max_pixels: (mem_limit / PIXEL_MEM).max(1),
+            tmp_dir: PathBuf::from(tmp_dir),
+            tmp_prefix: format!("hic_matrix_pixels.{}.{}", process::id(), N_AGGREGATORS.fetch_add(1, Ordering::Relaxed)),
+            buffer: AHashMap::default(),
+            runs: TempRuns { paths: Vec::new() },
+            last_run: None,
+        }
+    }
+
+    pub fn add(&mut self, bin1: u32, bin2: u32, count: u32) -> io::Result<()> {
+        *self.buffer.entry((bin1, bin2)).or_insert(0) += count;
+        if self.buffer.len() >= self.max_pixels {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    // Called when input moves to the next band of bins. Buffer is spilled only if it is more than half full,
+    // so that the next band does not overflow it and a band is not split between runs.
+    pub fn end_band(&mut self) -> io::Result<()> {
+        if self.buffer.len() >= self.max_pixels / 2 {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    pub fn into_sorted_pixels(mut self) -> io::Result<SortedPixels> {
+        if self.runs.paths.is_empty() {
+            let mut pixels: Vec<PixelT> = self.buffer.drain().map(|((bin1, bin2), c)| (bin1, bin2, c)).collect();
+            pixels.sort_unstable_by_key(|p| (p.0, p.1));
+            return Ok(SortedPixels::Memory(pixels.into_iter()));
+        }
+
+        self.spill()?;
+        if let Some((mut writer, _)) = self.last_run.take() {
+            writer.flush()?;
+        }
+        println!("Merging {} sorted runs of pixels", self.runs.paths.len());
+
+        let mut readers = Vec::with_capacity(self.runs.paths.len());
+        let mut heap = BinaryHeap::new();
+        for (i, path) in self.runs.paths.iter().enumerate() {
+            let mut reader = BufReader::new(File::open(path)?);
+            if let Some(pixel) = read_pixel(&mut reader)? {
+                heap.push(Reverse((pixel, i)));
+            }
+            readers.push(reader);
+        }
+        Ok(SortedPixels::Runs(RunsMerger { readers, heap, _runs: self.runs }))
+    }
+
+    fn spill(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() { return Ok(()); }
+        let mut pixels: Vec<PixelT> = self.buffer.drain().map(|((bin1, bin2), c)| (bin1, bin2, c)).collect();
+        pixels.sort_unstable_by_key(|p| (p.0, p.1));
+
+        let is_continuation = matches!(&self.last_run, Some((_, last)) if (last.0, last.1) < (pixels[0].0, pixels[0].1));
+        if !is_continuation {
+            if let Some((mut writer, _)) = self.last_run.take() {
+                writer.flush()?;
+            }
+            let path = self.tmp_dir.join(format!("{}.{}.tmp", self.tmp_prefix, self.runs.paths.len()));
+            let writer = BufWriter::new(File::create(&path)?);
+            self.runs.paths.push(path);
+            self.last_run = Some((writer, pixels[0]));
+        }
+
+        let (writer, last) = self.last_run.as_mut().unwrap();
+        for pixel in pixels.iter() {
+            writer.write_all(&pixel.0.to_le_bytes())?;
+            writer.write_all(&pixel.1.to_le_bytes())?;
+            writer.write_all(&pixel.2.to_le_bytes())?;
+        }
+        *last = *pixels.last().unwrap();
+        Ok(())
+    }
+}
+
+pub enum SortedPixels {
+    Memory(std::vec::IntoIter<PixelT>),
+    Runs(RunsMerger)
+}
+
+impl Iterator for SortedPixels {
+    type Item = io::Result<PixelT>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            SortedPixels::Memory(pixels) => pixels.next().map(Ok),
+            SortedPixels::Runs(merger) => merger.next_pixel().transpose()
+        }
+    }
+}
+
+// K-way merge of runs, counts of the same pixel from different runs are summed.
+pub struct RunsMerger {
+    readers: Vec<BufReader<File>>,
+    heap: BinaryHeap<Reverse<(PixelT, usize)>>,
+    _runs: TempRuns,
+}
+
+impl RunsMerger {
+    fn next_pixel(&mut self) -> io::Result<Option<PixelT>> {
+        let mut pixel = match self.pop()? {
+            Some(pixel) => pixel,
+            None => return Ok(None)
+        };
+        while matches!(self.heap.peek(), Some(Reverse((p, _))) if p.0 == pixel.0 && p.1 == pixel.1) {
+            pixel.2 += self.pop()?.unwrap().2;
+        }
+        Ok(Some(pixel))
+    }
+
+    fn pop(&mut self) -> io::Result<Option<PixelT>> {
+        match self.heap.pop() {
+            Some(Reverse((pixel, i))) => {
+                if let Some(next) = read_pixel(&mut self.readers[i])? {
+                    self.heap.push(Reverse((next, i)));
+                }
+                Ok(Some(pixel))
+            }
+            None => Ok(None)
+        }
+    }
+}
+
+fn read_pixel(reader: &mut impl Read) -> io::Result<Option<PixelT>> {
+    let mut buf = [0_u8; PIXEL_BYTES];
+    match reader.read_exact(&mut buf) {
+        Ok(()) => {},
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e)
+    };
+    let field = |i: usize| u32::from_le_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]]);
+    Ok(Some((field(0), field(4), field(8))))
+}
+
+// Temporary run files are removed when merging is finished (or failed).
+struct TempRuns {
+    paths: Vec<PathBuf>
+}
+
+impl Drop for TempRuns {
+    fn drop(&mut self) {
+        for run in self.paths.iter() {
+            if let Err(e) = fs::remove_file(run) {
+                println!("Can not remove temporary file {}: {}", run.display(), e);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn tmp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hic_matrix_aggregator_{}_{}", name, process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn n_tmp_files(dir: &Path) -> usize {
+        fs::read_dir(dir).unwrap().count()
+    }
+
+    // Aggregator of at most 4 pixels in memory.
+    fn small_aggregator(dir: &Path) -> PixelAggregator {
+        PixelAggregator::new(4 * PIXEL_MEM, dir)
+    }
+
+    #[test]
+    fn test_spill_and_merge() {
+        let dir = tmp_dir("merge");
+        let mut aggregator = small_aggregator(&dir);
+        let mut expected: BTreeMap<(u32, u32), u32> = BTreeMap::new();
+        for i in 0..200_u32 {
+            let (bin1, bin2, count) = ((i * 7) % 11, (i * 7) % 11 + i % 3, i % 4 + 1);
+            aggregator.add(bin1, bin2, count).unwrap();
+            *expected.entry((bin1, bin2)).or_insert(0) += count;
+        }
+        assert!(aggregator.runs.paths.len() > 1);
+
+        let pixels = aggregator.into_sorted_pixels().unwrap();
+        assert!(matches!(pixels, SortedPixels::Runs(_)));
+        let pixels: Vec<PixelT> = pixels.map(|p| p.unwrap()).collect();
+        let expected: Vec<PixelT> = expected.into_iter().map(|((bin1, bin2), c)| (bin1, bin2, c)).collect();
+        assert_eq!(pixels, expected);
+        assert_eq!(n_tmp_files(&dir), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_runs_merger() {
+        let dir = tmp_dir("runs");
+        let mut aggregator = small_aggregator(&dir);
+        for &(bin1, bin2, count) in [(5, 6, 1), (0, 1, 2), (3, 3, 1), (0, 0, 4)].iter() {
+            aggregator.add(bin1, bin2, count).unwrap();
+        }
+        // pixels go before the spilled ones, so they start a new run
+        for &(bin1, bin2, count) in [(0, 1, 3), (3, 3, 5), (4, 9, 1), (1, 1, 1)].iter() {
+            aggregator.add(bin1, bin2, count).unwrap();
+        }
+        assert_eq!(aggregator.runs.paths.len(), 2);
+        aggregator.add(0, 0, 1).unwrap();
+
+        let mut merger = match aggregator.into_sorted_pixels().unwrap() {
+            SortedPixels::Runs(merger) => merger,
+            SortedPixels::Memory(_) => panic!("pixels must be spilled")
+        };
+        let mut pixels = Vec::new();
+        while let Some(pixel) = merger.next_pixel().unwrap() {
+            pixels.push(pixel);
+        }
+        assert_eq!(pixels, vec![(0, 0, 5), (0, 1, 5), (1, 1, 1), (3, 3, 6), (4, 9, 1), (5, 6, 1)]);
+        drop(merger);
+        assert_eq!(n_tmp_files(&dir), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // Bands which go in order of bins continue the last run, a band of a preceding contig starts a new one.
+    #[test]
+    fn test_sorted_bands() {
+        let dir = tmp_dir("bands");
+        let mut aggregator = small_aggregator(&dir);
+        for &band in [0_u32, 10, 20, 5].iter() {
+            for bin2 in band..(band + 3) {
+                aggregator.add(band, bin2, 1).unwrap();
+            }
+            aggregator.end_band().unwrap();
+        }
+        assert_eq!(aggregator.runs.paths.len(), 2);
+        let pixels: Vec<PixelT> = aggregator.into_sorted_pixels().unwrap().map(|p| p.unwrap()).collect();
+        assert_eq!(pixels.len(), 12);
+        assert!(pixels.windows(2).all(|w| (w[0].0, w[0].1) < (w[1].0, w[1].1)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_unique_tmp_files() {
+        let dir = tmp_dir("unique");
+        let mut aggregators = vec![small_aggregator(&dir), small_aggregator(&dir)];
+        for (i, aggregator) in aggregators.iter_mut().enumerate() {
+            for bin in 0..8 {
+                aggregator.add(bin, bin, i as u32 + 1).unwrap();
+            }
+        }
+        assert_eq!(n_tmp_files(&dir), 2);
+        for (i, aggregator) in aggregators.into_iter().enumerate() {
+            let pixels: Vec<PixelT> = aggregator.into_sorted_pixels().unwrap().map(|p| p.unwrap()).collect();
+            assert_eq!(pixels, (0..8).map(|bin| (bin, bin, i as u32 + 1)).collect::<Vec<PixelT>>());
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,9 +1,12 @@
 use ndarray::{Array1, ArrayView1};
 use itertools::Itertools;
 use std::error::Error;
+use std::io;
 
 use super::super::reader::PixelT;
 
+// Pixels sorted by bin1 and bin2 without duplicates, writer consumes them one by one.
+pub type PixelIter<'a> = Box<dyn Iterator<Item = io::Result<PixelT>> + 'a>;
 
 pub trait ResGrpBuilder {
     fn get_resolution(&self) -> u32;
@@ -12,9 +15,7 @@ pub trait ResGrpBuilder {
 
     fn get_bin_table(&self) -> (ArrayView1<u32>, ArrayView1<u64>, ArrayView1<u64>);
 
-    fn get_bin_offsets(&self, pixels: &[PixelT]) -> Array1<u32>;
-
-    fn get_pixels(&self) -> Result<Vec<PixelT>, Box<dyn Error>>;
+    fn get_pixels(&self) -> Result<PixelIter, Box<dyn Error>>;
 
     fn build_tig_offsets(rsltn: u32, tig_lengths: ArrayView1<u64>) -> Array1<u32> {
         let mut count = 0_u32;
@@ -50,32 +51,6 @@ pub trait ResGrpBuilder {
 
         (chrs, starts, ends)
     }
-
-    fn build_bin_offsets_from_pixels(total_bins: usize, pixels: &[PixelT]) -> Array1<u32> {
-        let tb = total_bins + 1;
-        let mut bin_offsets = Array1::<u32>::default(tb);
-        let start_ind;
-
-        match pixels.first() {
-            Some(bin) => {
-                let ind = bin.0 as usize;
-                bin_offsets[ind] = 0;
-                start_ind = ind;
-            },
-            None => return bin_offsets,
-        };
-
-        pixels.iter().enumerate().tuple_windows().for_each(|((_, prev_bin), (i, cur_bin))| {
-            if prev_bin.0 != cur_bin.0 { bin_offsets[cur_bin.0 as usize] = i as u32; }
-        });
-        bin_offsets[tb - 1] = pixels.len() as u32;
-
-        for i in ((start_ind + 1)..bin_offsets.len()).rev() {
-            if bin_offsets[i] == 0 { bin_offsets[i] = bin_offsets[i + 1] };
-        }
-
-        bin_offsets
-    }
 }
 
 // fn calc_n_bins(rsltn: u32, tig_lengths: ArrayView1<u64>) -> usize {
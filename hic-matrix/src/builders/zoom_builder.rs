@@ -3,12 +3,11 @@ use std::error::Error;
 use ahash::AHashMap;
 
 use super::super::{res_group::ResGroup, reader::PixelT};
-use super::res_grp_builder::ResGrpBuilder;
+use super::res_grp_builder::{ResGrpBuilder, PixelIter};
 
 pub struct ZoomBuilder<'a> {
     from_grp: &'a ResGroup,
     new_res: u32,
-    n_new_bins: usize,
     chunksize: usize,
     bin_table: (Array1<u32>, Array1<u64>, Array1<u64>),
     tig_offsets: Array1<u32>
@@ -23,7 +22,6 @@ impl<'a> ZoomBuilder<'a> {
         ZoomBuilder {
             from_grp,
             new_res,
-            n_new_bins,
             chunksize,
             bin_table,
             tig_offsets,
@@ -44,11 +42,7 @@ impl<'a> ResGrpBuilder for ZoomBuilder<'a> {
         (self.bin_table.0.view(), self.bin_table.1.view(), self.bin_table.2.view())
     }
 
-    fn get_bin_offsets(&self, pixels: &[PixelT]) -> Array1<u32> {
-        ZoomBuilder::build_bin_offsets_from_pixels(self.n_new_bins, pixels)
-    }
-
-    fn get_pixels(&self) -> Result<Vec<PixelT>, Box<dyn Error>> {
+    fn get_pixels(&self) -> Result<PixelIter, Box<dyn Error>> {
         let new_res = self.new_res as u32;
         let bscs: Array1<(u32, u32)> = self.from_grp.get_bin_coords()?;
         let mut pixels:AHashMap<(u32, u32), u32> = AHashMap::default();
@@ -73,7 +67,7 @@ impl<'a> ResGrpBuilder for ZoomBuilder<'a> {
 
         let mut pixels: Vec<PixelT> = pixels.into_iter().map(|x| ((x.0).0, (x.0).1, x.1)).collect();
         pixels.sort_by_key(|rec| { (rec.0, rec.1) });
-        Ok(pixels)
+        Ok(Box::new(pixels.into_iter().map(Ok)))
     }
 }
 
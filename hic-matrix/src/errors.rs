@@ -32,3 +32,69 @@ impl fmt::Display for MatrixResolutionError {
 }
 
 impl error::Error for MatrixResolutionError {}
+
+#[derive(Debug, Clone)]
+pub struct PairsHeaderError;
+
+impl fmt::Display for PairsHeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Header of pairs file is incorrect or does not contain contig lengths.")
+    }
+}
+
+impl error::Error for PairsHeaderError {}
+
+#[derive(Debug, Clone)]
+pub struct SingleResolutionError;
+
+impl fmt::Display for SingleResolutionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Single-resolution cooler file can not store several resolutions.")
+    }
+}
+
+impl error::Error for SingleResolutionError {}
+
+#[derive(Debug, Clone)]
+pub struct TigNameError;
+
+impl fmt::Display for TigNameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Contig name is too long for storing in cooler file.")
+    }
+}
+
+impl error::Error for TigNameError {}
+
+#[derive(Debug, Clone)]
+pub struct HicFormatError;
+
+impl fmt::Display for HicFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Matrix can not be stored in .hic file, contig is too long.")
+    }
+}
+
+impl error::Error for HicFormatError {}
+
+#[derive(Debug, Clone)]
+pub struct HicVersionError;
+
+impl fmt::Display for HicVersionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "File is not a .hic file of version 8 or 9, or it is corrupted.")
+    }
+}
+
+impl error::Error for HicVersionError {}
+
+#[derive(Debug, Clone)]
+pub struct CompressionError;
+
+impl fmt::Display for CompressionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unknown compression, it must be none, lzf, gzip or gzip:<level> with level from 0 to 9.")
+    }
+}
+
+impl error::Error for CompressionError {}
@@ -0,0 +1,975 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::iter::FromIterator;
+use std::path::{Path, PathBuf};
+
+use ascii::AsciiString;
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use ndarray::{Array1, ArrayView1};
+
+use super::matrix::Matrix;
+use super::reader::PixelT;
+use super::res_group::ResGroup;
+use super::errors::{HicFormatError, HicVersionError};
+
+// Juicer .hic format v8 (https://github.com/aidenlab/hic-format/blob/master/HiCFormatV8.md): header with
+// contigs and resolutions, body with compressed blocks of contacts and metadata of matrices of contig pairs,
+// footer with master index, expected values and index of normalization vectors. Integers and floats are
+// little-endian, strings are null-terminated. Version 9 files (written by recent Juicer) are also read, they
+// differ by wider integer fields and float values.
+const HIC_MAGIC: &[u8; 4] = b"HIC\0";
+const HIC_VERSION: i32 = 8;
+const LAST_HIC_VERSION: i32 = 9;
+const GENOME_ID: &str = "unknown";
+const SOFTWARE: &str = concat!("hic-matrix-", env!("CARGO_PKG_VERSION"));
+const BP_UNIT: &str = "BP";
+// Juicebox expects pseudo-contig for whole-genome view at index 0, so contig i has index i + 1.
+const ALL_CHROM: &str = "All";
+const ALL_CHROM_SCALE: u64 = 1000;
+// Blocks are squares of BLOCK_BIN_COUNT x BLOCK_BIN_COUNT bins (as in Juicer).
+const BLOCK_BIN_COUNT: u32 = 1000;
+// Flags of block: counts are floats (yes, 1 means float in .hic), records are grouped in rows.
+const FLOAT_COUNTS: u8 = 1;
+const LIST_OF_ROWS: u8 = 1;
+const DENSE: u8 = 2;
+const EMPTY_SHORT_COUNT: i16 = i16::MIN;
+// Balancing weights are written as normalization vectors of this type (1 / weight, Juicer norms are divisive).
+const NORM_TYPE: &str = "KR";
+// Normalization vectors of imported files are taken by this preference.
+const IMPORTED_NORM_TYPES: [&str; 4] = [NORM_TYPE, "SCALE", "VC_SQRT", "VC"];
+const PIXEL_CHUNKSIZE: usize = 10_000_000;
+const MASTER_INDEX_OFFSET: u64 = 8;
+
+// Writes all resolutions of matrix into .hic file. Balancing weights of resolutions (if they exist) are
+// written as normalization vectors.
+pub fn write_hic_file(matrix: &Matrix, hic_file: &Path) -> Result<(), Box<dyn Error>> {
+    let mut rslns = matrix.get_resolutions();
+    rslns.sort_unstable_by(|a, b| b.cmp(a));
+
+    let mut out = CountingWriter::new(BufWriter::new(File::create(hic_file)?));
+    write_header(&mut out, matrix.tig_order_view(), matrix.lengths_view(), &rslns)?;
+
+    let mut footer = Footer::default();
+    let mut zooms: BTreeMap<(usize, usize), Vec<ZoomData>> = BTreeMap::new();
+    for &rsltn in rslns.iter() {
+        println!("Writing resolution {} into .hic file", rsltn);
+        let grp = matrix.get_local_matrix(rsltn).unwrap();
+        let tig_bins = get_tig_bins(grp)?;
+        let weights = grp.get_weights().ok();
+
+        let mut writer = ZoomWriter::new(rsltn, tig_bins.view(), weights.as_ref().map(|w| w.view()));
+        writer.write_blocks(&mut out, grp)?;
+        writer.write_norm_vectors(&mut out, &mut footer)?;
+        writer.save_expected_values(&mut footer);
+        for (tigs, zoom) in writer.zooms.into_iter() {
+            zooms.entry(tigs).or_default().push(zoom);
+        }
+    }
+
+    write_footer(out, footer, &zooms)?;
+    Ok(())
+}
+
+// Writes metadata of matrices of contig pairs and footer, position of master index is patched in header.
+fn write_footer<W: Write + Seek>(mut out: CountingWriter<BufWriter<W>>, mut footer: Footer,
+                                 zooms: &BTreeMap<(usize, usize), Vec<ZoomData>>) -> io::Result<()> {
+    for ((tig1, tig2), zooms) in zooms.iter() {
+        let pos = out.pos;
+        write_i32(&mut out, *tig1 as i32 + 1)?;
+        write_i32(&mut out, *tig2 as i32 + 1)?;
+        write_i32(&mut out, zooms.len() as i32)?;
+        for zoom in zooms.iter() {
+            zoom.write(&mut out)?;
+        }
+        footer.master_index.push((format!("{}_{}", tig1 + 1, tig2 + 1), pos, (out.pos - pos) as i32));
+    }
+
+    let master_index_pos = out.pos;
+    footer.write(&mut out)?;
+
+    let mut file = out.inner.into_inner().map_err(|e| e.into_error())?;
+    file.seek(SeekFrom::Start(MASTER_INDEX_OFFSET))?;
+    file.write_all(&(master_index_pos as i64).to_le_bytes())?;
+    file.flush()
+}
+
+fn write_header(out: &mut impl Write, tig_order: ArrayView1<AsciiString>, tig_lengths: ArrayView1<u64>,
+                rslns: &[u32]) -> Result<(), Box<dyn Error>> {
+    out.write_all(HIC_MAGIC)?;
+    write_i32(out, HIC_VERSION)?;
+    write_i64(out, 0)?; // position of master index, it is updated at the end
+    write_str(out, GENOME_ID)?;
+
+    write_i32(out, 1)?;
+    write_str(out, "software")?;
+    write_str(out, SOFTWARE)?;
+
+    write_i32(out, tig_order.len() as i32 + 1)?;
+    write_str(out, ALL_CHROM)?;
+    write_i32(out, (tig_lengths.sum() / ALL_CHROM_SCALE) as i32)?;
+    for (name, &length) in tig_order.iter().zip(tig_lengths.iter()) {
+        if length > i32::MAX as u64 { return Err(HicFormatError.into()); }
+        write_str(out, name.as_str())?;
+        write_i32(out, length as i32)?;
+    }
+
+    write_i32(out, rslns.len() as i32)?;
+    for &rsltn in rslns.iter() {
+        write_i32(out, rsltn as i32)?;
+    }
+    write_i32(out, 0)?; // fragment resolutions are not supported
+    Ok(())
+}
+
+// Number of bins of each contig.
+fn get_tig_bins(grp: &ResGroup) -> hdf5::Result<Array1<u32>> {
+    let tig_offsets = grp.get_tigs_offsets()?;
+    Ok(Array1::from_iter((1..tig_offsets.len()).map(|i| tig_offsets[i] - tig_offsets[i - 1])))
+}
+
+// Metadata of the matrix of two contigs at one resolution.
+struct ZoomData {
+    rsltn: u32,
+    zoom_index: i32,
+    sum: f64,
+    n_cells: u64,
+    block_column_count: u32,
+    blocks: Vec<(i32, i64, i32)>
+}
+
+impl ZoomData {
+    fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        write_str(out, BP_UNIT)?;
+        write_i32(out, self.zoom_index)?;
+        write_f32(out, self.sum as f32)?;
+        write_f32(out, self.n_cells as f32)?;
+        write_f32(out, 0.0)?; // standard deviation and 95th percentile are not used by readers
+        write_f32(out, 0.0)?;
+        write_i32(out, self.rsltn as i32)?;
+        write_i32(out, BLOCK_BIN_COUNT as i32)?;
+        write_i32(out, self.block_column_count as i32)?;
+        write_i32(out, self.blocks.len() as i32)?;
+        for &(number, pos, size) in self.blocks.iter() {
+            write_i32(out, number)?;
+            write_i64(out, pos)?;
+            write_i32(out, size)?;
+        }
+        Ok(())
+    }
+}
+
+// Contact of band of the first contig: the second contig, bins within contigs and count.
+type BandContact = (usize, u32, u32, u32);
+
+// Writes blocks of one resolution. Pixels are sorted by the first bin, so they are processed in bands of
+// the first contig. Observed counts by distance are collected for expected values.
+struct ZoomWriter<'a> {
+    rsltn: u32,
+    tig_bins: ArrayView1<'a, u32>,
+    weights: Option<ArrayView1<'a, f64>>,
+    zooms: BTreeMap<(usize, usize), ZoomData>,
+    observed: Vec<f64>,
+    norm_observed: Vec<f64>,
+    tig_observed: Vec<f64>,
+    tig_norm_observed: Vec<f64>,
+}
+
+impl<'a> ZoomWriter<'a> {
+    fn new(rsltn: u32, tig_bins: ArrayView1<'a, u32>, weights: Option<ArrayView1<'a, f64>>) -> ZoomWriter<'a> {
+        let max_bins = tig_bins.iter().copied().max().unwrap_or(0) as usize;
+        ZoomWriter {
+            rsltn,
+            tig_bins,
+            weights,
+            zooms: BTreeMap::new(),
+            observed: vec![0.0; max_bins],
+            norm_observed: vec![0.0; max_bins],
+            tig_observed: vec![0.0; tig_bins.len()],
+            tig_norm_observed: vec![0.0; tig_bins.len()],
+        }
+    }
+
+    fn write_blocks<W: Write>(&mut self, out: &mut CountingWriter<W>, grp: &ResGroup) -> Result<(), Box<dyn Error>> {
+        if grp.get_n_pixels() == 0 { return Ok(()); }
+        let bin_tigs = grp.get_bin_chr_ids()?;
+        let tig_offsets = grp.get_tigs_offsets()?;
+        let mut band_tig = None;
+        let mut band: Vec<BandContact> = Vec::new();
+
+        for (bins1, bins2, counts) in grp.get_raw_pixel_iter(PIXEL_CHUNKSIZE) {
+            for i in 0..counts.len() {
+                let (bin1, bin2, count) = (bins1[i] as usize, bins2[i] as usize, counts[i]);
+                let (tig1, tig2) = (bin_tigs[bin1] as usize, bin_tigs[bin2] as usize);
+                if band_tig != Some(tig1) {
+                    if let Some(tig) = band_tig { self.write_band(out, tig, &mut band)?; }
+                    band_tig = Some(tig1);
+                }
+
+                let (x, y) = (bin1 as u32 - tig_offsets[tig1], bin2 as u32 - tig_offsets[tig2]);
+                band.push((tig2, x, y, count));
+                if tig1 == tig2 {
+                    self.update_observed(tig1, (y - x) as usize, count as f64, bin1, bin2);
+                }
+            }
+        }
+        if let Some(tig) = band_tig { self.write_band(out, tig, &mut band)?; }
+        Ok(())
+    }
+
+    fn update_observed(&mut self, tig: usize, dist: usize, count: f64, bin1: usize, bin2: usize) {
+        self.observed[dist] += count;
+        self.tig_observed[tig] += count;
+        if let Some(weights) = self.weights {
+            let value = count * weights[bin1] * weights[bin2];
+            if value.is_finite() {
+                self.norm_observed[dist] += value;
+                self.tig_norm_observed[tig] += value;
+            }
+        }
+    }
+
+    fn write_band<W: Write>(&mut self, out: &mut CountingWriter<W>, tig1: usize, band: &mut Vec<BandContact>) -> io::Result<()> {
+        let (tig_bins, rsltn) = (self.tig_bins, self.rsltn);
+        let block_column_count = |tig2: usize| tig_bins[tig1].max(tig_bins[tig2]) / BLOCK_BIN_COUNT + 1;
+        let block_number = |tig2: usize, x: u32, y: u32| (y / BLOCK_BIN_COUNT) * block_column_count(tig2) + x / BLOCK_BIN_COUNT;
+        band.sort_unstable_by_key(|&(tig2, x, y, _)| (tig2, block_number(tig2, x, y), y, x));
+
+        let mut start = 0;
+        while start < band.len() {
+            let (tig2, x, y, _) = band[start];
+            let number = block_number(tig2, x, y);
+            let end = start + band[start..].iter()
+                .take_while(|&&(t, x, y, _)| t == tig2 && block_number(t, x, y) == number)
+                .count();
+
+            let pos = out.pos;
+            let size = write_block(out, &band[start..end])?;
+            let zoom = self.zooms.entry((tig1, tig2)).or_insert_with(|| ZoomData {
+                rsltn,
+                zoom_index: 0,
+                sum: 0.0,
+                n_cells: 0,
+                block_column_count: block_column_count(tig2),
+                blocks: Vec::new()
+            });
+            zoom.sum += band[start..end].iter().map(|c| c.3 as f64).sum::<f64>();
+            zoom.n_cells += (end - start) as u64;
+            zoom.blocks.push((number as i32, pos as i64, size as i32));
+            start = end;
+        }
+        band.clear();
+        Ok(())
+    }
+
+    // Normalization vectors of contigs are written into body, footer keeps their positions.
+    fn write_norm_vectors<W: Write>(&self, out: &mut CountingWriter<W>, footer: &mut Footer) -> io::Result<()> {
+        let weights = match self.weights {
+            Some(weights) => weights,
+            None => return Ok(())
+        };
+
+        let mut offset = 0;
+        for (tig, &n_bins) in self.tig_bins.iter().enumerate() {
+            let pos = out.pos;
+            write_i32(out, n_bins as i32)?;
+            for bin in offset..(offset + n_bins as usize) {
+                let weight = weights[bin];
+                write_f64(out, if weight.is_finite() && weight != 0.0 { 1.0 / weight } else { f64::NAN })?;
+            }
+            footer.norm_vectors.push((tig as i32 + 1, self.rsltn, pos as i64, (out.pos - pos) as i32));
+            offset += n_bins as usize;
+        }
+        Ok(())
+    }
+
+    // Expected count at distance d is the sum of intra-contig counts at distance d divided by the number of
+    // such pixels, factor of a contig is the ratio of its observed and expected sums.
+    fn save_expected_values(&self, footer: &mut Footer) {
+        let mut n_pixels = vec![0.0; self.observed.len()];
+        for &n_bins in self.tig_bins.iter() {
+            for (dist, n) in n_pixels.iter_mut().enumerate().take(n_bins as usize) {
+                *n += (n_bins as usize - dist) as f64;
+            }
+        }
+
+        let expected_values = |observed: &[f64], tig_observed: &[f64]| {
+            let values: Vec<f64> = observed.iter().zip(n_pixels.iter())
+                .map(|(&obs, &n)| if n > 0.0 { obs / n } else { 0.0 })
+                .collect();
+            let factors: Vec<(i32, f64)> = self.tig_bins.iter().enumerate().map(|(tig, &n_bins)| {
+                let expected: f64 = values.iter().take(n_bins as usize).enumerate()
+                    .map(|(dist, v)| v * (n_bins as usize - dist) as f64)
+                    .sum();
+                let factor = if expected > 0.0 && tig_observed[tig] > 0.0 { tig_observed[tig] / expected } else { 1.0 };
+                (tig as i32 + 1, factor)
+            }).collect();
+            ExpectedValues { rsltn: self.rsltn, values, factors }
+        };
+
+        footer.expected.push(expected_values(&self.observed, &self.tig_observed));
+        if self.weights.is_some() {
+            footer.norm_expected.push(expected_values(&self.norm_observed, &self.tig_norm_observed));
+        }
+    }
+}
+
+// Contacts of a block are grouped in rows by bin of the second contig, bins are relative to block offsets.
+fn write_block(out: &mut impl Write, contacts: &[BandContact]) -> io::Result<usize> {
+    let x_offset = contacts.iter().map(|c| c.1).min().unwrap_or(0);
+    let y_offset = contacts.iter().map(|c| c.2).min().unwrap_or(0);
+
+    let mut data = Vec::new();
+    write_i32(&mut data, contacts.len() as i32)?;
+    write_i32(&mut data, x_offset as i32)?;
+    write_i32(&mut data, y_offset as i32)?;
+    data.push(FLOAT_COUNTS);
+    data.push(LIST_OF_ROWS);
+
+    let mut rows: Vec<(u32, Vec<(u32, u32)>)> = Vec::new();
+    for &(_, x, y, count) in contacts.iter() {
+        match rows.last_mut() {
+            Some((row_y, cells)) if *row_y == y => cells.push((x, count)),
+            _ => rows.push((y, vec![(x, count)]))
+        }
+    }
+    write_i16(&mut data, rows.len() as i16)?;
+    for (y, cells) in rows.iter() {
+        write_i16(&mut data, (y - y_offset) as i16)?;
+        write_i16(&mut data, cells.len() as i16)?;
+        for &(x, count) in cells.iter() {
+            write_i16(&mut data, (x - x_offset) as i16)?;
+            write_f32(&mut data, count as f32)?;
+        }
+    }
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data.as_slice())?;
+    let compressed = encoder.finish()?;
+    out.write_all(compressed.as_slice())?;
+    Ok(compressed.len())
+}
+
+struct ExpectedValues {
+    rsltn: u32,
+    values: Vec<f64>,
+    factors: Vec<(i32, f64)>
+}
+
+impl ExpectedValues {
+    fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        write_str(out, BP_UNIT)?;
+        write_i32(out, self.rsltn as i32)?;
+        write_i32(out, self.values.len() as i32)?;
+        for &v in self.values.iter() {
+            write_f64(out, v)?;
+        }
+        write_i32(out, self.factors.len() as i32)?;
+        for &(tig, factor) in self.factors.iter() {
+            write_i32(out, tig)?;
+            write_f64(out, factor)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Footer {
+    master_index: Vec<(String, u64, i32)>,
+    expected: Vec<ExpectedValues>,
+    norm_expected: Vec<ExpectedValues>,
+    norm_vectors: Vec<(i32, u32, i64, i32)>,
+}
+
+impl Footer {
+    // Size of master index and expected values precedes them.
+    fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut data = Vec::new();
+        write_i32(&mut data, self.master_index.len() as i32)?;
+        for (key, pos, size) in self.master_index.iter() {
+            write_str(&mut data, key.as_str())?;
+            write_i64(&mut data, *pos as i64)?;
+            write_i32(&mut data, *size)?;
+        }
+        write_i32(&mut data, self.expected.len() as i32)?;
+        for expected in self.expected.iter() {
+            expected.write(&mut data)?;
+        }
+        write_i32(out, data.len() as i32)?;
+        out.write_all(data.as_slice())?;
+
+        write_i32(out, self.norm_expected.len() as i32)?;
+        for expected in self.norm_expected.iter() {
+            write_str(out, NORM_TYPE)?;
+            expected.write(out)?;
+        }
+        write_i32(out, self.norm_vectors.len() as i32)?;
+        for &(tig, rsltn, pos, size) in self.norm_vectors.iter() {
+            write_str(out, NORM_TYPE)?;
+            write_i32(out, tig)?;
+            write_str(out, BP_UNIT)?;
+            write_i32(out, rsltn as i32)?;
+            write_i64(out, pos)?;
+            write_i32(out, size)?;
+        }
+        Ok(())
+    }
+}
+
+// Keeps track of position in file, positions of blocks and matrices are saved in indexes.
+struct CountingWriter<W: Write> {
+    inner: W,
+    pos: u64
+}
+
+impl<W: Write> CountingWriter<W> {
+    fn new(inner: W) -> CountingWriter<W> {
+        CountingWriter { inner, pos: 0 }
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn write_i16(out: &mut impl Write, v: i16) -> io::Result<()> {
+    out.write_all(&v.to_le_bytes())
+}
+
+fn write_i32(out: &mut impl Write, v: i32) -> io::Result<()> {
+    out.write_all(&v.to_le_bytes())
+}
+
+fn write_i64(out: &mut impl Write, v: i64) -> io::Result<()> {
+    out.write_all(&v.to_le_bytes())
+}
+
+fn write_f32(out: &mut impl Write, v: f32) -> io::Result<()> {
+    out.write_all(&v.to_le_bytes())
+}
+
+fn write_f64(out: &mut impl Write, v: f64) -> io::Result<()> {
+    out.write_all(&v.to_le_bytes())
+}
+
+fn write_str(out: &mut impl Write, s: &str) -> io::Result<()> {
+    out.write_all(s.as_bytes())?;
+    out.write_all(&[0])
+}
+
+// Reader of .hic files, only matrices of base-pair resolutions are read. Contigs keep the order of the file
+// without "All" pseudo-contig.
+pub struct HicFile {
+    path: PathBuf,
+    version: i32,
+    tig_order: Array1<AsciiString>,
+    tig_lengths: Array1<u64>,
+    // Index in tig_order of each contig of the file.
+    tig_ids: Vec<Option<usize>>,
+    rslns: Vec<u32>,
+    // Indexes of contigs in the file and position of matrix metadata.
+    matrices: Vec<(usize, usize, u64)>,
+    // Type, index of contig in the file, resolution and position of normalization vectors.
+    norm_vectors: Vec<(String, usize, u32, u64)>,
+}
+
+// Checks magic string, so any file can be passed.
+pub fn is_hic_file(path: &Path) -> io::Result<bool> {
+    let mut magic = [0_u8; 4];
+    let mut f = File::open(path)?;
+    match f.read_exact(&mut magic) {
+        Ok(()) => Ok(&magic == HIC_MAGIC),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e)
+    }
+}
+
+impl HicFile {
+    pub fn open(path: &Path) -> Result<HicFile, Box<dyn Error>> {
+        let mut f = BufReader::new(File::open(path)?);
+        let mut magic = [0_u8; 4];
+        f.read_exact(&mut magic)?;
+        let version = read_i32(&mut f)?;
+        if &magic != HIC_MAGIC || !(HIC_VERSION..=LAST_HIC_VERSION).contains(&version) {
+            return Err(HicVersionError.into());
+        }
+        let is_v9 = version == LAST_HIC_VERSION;
+
+        let master_index_pos = read_i64(&mut f)? as u64;
+        read_str(&mut f)?; // genome id
+        if is_v9 {
+            read_i64(&mut f)?; // position and size of normalization vector index, it is read from footer
+            read_i64(&mut f)?;
+        }
+        for _ in 0..read_i32(&mut f)? {
+            read_str(&mut f)?;
+            read_str(&mut f)?;
+        }
+
+        let mut tig_order = Vec::new();
+        let mut tig_lengths = Vec::new();
+        let mut tig_ids = Vec::new();
+        for i in 0..read_i32(&mut f)? {
+            let name = read_str(&mut f)?;
+            let length = if is_v9 { read_i64(&mut f)? } else { read_i32(&mut f)? as i64 };
+            if i == 0 && name.eq_ignore_ascii_case(ALL_CHROM) {
+                tig_ids.push(None);
+                continue;
+            }
+            tig_ids.push(Some(tig_order.len()));
+            tig_order.push(AsciiString::from_ascii(name).map_err(|_| HicVersionError)?);
+            tig_lengths.push(length as u64);
+        }
+        let mut rslns = Vec::new();
+        for _ in 0..read_i32(&mut f)? {
+            rslns.push(read_i32(&mut f)? as u32);
+        }
+
+        f.seek(SeekFrom::Start(master_index_pos))?;
+        if is_v9 { read_i64(&mut f)?; } else { read_i32(&mut f)?; }
+        let mut matrices = Vec::new();
+        for _ in 0..read_i32(&mut f)? {
+            let key = read_str(&mut f)?;
+            let pos = read_i64(&mut f)? as u64;
+            read_i32(&mut f)?;
+            let mut chroms = key.split('_').map(|x| x.parse::<usize>());
+            match (chroms.next(), chroms.next()) {
+                (Some(Ok(chr1)), Some(Ok(chr2))) if chr1 < tig_ids.len() && chr2 < tig_ids.len() => {
+                    matrices.push((chr1, chr2, pos))
+                },
+                _ => return Err(HicVersionError.into())
+            }
+        }
+
+        for _ in 0..read_i32(&mut f)? {
+            skip_expected_values(&mut f, is_v9)?;
+        }
+        for _ in 0..read_i32(&mut f)? {
+            read_str(&mut f)?;
+            skip_expected_values(&mut f, is_v9)?;
+        }
+        let mut norm_vectors = Vec::new();
+        for _ in 0..read_i32(&mut f)? {
+            let norm_type = read_str(&mut f)?;
+            let chr = read_i32(&mut f)? as usize;
+            let unit = read_str(&mut f)?;
+            let rsltn = read_i32(&mut f)? as u32;
+            let pos = read_i64(&mut f)? as u64;
+            if is_v9 { read_i64(&mut f)?; } else { read_i32(&mut f)?; }
+            if unit == BP_UNIT && chr < tig_ids.len() {
+                norm_vectors.push((norm_type, chr, rsltn, pos));
+            }
+        }
+
+        Ok(HicFile {
+            path: PathBuf::from(path),
+            version,
+            tig_order: Array1::from(tig_order),
+            tig_lengths: Array1::from(tig_lengths),
+            tig_ids,
+            rslns,
+            matrices,
+            norm_vectors
+        })
+    }
+
+    pub fn get_resolutions(&self) -> Vec<u32> {
+        self.rslns.clone()
+    }
+
+    pub fn tig_order_view(&self) -> ArrayView1<AsciiString> {
+        self.tig_order.view()
+    }
+
+    pub fn lengths_view(&self) -> ArrayView1<u64> {
+        self.tig_lengths.view()
+    }
+
+    // Pixels with bins of contigs starting from tig_offsets, they are sorted by bin1 and bin2. Counts are
+    // rounded, contacts outside of contigs are skipped. Blocks are not ordered by bins in the file, so all
+    // pixels of the resolution are collected in memory (12 bytes per pixel) before sorting.
+    pub fn read_pixels(&self, rsltn: u32, tig_offsets: ArrayView1<u32>) -> Result<Vec<PixelT>, Box<dyn Error>> {
+        let mut f = BufReader::new(File::open(self.path.as_path())?);
+        let mut pixels: Vec<PixelT> = Vec::new();
+        let mut n_skipped = 0_usize;
+
+        for &(chr1, chr2, pos) in self.matrices.iter() {
+            let (tig1, tig2) = match (self.tig_ids[chr1], self.tig_ids[chr2]) {
+                (Some(tig1), Some(tig2)) => (tig1, tig2),
+                _ => continue
+            };
+            let n_bins1 = tig_offsets[tig1 + 1] - tig_offsets[tig1];
+            let n_bins2 = tig_offsets[tig2 + 1] - tig_offsets[tig2];
+
+            for (block_pos, block_size) in read_zoom_blocks(&mut f, pos, rsltn)? {
+                for (x, y, count) in self.read_block(&mut f, block_pos, block_size)? {
+                    if x >= n_bins1 || y >= n_bins2 {
+                        n_skipped += 1;
+                        continue;
+                    }
+                    let count = count.round();
+                    if count < 1.0 { continue; }
+
+                    let (bin1, bin2) = (tig_offsets[tig1] + x, tig_offsets[tig2] + y);
+                    pixels.push((bin1.min(bin2), bin1.max(bin2), count as u32));
+                }
+            }
+        }
+        if n_skipped > 0 {
+            println!("{} contacts of resolution {} are outside of contigs and skipped", n_skipped, rsltn);
+        }
+
+        pixels.sort_unstable_by_key(|p| (p.0, p.1));
+        pixels.dedup_by(|next, prev| {
+            if next.0 == prev.0 && next.1 == prev.1 { prev.2 += next.2; true } else { false }
+        });
+        Ok(pixels)
+    }
+
+    // Balancing weights are inverse of normalization vectors. Bins of contigs without vector get NaN.
+    pub fn read_weights(&self, rsltn: u32, tig_offsets: ArrayView1<u32>) -> Result<Option<Array1<f64>>, Box<dyn Error>> {
+        let norm_type = IMPORTED_NORM_TYPES.iter()
+            .find(|&&t| self.norm_vectors.iter().any(|v| v.0 == t && v.2 == rsltn));
+        let norm_type = match norm_type {
+            Some(&norm_type) => norm_type,
+            None => return Ok(None)
+        };
+
+        let mut f = BufReader::new(File::open(self.path.as_path())?);
+        let is_v9 = self.version == LAST_HIC_VERSION;
+        let mut weights = Array1::from_elem(tig_offsets[tig_offsets.len() - 1] as usize, f64::NAN);
+        for (_, chr, _, pos) in self.norm_vectors.iter().filter(|v| v.0 == norm_type && v.2 == rsltn) {
+            let tig = match self.tig_ids[*chr] {
+                Some(tig) => tig,
+                None => continue
+            };
+            let n_bins = (tig_offsets[tig + 1] - tig_offsets[tig]) as usize;
+
+            f.seek(SeekFrom::Start(*pos))?;
+            let n_values = if is_v9 { read_i64(&mut f)? as usize } else { read_i32(&mut f)? as usize };
+            for i in 0..n_values {
+                let value = if is_v9 { read_f32(&mut f)? as f64 } else { read_f64(&mut f)? };
+                if i < n_bins && value.is_finite() && value != 0.0 {
+                    weights[tig_offsets[tig] as usize + i] = 1.0 / value;
+                }
+            }
+        }
+        Ok(Some(weights))
+    }
+
+    // Contacts of block as bins within the first and the second contigs.
+    fn read_block(&self, f: &mut BufReader<File>, pos: u64, size: usize) -> io::Result<Vec<(u32, u32, f32)>> {
+        let mut compressed = vec![0_u8; size];
+        f.seek(SeekFrom::Start(pos))?;
+        f.read_exact(compressed.as_mut_slice())?;
+        let mut data = Vec::new();
+        ZlibDecoder::new(compressed.as_slice()).read_to_end(&mut data)?;
+
+        let mut r = data.as_slice();
+        let n_records = read_i32(&mut r)? as usize;
+        let x_offset = read_i32(&mut r)?;
+        let y_offset = read_i32(&mut r)?;
+        let is_float = read_u8(&mut r)? == FLOAT_COUNTS;
+        // Version 9 flags bins stored as i32 (useIntXPos and useIntYPos), version 8 bins are always i16.
+        let (is_short_x, is_short_y) = if self.version == LAST_HIC_VERSION {
+            (read_u8(&mut r)? == 0, read_u8(&mut r)? == 0)
+        } else {
+            (true, true)
+        };
+        let repr = read_u8(&mut r)?;
+
+        let read_count = |r: &mut &[u8]| -> io::Result<Option<f32>> {
+            if is_float {
+                let count = read_f32(r)?;
+                Ok(if count.is_nan() { None } else { Some(count) })
+            } else {
+                let count = read_i16(r)?;
+                Ok(if count == EMPTY_SHORT_COUNT { None } else { Some(count as f32) })
+            }
+        };
+        let read_bin = |r: &mut &[u8], is_short: bool| -> io::Result<i32> {
+            if is_short { Ok(read_i16(r)? as i32) } else { read_i32(r) }
+        };
+
+        let mut contacts = Vec::with_capacity(n_records);
+        if repr == LIST_OF_ROWS {
+            for _ in 0..read_bin(&mut r, is_short_y)? {
+                let y = y_offset + read_bin(&mut r, is_short_y)?;
+                for _ in 0..read_bin(&mut r, is_short_x)? {
+                    let x = x_offset + read_bin(&mut r, is_short_x)?;
+                    if let Some(count) = read_count(&mut r)? {
+                        contacts.push((x as u32, y as u32, count));
+                    }
+                }
+            }
+        } else if repr == DENSE {
+            let n_points = read_i32(&mut r)?;
+            let width = read_i16(&mut r)? as i32;
+            for i in 0..n_points {
+                if let Some(count) = read_count(&mut r)? {
+                    contacts.push(((x_offset + i % width) as u32, (y_offset + i / width) as u32, count));
+                }
+            }
+        } else {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unknown type of .hic block {}", repr)));
+        }
+        Ok(contacts)
+    }
+}
+
+// Positions and sizes of blocks of the matrix at base-pair resolution, empty if resolution is absent.
+fn read_zoom_blocks(f: &mut BufReader<File>, pos: u64, rsltn: u32) -> io::Result<Vec<(u64, usize)>> {
+    f.seek(SeekFrom::Start(pos))?;
+    read_i32(f)?;
+    read_i32(f)?;
+    for _ in 0..read_i32(f)? {
+        let unit = read_str(f)?;
+        read_i32(f)?; // zoom index
+        for _ in 0..4 { read_f32(f)?; }
+        let bin_size = read_i32(f)? as u32;
+        read_i32(f)?; // block bin count
+        read_i32(f)?; // block column count
+
+        let mut blocks = Vec::new();
+        for _ in 0..read_i32(f)? {
+            read_i32(f)?; // block number
+            let block_pos = read_i64(f)? as u64;
+            let block_size = read_i32(f)? as usize;
+            blocks.push((block_pos, block_size));
+        }
+        if unit == BP_UNIT && bin_size == rsltn {
+            return Ok(blocks);
+        }
+    }
+    Ok(Vec::new())
+}
+
+fn skip_expected_values(f: &mut impl Read, is_v9: bool) -> io::Result<()> {
+    read_str(f)?;
+    read_i32(f)?;
+    let n_values = if is_v9 { read_i64(f)? as u64 * 4 } else { read_i32(f)? as u64 * 8 };
+    io::copy(&mut f.by_ref().take(n_values), &mut io::sink())?;
+    let n_factors = read_i32(f)? as u64 * if is_v9 { 8 } else { 12 };
+    io::copy(&mut f.by_ref().take(n_factors), &mut io::sink())?;
+    Ok(())
+}
+
+fn read_u8(f: &mut impl Read) -> io::Result<u8> {
+    let mut buf = [0_u8; 1];
+    f.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_i16(f: &mut impl Read) -> io::Result<i16> {
+    let mut buf = [0_u8; 2];
+    f.read_exact(&mut buf)?;
+    Ok(i16::from_le_bytes(buf))
+}
+
+fn read_i32(f: &mut impl Read) -> io::Result<i32> {
+    let mut buf = [0_u8; 4];
+    f.read_exact(&mut buf)?;
+    Ok(i32::from_le_bytes(buf))
+}
+
+fn read_i64(f: &mut impl Read) -> io::Result<i64> {
+    let mut buf = [0_u8; 8];
+    f.read_exact(&mut buf)?;
+    Ok(i64::from_le_bytes(buf))
+}
+
+fn read_f32(f: &mut impl Read) -> io::Result<f32> {
+    let mut buf = [0_u8; 4];
+    f.read_exact(&mut buf)?;
+    Ok(f32::from_le_bytes(buf))
+}
+
+fn read_f64(f: &mut impl Read) -> io::Result<f64> {
+    let mut buf = [0_u8; 8];
+    f.read_exact(&mut buf)?;
+    Ok(f64::from_le_bytes(buf))
+}
+
+fn read_str(f: &mut impl Read) -> io::Result<String> {
+    let mut bytes = Vec::new();
+    loop {
+        let b = read_u8(f)?;
+        if b == 0 { break; }
+        bytes.push(b);
+    }
+    String::from_utf8(bytes).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Incorrect string in .hic file"))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn tmp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("hic_format_{}_{}", name, std::process::id()))
+    }
+
+    fn hic_file(path: &Path, version: i32) -> HicFile {
+        HicFile {
+            path: PathBuf::from(path),
+            version,
+            tig_order: Array1::from(Vec::new()),
+            tig_lengths: Array1::from(Vec::new()),
+            tig_ids: Vec::new(),
+            rslns: Vec::new(),
+            matrices: Vec::new(),
+            norm_vectors: Vec::new()
+        }
+    }
+
+    // Compresses block data into a file as the only block of it.
+    fn read_block_data(name: &str, version: i32, data: &[u8]) -> Vec<(u32, u32, f32)> {
+        let path = tmp_path(name);
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        let compressed = encoder.finish().unwrap();
+        fs::write(&path, compressed.as_slice()).unwrap();
+
+        let mut f = BufReader::new(File::open(&path).unwrap());
+        let contacts = hic_file(&path, version).read_block(&mut f, 0, compressed.len()).unwrap();
+        fs::remove_file(&path).unwrap();
+        contacts
+    }
+
+    // Block of version 9 with list of rows: 2 rows, the first of 2 cells and the second with an empty cell.
+    fn v9_block(use_int_x: bool, use_int_y: bool) -> Vec<u8> {
+        let write_bin = |data: &mut Vec<u8>, v: i32, use_int: bool| {
+            if use_int { write_i32(data, v).unwrap() } else { write_i16(data, v as i16).unwrap() }
+        };
+        let mut data = Vec::new();
+        write_i32(&mut data, 3).unwrap();
+        write_i32(&mut data, 1000).unwrap();
+        write_i32(&mut data, 2000).unwrap();
+        data.push(FLOAT_COUNTS);
+        data.push(use_int_x as u8);
+        data.push(use_int_y as u8);
+        data.push(LIST_OF_ROWS);
+        write_bin(&mut data, 2, use_int_y);
+        write_bin(&mut data, 0, use_int_y);
+        write_bin(&mut data, 2, use_int_x);
+        write_bin(&mut data, 1, use_int_x);
+        write_f32(&mut data, 2.5).unwrap();
+        write_bin(&mut data, 7, use_int_x);
+        write_f32(&mut data, 1.0).unwrap();
+        write_bin(&mut data, 5, use_int_y);
+        write_bin(&mut data, 1, use_int_x);
+        write_bin(&mut data, 3, use_int_x);
+        write_f32(&mut data, f32::NAN).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_block_round_trip() {
+        let contacts: Vec<BandContact> = vec![(0, 5, 3, 2), (0, 9, 3, 1), (0, 7, 4, 10), (0, 1200, 4, 3)];
+        let mut data = Vec::new();
+        let size = write_block(&mut data, &contacts).unwrap();
+        assert_eq!(size, data.len());
+
+        let path = tmp_path("block");
+        fs::write(&path, data.as_slice()).unwrap();
+        let mut f = BufReader::new(File::open(&path).unwrap());
+        let read = hic_file(&path, HIC_VERSION).read_block(&mut f, 0, size).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(read, vec![(5, 3, 2.0), (9, 3, 1.0), (7, 4, 10.0), (1200, 4, 3.0)]);
+    }
+
+    // Two contigs of 3 and 2 bins at resolution 1000, pixels are given by bins of the matrix. Blocks are
+    // written by bands of the first contig as in write_hic_file.
+    #[test]
+    fn test_hic_file_round_trip() {
+        let rsltn = 1000;
+        let tig_order = Array1::from(vec![AsciiString::from_ascii("ctg1").unwrap(),
+                                          AsciiString::from_ascii("ctg22").unwrap()]);
+        let tig_lengths = Array1::from(vec![2500_u64, 1200]);
+        let tig_offsets = Array1::from(vec![0_u32, 3, 5]);
+        let tig_bins = Array1::from(vec![3_u32, 2]);
+        let bin_tigs = [0, 0, 0, 1, 1];
+        let weights = Array1::from(vec![1.0, 0.5, 2.0, f64::NAN, 0.25]);
+        let pixels: Vec<PixelT> = vec![(0, 0, 5), (0, 1, 3), (0, 3, 1), (1, 1, 4), (2, 4, 2), (3, 3, 6), (4, 4, 1)];
+
+        let path = tmp_path("hic_file");
+        let mut out = CountingWriter::new(BufWriter::new(File::create(&path).unwrap()));
+        write_header(&mut out, tig_order.view(), tig_lengths.view(), &[rsltn]).unwrap();
+        let mut footer = Footer::default();
+        let mut writer = ZoomWriter::new(rsltn, tig_bins.view(), Some(weights.view()));
+        for tig1 in 0..2 {
+            let mut band: Vec<BandContact> = Vec::new();
+            for &(bin1, bin2, count) in pixels.iter().filter(|p| bin_tigs[p.0 as usize] == tig1) {
+                let tig2 = bin_tigs[bin2 as usize];
+                let (x, y) = (bin1 - tig_offsets[tig1], bin2 - tig_offsets[tig2]);
+                band.push((tig2, x, y, count));
+                if tig1 == tig2 {
+                    writer.update_observed(tig1, (y - x) as usize, count as f64, bin1 as usize, bin2 as usize);
+                }
+            }
+            writer.write_band(&mut out, tig1, &mut band).unwrap();
+        }
+        writer.write_norm_vectors(&mut out, &mut footer).unwrap();
+        writer.save_expected_values(&mut footer);
+        let zooms = writer.zooms.into_iter().map(|(tigs, zoom)| (tigs, vec![zoom])).collect();
+        write_footer(out, footer, &zooms).unwrap();
+
+        assert!(is_hic_file(&path).unwrap());
+        let hic_file = HicFile::open(&path).unwrap();
+        assert_eq!(hic_file.tig_order_view(), tig_order.view());
+        assert_eq!(hic_file.lengths_view(), tig_lengths.view());
+        assert_eq!(hic_file.get_resolutions(), vec![rsltn]);
+        assert_eq!(hic_file.matrices.len(), 3);
+
+        assert_eq!(hic_file.read_pixels(rsltn, tig_offsets.view()).unwrap(), pixels);
+        assert!(hic_file.read_pixels(rsltn * 10, tig_offsets.view()).unwrap().is_empty());
+
+        let read_weights = hic_file.read_weights(rsltn, tig_offsets.view()).unwrap().unwrap();
+        assert_eq!(read_weights.len(), weights.len());
+        for (w, expected) in read_weights.iter().zip(weights.iter()) {
+            assert!(w == expected || (w.is_nan() && expected.is_nan()));
+        }
+        assert!(hic_file.read_weights(rsltn * 10, tig_offsets.view()).unwrap().is_none());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_read_v9_block() {
+        let expected = vec![(1001, 2000, 2.5), (1007, 2000, 1.0)];
+        assert_eq!(read_block_data("v9_short", LAST_HIC_VERSION, &v9_block(false, false)), expected);
+        assert_eq!(read_block_data("v9_int", LAST_HIC_VERSION, &v9_block(true, true)), expected);
+        assert_eq!(read_block_data("v9_int_x", LAST_HIC_VERSION, &v9_block(true, false)), expected);
+        assert_eq!(read_block_data("v9_int_y", LAST_HIC_VERSION, &v9_block(false, true)), expected);
+    }
+
+    #[test]
+    fn test_read_v9_dense_block() {
+        let mut data = Vec::new();
+        write_i32(&mut data, 3).unwrap();
+        write_i32(&mut data, 10).unwrap();
+        write_i32(&mut data, 20).unwrap();
+        data.push(0); // short counts
+        data.push(0);
+        data.push(0);
+        data.push(DENSE);
+        write_i32(&mut data, 4).unwrap();
+        write_i16(&mut data, 2).unwrap();
+        for &count in [3, EMPTY_SHORT_COUNT, 1, 4].iter() {
+            write_i16(&mut data, count).unwrap();
+        }
+        assert_eq!(read_block_data("v9_dense", LAST_HIC_VERSION, &data),
+                   vec![(10, 20, 3.0), (10, 21, 1.0), (11, 21, 4.0)]);
+    }
+}
@@ -8,8 +8,10 @@ mod builders;
 mod selector;
 mod utils;
 mod balancer;
+mod hic_format;
+mod lzf;
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::error::Error;
 use self::builders::matrix_builder;
 
@@ -17,6 +19,7 @@ use self::builders::matrix_builder;
 pub use self::res_group::ResGroup;
 pub use self::matrix::Matrix;
 pub use self::balancer::Strategy;
+pub use self::writer::Compression;
 
 
 
@@ -32,11 +35,24 @@ pub use self::balancer::Strategy;
 //     Ok(matrix)
 // }
 
-pub fn create_matrix_from_pairs(pairs_file: &Path, tig_length_file: &Path,
+// Contig lengths are taken from the file if it is provided, otherwise from #chromsize lines of pairs header.
+// Pixels are aggregated within memory budget (e.g. 512M, 2G), the rest is spilled into temporary directory.
+pub fn create_matrix_from_pairs(pairs_file: &Path, tig_length_file: Option<&Path>,
                                 matrix_file: &Path, rslns: &[u32],
-                                strategy: &Strategy) -> Result<(), Box<dyn Error>> {
-    let ord_tig_lengths = utils::parse_tig_lengths(tig_length_file)?;
-    matrix_builder::build_from_pairs_multi_res(pairs_file, matrix_file, &ord_tig_lengths, rslns, strategy)?;
+                                strategy: &Strategy, memory: &str,
+                                tmp_dir: Option<&Path>, compression: &Compression) -> Result<(), Box<dyn Error>> {
+    let mem_limit = hic_convertor::parse_memory_size(memory)?;
+    let tmp_dir = tmp_dir.map(PathBuf::from).unwrap_or_else(std::env::temp_dir);
+    let ord_tig_lengths = match tig_length_file {
+        Some(path) => utils::parse_tig_lengths(path)?,
+        None => {
+            let layout = utils::parse_pairs_header(pairs_file)?;
+            if layout.chromsizes.is_empty() { return Err(errors::PairsHeaderError.into()); }
+            layout.chromsizes
+        }
+    };
+    matrix_builder::build_from_pairs_multi_res(pairs_file, matrix_file, &ord_tig_lengths, rslns, strategy,
+                                               mem_limit, tmp_dir.as_path(), compression)?;
     Ok(())
 }
 
@@ -45,6 +61,22 @@ pub use self::builders::matrix_builder::balance;
 
 pub use self::builders::matrix_builder::zoom;
 
+// Writes all resolutions of matrix into Juicer .hic file (v8), balancing weights become normalization vectors.
+pub fn export_hic(matrix_file: &Path, hic_file: &Path) -> Result<(), Box<dyn Error>> {
+    let matrix = Matrix::from_hdf_file(matrix_file)?;
+    hic_format::write_hic_file(&matrix, hic_file)
+}
+
+// Converts Juicer .hic file or cooler file of another pipeline into matrix file of our layout,
+// format is detected by the content of the file.
+pub fn import_matrix(in_file: &Path, matrix_file: &Path) -> Result<(), Box<dyn Error>> {
+    if hic_format::is_hic_file(in_file)? {
+        matrix_builder::import_from_hic(in_file, matrix_file)
+    } else {
+        matrix_builder::import_from_cooler(in_file, matrix_file)
+    }
+}
+
 
 
 
@@ -0,0 +1,238 @@
+use std::ffi::c_void;
+use std::os::raw::{c_char, c_int, c_uint};
+use std::ptr;
+use std::slice;
+
+use hdf5::h5check;
+use hdf5_sys::h5z::{H5Z_class2_t, H5Z_filter_t, H5Zfilter_avail, H5Zregister, H5Z_CLASS_T_VERS, H5Z_FLAG_REVERSE};
+
+// LZF filter of h5py (https://github.com/h5py/h5py/tree/master/lzf), chunks are compatible with it, so
+// datasets are readable by h5py and cooler. The filter is not a part of HDF5 library, it is registered here.
+pub const LZF_FILTER: H5Z_filter_t = 32000;
+const LZF_NAME: &[u8] = b"lzf\0";
+// Versions of h5py filter and liblzf which are saved into parameters of the filter as h5py does.
+pub const LZF_FILTER_VERSION: c_uint = 4;
+pub const LZF_VERSION: c_uint = 0x0105;
+
+const HASH_LOG: usize = 14;
+const MAX_LITERAL: usize = 1 << 5;
+const MAX_OFFSET: usize = 1 << 13;
+const MAX_MATCH: usize = (1 << 8) + (1 << 3);
+
+#[derive(Debug, PartialEq)]
+pub enum LzfError {
+    OutputTooSmall,
+    Corrupted,
+}
+
+// Registers the filter in HDF5 library once per process, datasets with it are then written and read as usual.
+pub fn register_filter() -> hdf5::Result<()> {
+    hdf5::sync::sync(|| unsafe {
+        if h5check(H5Zfilter_avail(LZF_FILTER))? > 0 {
+            return Ok(());
+        }
+        let class = H5Z_class2_t {
+            version: H5Z_CLASS_T_VERS as c_int,
+            id: LZF_FILTER,
+            encoder_present: 1,
+            decoder_present: 1,
+            name: LZF_NAME.as_ptr() as *const c_char,
+            can_apply: None,
+            set_local: None,
+            filter: Some(lzf_filter),
+        };
+        h5check(H5Zregister(&class as *const H5Z_class2_t as *const c_void)).map(|_| ())
+    })
+}
+
+// Callback of HDF5 filter pipeline. Buffer of chunk is replaced by a new one allocated by malloc, since HDF5
+// frees it by free. Zero is returned on failure, then an incompressible chunk is stored as is (filter is optional).
+extern "C" fn lzf_filter(flags: c_uint, cd_nelmts: usize, cd_values: *const c_uint, nbytes: usize,
+                         buf_size: *mut usize, buf: *mut *mut c_void) -> usize {
+    let input = unsafe { slice::from_raw_parts(*buf as *const u8, nbytes) };
+    let output = if flags & H5Z_FLAG_REVERSE == 0 {
+        match compress(input, nbytes.saturating_sub(1)) {
+            Some(output) => output,
+            None => return 0
+        }
+    } else {
+        // Size of chunk is saved in the third parameter, otherwise the buffer grows until chunk fits into it.
+        let mut out_size = unsafe {
+            if cd_nelmts >= 3 && *cd_values.add(2) > 0 { *cd_values.add(2) as usize } else { *buf_size }
+        };
+        loop {
+            match decompress(input, out_size) {
+                Ok(output) => break output,
+                Err(LzfError::OutputTooSmall) => out_size = out_size.max(1) * 2,
+                Err(LzfError::Corrupted) => return 0
+            }
+        }
+    };
+
+    unsafe {
+        let out_buf = libc::malloc(output.len().max(1)) as *mut u8;
+        if out_buf.is_null() {
+            return 0;
+        }
+        ptr::copy_nonoverlapping(output.as_ptr(), out_buf, output.len());
+        libc::free(*buf);
+        *buf = out_buf as *mut c_void;
+        *buf_size = output.len().max(1);
+    }
+    output.len()
+}
+
+// Compresses data in liblzf format, None is returned if compressed data is longer than max_len.
+pub fn compress(input: &[u8], max_len: usize) -> Option<Vec<u8>> {
+    let mut output = Vec::with_capacity(max_len.min(input.len()));
+    // Positions (plus one) of the last occurrences of 3-byte sequences by their hash.
+    let mut table = vec![0_usize; 1 << HASH_LOG];
+    let mut literal_start = 0;
+    let mut pos = 0;
+
+    while pos + 2 < input.len() {
+        let hash = hash(&input[pos..(pos + 3)]);
+        let candidate = table[hash];
+        table[hash] = pos + 1;
+        if candidate > 0 && pos - candidate < MAX_OFFSET && input[(candidate - 1)..(candidate + 2)] == input[pos..(pos + 3)] {
+            let reference = candidate - 1;
+            let max_match = MAX_MATCH.min(input.len() - pos);
+            let mut len = 3;
+            while len < max_match && input[reference + len] == input[pos + len] {
+                len += 1;
+            }
+
+            push_literals(&mut output, &input[literal_start..pos]);
+            let offset = pos - reference - 1;
+            if len - 2 < 7 {
+                output.push((((len - 2) << 5) + (offset >> 8)) as u8);
+            } else {
+                output.push(((7 << 5) + (offset >> 8)) as u8);
+                output.push((len - 2 - 7) as u8);
+            }
+            output.push(offset as u8);
+            pos += len;
+            literal_start = pos;
+        } else {
+            pos += 1;
+        }
+        if output.len() > max_len {
+            return None;
+        }
+    }
+
+    push_literals(&mut output, &input[literal_start..]);
+    if output.len() > max_len { None } else { Some(output) }
+}
+
+// Decompresses data in liblzf format, output must not be longer than max_len.
+pub fn decompress(input: &[u8], max_len: usize) -> Result<Vec<u8>, LzfError> {
+    let mut output: Vec<u8> = Vec::with_capacity(max_len);
+    let mut pos = 0;
+
+    while pos < input.len() {
+        let ctrl = input[pos] as usize;
+        pos += 1;
+        if ctrl < MAX_LITERAL {
+            let len = ctrl + 1;
+            if pos + len > input.len() {
+                return Err(LzfError::Corrupted);
+            }
+            if output.len() + len > max_len {
+                return Err(LzfError::OutputTooSmall);
+            }
+            output.extend_from_slice(&input[pos..(pos + len)]);
+            pos += len;
+        } else {
+            let mut len = ctrl >> 5;
+            if len == 7 {
+                len += *input.get(pos).ok_or(LzfError::Corrupted)? as usize;
+                pos += 1;
+            }
+            len += 2;
+            let offset = ((ctrl & 0x1f) << 8) + *input.get(pos).ok_or(LzfError::Corrupted)? as usize + 1;
+            pos += 1;
+            if offset > output.len() {
+                return Err(LzfError::Corrupted);
+            }
+            if output.len() + len > max_len {
+                return Err(LzfError::OutputTooSmall);
+            }
+            // Reference may overlap with copied bytes, so they are copied one by one.
+            let start = output.len() - offset;
+            for i in start..(start + len) {
+                output.push(output[i]);
+            }
+        }
+    }
+    Ok(output)
+}
+
+fn push_literals(output: &mut Vec<u8>, literals: &[u8]) {
+    for run in literals.chunks(MAX_LITERAL) {
+        output.push((run.len() - 1) as u8);
+        output.extend_from_slice(run);
+    }
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let v = ((bytes[0] as usize) << 16) | ((bytes[1] as usize) << 8) | bytes[2] as usize;
+    (v.wrapping_mul(2654435761) >> 8) & ((1 << HASH_LOG) - 1)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(input: &[u8]) -> Vec<u8> {
+        let compressed = compress(input, input.len() * 2 + 16).unwrap();
+        decompress(&compressed, input.len()).unwrap()
+    }
+
+    // Pseudo-random bytes (xorshift) without repeats to compress.
+    fn noise() -> Vec<u8> {
+        let mut x = 88172645463325252_u64;
+        (0..5000).map(|_| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            (x >> 32) as u8
+        }).collect()
+    }
+
+    // Columns of cooler are sorted integers, they are well compressed.
+    fn pixel_column() -> Vec<u8> {
+        (0..10000_u32).flat_map(|i| (i / 7).to_le_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let column = pixel_column();
+        assert!(compress(&column, column.len()).unwrap().len() < column.len() / 2);
+        assert_eq!(round_trip(&column), column);
+
+        let text = b"abcabcabcabcabcabcabcd, long runs: aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".repeat(20);
+        assert_eq!(round_trip(&text), text);
+        assert_eq!(round_trip(&noise()), noise());
+        assert_eq!(round_trip(&[]), Vec::<u8>::new());
+        assert_eq!(round_trip(&[1, 2]), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_incompressible() {
+        assert!(compress(&noise(), noise().len() - 1).is_none());
+    }
+
+    // Blocks produced by liblzf: a literal run and back references of short and long matches.
+    #[test]
+    fn test_decompress_liblzf() {
+        assert_eq!(decompress(&[2, b'a', b'b', b'c', 0x20, 2], 6).unwrap(), b"abcabc".to_vec());
+        let long = decompress(&[0, b'x', 0xe0, 3, 0], 13).unwrap();
+        assert_eq!(long, vec![b'x'; 13]);
+
+        assert!(decompress(&[2, b'a', b'b', b'c', 0x20, 2], 5) == Err(LzfError::OutputTooSmall));
+        assert!(decompress(&[0, b'x', 0x20, 5], 10) == Err(LzfError::Corrupted));
+        assert!(decompress(&[5, b'a'], 10) == Err(LzfError::Corrupted));
+    }
+}
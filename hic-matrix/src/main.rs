@@ -4,7 +4,7 @@ use std::path::Path;
 
 use fern;
 use clap::{Arg, App, SubCommand};
-use hic_matrix::{zoom, Strategy, balance, create_matrix_from_pairs};
+use hic_matrix::{zoom, Strategy, Compression, balance, create_matrix_from_pairs, export_hic, import_matrix};
 
 
 fn setup_logging(verbosity: u64, log_file: &Path) -> Result<(), fern::InitError> {
@@ -55,7 +55,7 @@ fn matrix_arg() -> Arg<'static, 'static> {
         .value_name("FILE")
         .takes_value(true)
         .required(true)
-        .help("Matrix file in specific hdf5 format.")
+        .help("Matrix file in cooler format (.mcool, or .cool for a single resolution).")
 }
 
 fn rslns_arg(h: &'static str) -> Arg<'static, 'static> {
@@ -81,6 +81,24 @@ fn strategy_arg() -> Arg<'static, 'static> {
         .help("Balancing strategy:. ICGW - iterative correction genome-wide, LEN - resolution size")
 }
 
+fn compression_arg() -> Arg<'static, 'static> {
+    Arg::<'static, 'static>::with_name("compression")
+        .short("c")
+        .long("compression")
+        .value_name("STR")
+        .takes_value(true)
+        .required(false)
+        .help("Compression of HDF5 datasets: none, lzf, gzip or gzip:<level> (level from 0 to 9). Default is gzip:6.")
+}
+
+fn no_shuffle_arg() -> Arg<'static, 'static> {
+    Arg::<'static, 'static>::with_name("no_shuffle")
+        .long("no_shuffle")
+        .takes_value(false)
+        .required(false)
+        .help("Do not shuffle bytes of HDF5 datasets before compression.")
+}
+
 fn parse_rslns_arg(arg: Option<clap::Values>) -> Vec<u32> {
     arg.expect("List of resolutions must be provided")
         .into_iter()
@@ -102,7 +120,7 @@ fn main() -> Result<(), Box<dyn Error>> {
                         .value_name("FILE")
                         .takes_value(true)
                         .required(true)
-                        .help("A file with Hi-C pairs. File must be tab separated.
+                        .help("A file with Hi-C pairs in 4DN pairs format or tab separated file without header.
                                     1 col - read name,
                                     2 col - first contig,
                                     3 col - first coordinate,
@@ -117,13 +135,34 @@ fn main() -> Result<(), Box<dyn Error>> {
                         .long("lengts")
                         .value_name("FILE")
                         .takes_value(true)
-                        .required(true)
+                        .required(false)
                         .help("File with contig lengths. First column is tig name, \
-                                second one is length.")
+                                second one is length. If it is not provided, \
+                                lengths are taken from #chromsize lines of pairs file.")
                 )
                 .arg( rslns_arg("List of matrix resolutions") )
                 .arg( matrix_arg() )
                 .arg( strategy_arg() )
+                .arg(
+                    Arg::with_name("mem")
+                        .short("M")
+                        .long("memory")
+                        .value_name("STR")
+                        .takes_value(true)
+                        .required(false)
+                        .help("The amount of RAM memory for aggregating pixels (e.g. 512M, 2G).")
+                )
+                .arg(
+                    Arg::with_name("tmpdir")
+                        .short("d")
+                        .long("tmpdir")
+                        .value_name("PATH")
+                        .takes_value(true)
+                        .required(false)
+                        .help("Directory for storing temporary files.")
+                )
+                .arg( compression_arg() )
+                .arg( no_shuffle_arg() )
         ).subcommand(
             SubCommand::with_name("balance")
                 .arg( matrix_arg() )
@@ -134,6 +173,38 @@ fn main() -> Result<(), Box<dyn Error>> {
             SubCommand::with_name("zoom")
                 .arg(matrix_arg() )
                 .arg( rslns_arg("New matrix resolutions (it must be divisable by existed resolutions in matrix)") )
+                .arg( compression_arg() )
+                .arg( no_shuffle_arg() )
+        )
+        .subcommand(
+            SubCommand::with_name("export-hic")
+                .about("Writes all resolutions of matrix into Juicer .hic file (version 8).")
+                .arg( matrix_arg() )
+                .arg(
+                    Arg::with_name("hic")
+                        .short("o")
+                        .long("hic")
+                        .value_name("FILE")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Output .hic file. Balancing weights are written as KR normalization vectors.")
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("import")
+                .about("Converts Juicer .hic file (version 8 or 9) or cooler file of another tool into matrix file.")
+                .arg(
+                    Arg::with_name("input")
+                        .short("i")
+                        .long("input")
+                        .value_name("FILE")
+                        .takes_value(true)
+                        .required(true)
+                        .help(".hic, .mcool or .cool file. Normalization vectors of .hic file (KR, SCALE or VC) \
+                                become balancing weights. Each resolution of .hic file is loaded into memory \
+                                (12 bytes per pixel).")
+                )
+                .arg( matrix_arg() )
         )
         .get_matches();
 
@@ -142,11 +213,16 @@ fn main() -> Result<(), Box<dyn Error>> {
         ("build", Some(build_matches)) => {
             setup_logging(1, "matrix.log".as_ref()).expect("failed to initialize logging.");
             let pairs_file = Path::new(build_matches.value_of("pairs").expect("Pairs file must be provided."));
-            let tig_length_file = Path::new(build_matches.value_of("lengths").expect("File with contig lengths must be provided."));
+            let tig_length_file = build_matches.value_of("lengths").map(Path::new);
             let rslns: Vec<u32> = parse_rslns_arg(build_matches.values_of("rslns") );
             let matrix_file = Path::new(build_matches.value_of("matrix").expect("Matrix file must be provided."));
             let strategy = Strategy::from_option(build_matches.value_of("strategy"));
-            create_matrix_from_pairs(pairs_file, tig_length_file, matrix_file, &rslns, &strategy)?;
+            let mem = build_matches.value_of("mem").unwrap_or("2G");
+            let tmp_dir = build_matches.value_of("tmpdir").map(Path::new);
+            let compression = Compression::from_option(build_matches.value_of("compression"),
+                                                       !build_matches.is_present("no_shuffle"))?;
+            create_matrix_from_pairs(pairs_file, tig_length_file, matrix_file, &rslns, &strategy, mem, tmp_dir,
+                                     &compression)?;
         }
         ("balance", Some(bal_matches)) => {
             setup_logging(1, "matrix.log".as_ref()).expect("failed to initialize logging.");
@@ -159,7 +235,21 @@ fn main() -> Result<(), Box<dyn Error>> {
             setup_logging(1, "matrix.log".as_ref()).expect("failed to initialize logging.");
             let matrix_file = Path::new(zoom_matches.value_of("matrix").expect("Matrix file must be provided."));
             let rslns: Vec<u32> = parse_rslns_arg(zoom_matches.values_of("rslns") );
-            zoom(matrix_file, &rslns)?;
+            let compression = Compression::from_option(zoom_matches.value_of("compression"),
+                                                       !zoom_matches.is_present("no_shuffle"))?;
+            zoom(matrix_file, &rslns, &compression)?;
+        }
+        ("export-hic", Some(export_matches)) => {
+            setup_logging(1, "matrix.log".as_ref()).expect("failed to initialize logging.");
+            let matrix_file = Path::new(export_matches.value_of("matrix").expect("Matrix file must be provided."));
+            let hic_file = Path::new(export_matches.value_of("hic").expect("Output .hic file must be provided."));
+            export_hic(matrix_file, hic_file)?;
+        }
+        ("import", Some(import_matches)) => {
+            setup_logging(1, "matrix.log".as_ref()).expect("failed to initialize logging.");
+            let in_file = Path::new(import_matches.value_of("input").expect("Input file must be provided."));
+            let matrix_file = Path::new(import_matches.value_of("matrix").expect("Matrix file must be provided."));
+            import_matrix(in_file, matrix_file)?;
         }
         ("", None) => eprintln!("None subcommand was used. See help for available one."),
         _ => unreachable!(),
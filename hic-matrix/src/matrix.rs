@@ -8,7 +8,7 @@ use std::error::Error;
 use super::res_group::ResGroup;
 use super::reader::MatrixReader;
 use super::balancer::{Balancer, Strategy};
-use super::writer::MatrixWriter;
+use super::writer::{MatrixWriter, Compression};
 use super::builders::zoom_builder::ZoomBuilder;
 use super::errors::MatrixResolutionError;
 
@@ -21,6 +21,7 @@ pub struct Matrix {
     tig_order: Array1<AsciiString>,
     tig_lengths: Array1<u64>,
     file_path: PathBuf,
+    read_only: bool,
 }
 
 // TODO write code that checks that resolution exists
@@ -32,20 +33,31 @@ impl<'a> Matrix {
             name2order: Default::default(),
             tig_order: Default::default(),
             tig_lengths: Default::default(),
-            file_path: Default::default()
+            file_path: Default::default(),
+            read_only: false
         }
     }
 
     pub fn from_hdf_file(file_path: &Path) -> hdf5::Result<Matrix> {
-        let reader = MatrixReader::new(file_path)?;
-        let (tig_order, tig_lengths) = reader.read_chroms_info()?;
+        Matrix::open(file_path, false)
+    }
+
+    // Matrix of a file which is only read, e.g. cooler file of another tool which is imported.
+    pub fn from_hdf_file_in_reading_mode(file_path: &Path) -> hdf5::Result<Matrix> {
+        Matrix::open(file_path, true)
+    }
+
+    fn open(file_path: &Path, read_only: bool) -> hdf5::Result<Matrix> {
         let mut matrix = Matrix {
-            resolutions: AHashMap::default(),
-            name2order: tig_order.iter().enumerate().map(|(i, s)| (s.clone(), i)).collect(),
-            tig_order,
-            tig_lengths,
-            file_path: PathBuf::from(file_path)
+            file_path: PathBuf::from(file_path),
+            read_only,
+            ..Matrix::new()
         };
+        let reader = matrix.open_reader()?;
+        let (tig_order, tig_lengths) = reader.read_chroms_info()?;
+        matrix.name2order = tig_order.iter().enumerate().map(|(i, s)| (s.clone(), i)).collect();
+        matrix.tig_order = tig_order;
+        matrix.tig_lengths = tig_lengths;
 
         let resolutions = reader.read_resolutions()?;
         for res in resolutions.into_iter() {
@@ -84,13 +96,14 @@ impl<'a> Matrix {
         }
     }
 
-    pub fn zoom(&mut self, from_rstln: u32, to_rstln: u32) -> Result<(), Box<dyn Error>> {
+    pub fn zoom(&mut self, from_rstln: u32, to_rstln: u32, compression: &Compression) -> Result<(), Box<dyn Error>> {
         println!("Zooming matrix from {} to {}", from_rstln, to_rstln);
         match self.resolutions.get(&from_rstln) {
             Some(from_grp) => {
                 {
                     let builder = ZoomBuilder::new(from_grp, self.tig_lengths.view(), to_rstln, ZOOM_CHUNKSIZE);
-                    let writer = MatrixWriter::new_in_appending_mode(self.file_path.as_path())?;
+                    let writer = MatrixWriter::update_compression(
+                        MatrixWriter::new_in_appending_mode(self.file_path.as_path())?, *compression);
                     writer.write_resolution_group(&builder)?;
                 }
                 self.register_new_resolution(to_rstln)?;
@@ -134,12 +147,20 @@ impl<'a> Matrix {
 
     fn register_new_resolution(&mut self, rstln: u32) -> hdf5::Result<()> {
         println!("We are registering new resolution {}", rstln);
-        let reader = MatrixReader::new(self.file_path.as_path())?;
+        let reader = self.open_reader()?;
         let res_group_reader = reader.get_res_group_reader(rstln)?;
         let res_group = ResGroup::new(rstln, res_group_reader)?;
         self.resolutions.insert(rstln, res_group);
         Ok(())
     }
+
+    fn open_reader(&self) -> hdf5::Result<MatrixReader> {
+        if self.read_only {
+            MatrixReader::new_in_reading_mode(self.file_path.as_path())
+        } else {
+            MatrixReader::new(self.file_path.as_path())
+        }
+    }
 }
 
 //    pub fn balance_all(&self) -> Result<(), Box<dyn Error>> {
@@ -1,16 +1,30 @@
 use ndarray::{s, Array1, Zip};
 use std::iter::FromIterator;
-use hdf5::types;
+use std::convert::TryFrom;
+use std::ffi::c_void;
+use std::mem::MaybeUninit;
+use hdf5::h5check;
+use hdf5::types::{self, FixedAscii};
+use hdf5_sys::h5a::{H5Aopen, H5Aread, H5Aget_space, H5Aclose};
+use hdf5_sys::h5p::H5P_DEFAULT;
+use hdf5_sys::h5s::{H5Sget_simple_extent_npoints, H5Sclose};
 use ascii::AsciiString;
 use std::path::Path;
 
+use super::writer::attr_name;
+
 pub type PixelT = (u32, u32, u32);
 
+// The longest contig name which is read from fixed-length strings.
+const MAX_TIG_NAME_LEN: usize = 256;
+
 #[derive(Clone,Debug)]
 pub struct MatrixReader {
     file: hdf5::File,
 }
 
+// Reads both multi-resolution (.mcool) and single-resolution (.cool) cooler files. The latter keep a cooler
+// in the root group and its resolution in bin-size attribute.
 impl MatrixReader {
     pub fn new(file_path: &Path) -> hdf5::Result<MatrixReader> {
         Ok(MatrixReader {
@@ -18,12 +32,27 @@ impl MatrixReader {
         })
     }
 
+    // Files of other tools (and read-only ones) are not opened for writing.
+    pub fn new_in_reading_mode(file_path: &Path) -> hdf5::Result<MatrixReader> {
+        Ok(MatrixReader {
+            file: hdf5::File::open(file_path)?,
+        })
+    }
+
+    pub fn is_single_resolution(&self) -> bool {
+        !self.file.link_exists("resolutions") && self.file.link_exists("bins")
+    }
+
     pub fn get_n_chroms(&self) -> hdf5::Result<usize> {
-        let grp = self.file.group("chroms")?;
+        let grp = self.get_chroms_group()?;
         Ok(grp.dataset("name")?.size())
     }
 
     pub fn read_resolutions(&self) -> hdf5::Result<Vec<u32>> {
+        if self.is_single_resolution() {
+            return Ok(vec![self.read_single_resolution()?]);
+        }
+
         let grp = self.file.group("/resolutions/")?;
         let mmn = grp.member_names()?;
         Ok(Vec::from_iter(mmn.into_iter().map(|s_res| {
@@ -38,22 +67,59 @@ impl MatrixReader {
     }
 
     pub fn get_res_group_reader(&self, res: u32) -> hdf5::Result<ResGrpReader> {
+        if self.is_single_resolution() {
+            if self.read_single_resolution()? != res {
+                return Err(hdf5::Error::Internal(format!("Resolution {} does not exist", res)));
+            }
+            return ResGrpReader::new(self.file.group("/")?);
+        }
+
         let root = self.file.group(format!("/resolutions/{}", res).as_ref())?;
         ResGrpReader::new(root)
     }
 
     pub fn read_chrom_orders(&self) -> hdf5::Result<Array1<AsciiString>> {
-        let grp = self.file.group("chroms")?;
-        let tig_orders= read_dataset::<types::VarLenAscii>(&grp, "name")?;
-        let tig_orders = Array1::from_iter(tig_orders.iter()
-            .map(|x| {AsciiString::from_ascii(x.as_bytes()).unwrap()}));
-        Ok(tig_orders)
+        let grp = self.get_chroms_group()?;
+        read_tig_names(&grp)
     }
 
     pub fn read_chrom_lengths(&self) -> hdf5::Result<Array1<u64>> {
-        let grp = self.file.group("chroms")?;
+        let grp = self.get_chroms_group()?;
         read_dataset::<u64>(&grp, "length")
     }
+
+    // Bin size is int64 in cooler files.
+    fn read_single_resolution(&self) -> hdf5::Result<u32> {
+        let bin_size = read_attr::<i64>(&self.file, "bin-size")?;
+        u32::try_from(bin_size).map_err(|_| hdf5::Error::Internal(format!("Incorrect bin size {}", bin_size)))
+    }
+
+    // Contigs are kept in the root group by our builder, but .mcool files written by other tools have them
+    // only in groups of resolutions (which are the same for all resolutions).
+    fn get_chroms_group(&self) -> hdf5::Result<hdf5::Group> {
+        if self.file.link_exists("chroms") {
+            return self.file.group("chroms");
+        }
+
+        let grp = self.file.group("/resolutions/")?;
+        match grp.member_names()?.first() {
+            Some(res) => self.file.group(format!("/resolutions/{}/chroms", res).as_ref()),
+            None => Err(hdf5::Error::Internal(String::from("Matrix file has no resolutions")))
+        }
+    }
+}
+
+// Names are fixed-length strings in cooler files, older files of our builder have variable-length strings.
+pub fn read_tig_names(grp: &hdf5::Group) -> hdf5::Result<Array1<AsciiString>> {
+    match read_dataset::<FixedAscii<[u8; MAX_TIG_NAME_LEN]>>(grp, "name") {
+        Ok(names) => Ok(Array1::from_iter(names.iter()
+            .map(|x| {AsciiString::from_ascii(x.as_bytes()).unwrap()}))),
+        Err(_) => {
+            let names = read_dataset::<types::VarLenAscii>(grp, "name")?;
+            Ok(Array1::from_iter(names.iter()
+                .map(|x| {AsciiString::from_ascii(x.as_bytes()).unwrap()})))
+        }
+    }
 }
 
 #[derive(Clone,Debug)]
@@ -178,6 +244,8 @@ impl ResGrpReader {
     }
 }
 
+// Datasets are contiguous in older files and chunked with gzip/shuffle filters in newer ones,
+// filters are decoded by HDF5 library, so both are read the same way.
 pub fn read_dataset_slice<T: hdf5::H5Type>(grp: &hdf5::Group, name: &str, start: usize, end: usize)
                          -> hdf5::Result<Array1<T>> {
     let dts = grp.dataset(name)?;
@@ -189,3 +257,217 @@ pub fn read_dataset<T: hdf5::H5Type>(grp: &hdf5::Group, name: &str) -> hdf5::Res
     dts.read_1d::<T>()
 }
 
+// Scalar attribute, its value is converted to the requested type by HDF5 library (as values of datasets).
+pub fn read_attr<T: hdf5::H5Type>(loc: &hdf5::Location, name: &str) -> hdf5::Result<T> {
+    let c_name = attr_name(name)?;
+    let dtype = hdf5::Datatype::from_type::<T>()?;
+    hdf5::sync::sync(|| unsafe {
+        let attr = h5check(H5Aopen(loc.id(), c_name.as_ptr(), H5P_DEFAULT))?;
+        let space = h5check(H5Aget_space(attr));
+        let n_points = space.and_then(|space| {
+            let n_points = h5check(H5Sget_simple_extent_npoints(space));
+            H5Sclose(space);
+            n_points
+        });
+
+        let mut value = MaybeUninit::<T>::uninit();
+        let read = match n_points {
+            Ok(1) => h5check(H5Aread(attr, dtype.id(), value.as_mut_ptr() as *mut c_void)),
+            Ok(_) => Err(hdf5::Error::Internal(format!("Attribute {} is not a scalar", name))),
+            Err(e) => Err(e)
+        };
+        H5Aclose(attr);
+        read.map(|_| value.assume_init())
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+    use ndarray::arr1;
+    use hdf5::types::VarLenUnicode;
+
+    use crate::{Compression, Strategy};
+    use crate::writer::{write_attr, write_str_attr, write_compressed_dataset};
+
+    // Cooler file written as cooler does (see tests/data/make_cooler_small.py): bin-size is int64,
+    // contig names are 5-byte strings and contig lengths are int32.
+    const COOLER_FIXTURE: &str = "tests/data/cooler_small.cool";
+
+    fn tmp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hic_matrix_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // Fixture is copied, as matrix files are opened for writing.
+    fn copy_fixture(dir: &Path) -> PathBuf {
+        let path = dir.join("cooler_small.cool");
+        fs::copy(Path::new(env!("CARGO_MANIFEST_DIR")).join(COOLER_FIXTURE), &path).unwrap();
+        path
+    }
+
+    fn tig_names(reader: &MatrixReader) -> Vec<String> {
+        reader.read_chrom_orders().unwrap().iter().map(|x| x.to_string()).collect()
+    }
+
+    fn assert_fixture_resolution(grp: &ResGrpReader) {
+        assert_eq!(grp.get_n_bins().unwrap(), 5);
+        assert_eq!(grp.read_bin_table_chr_ids().unwrap(), arr1(&[0, 0, 0, 1, 1]));
+        assert_eq!(grp.read_bin_table_starts().unwrap(), arr1(&[0, 1000, 2000, 0, 1000]));
+        assert_eq!(grp.read_bin_table_ends().unwrap(), arr1(&[1000, 2000, 2500, 1000, 1200]));
+
+        let (bin1, bin2, count) = grp.read_pixels().unwrap();
+        assert_eq!(bin1, arr1(&[0, 0, 0, 1, 2, 3, 4]));
+        assert_eq!(bin2, arr1(&[0, 1, 3, 1, 4, 3, 4]));
+        assert_eq!(count, arr1(&[5, 3, 1, 4, 2, 6, 1]));
+
+        let (tig_offsets, bin_offsets) = grp.read_indices().unwrap();
+        assert_eq!(tig_offsets, arr1(&[0, 3, 5]));
+        assert_eq!(bin_offsets, arr1(&[0, 3, 4, 5, 6, 7]));
+    }
+
+    #[test]
+    fn test_read_cooler_fixture() {
+        let dir = tmp_dir("read_fixture");
+        let cooler_file = copy_fixture(&dir);
+        {
+            let reader = MatrixReader::new(&cooler_file).unwrap();
+            assert!(reader.is_single_resolution());
+            assert_eq!(reader.read_resolutions().unwrap(), vec![1000]);
+            assert_eq!(tig_names(&reader), vec!["ctg1", "ctg22"]);
+            assert_eq!(reader.read_chrom_lengths().unwrap(), arr1(&[2500, 1200]));
+            assert_fixture_resolution(&reader.get_res_group_reader(1000).unwrap());
+            assert!(reader.get_res_group_reader(2000).is_err());
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // Fixture is imported in place, it is opened only for reading and stays unchanged.
+    #[test]
+    fn test_import_cooler_round_trip() {
+        let dir = tmp_dir("import_fixture");
+        let cooler_file = Path::new(env!("CARGO_MANIFEST_DIR")).join(COOLER_FIXTURE);
+        let fixture = fs::read(&cooler_file).unwrap();
+        let matrix_file = dir.join("imported.mcool");
+        crate::import_matrix(&cooler_file, &matrix_file).unwrap();
+        assert_eq!(fs::read(&cooler_file).unwrap(), fixture);
+        {
+            let reader = MatrixReader::new(&matrix_file).unwrap();
+            assert!(!reader.is_single_resolution());
+            assert_eq!(reader.read_resolutions().unwrap(), vec![1000]);
+            assert_eq!(tig_names(&reader), vec!["ctg1", "ctg22"]);
+            assert_eq!(reader.read_chrom_lengths().unwrap(), arr1(&[2500, 1200]));
+
+            let grp = reader.get_res_group_reader(1000).unwrap();
+            assert_fixture_resolution(&grp);
+            assert_eq!(read_attr::<i64>(grp.get_root(), "bin-size").unwrap(), 1000);
+            assert_eq!(read_attr::<i64>(grp.get_root(), "nnz").unwrap(), 7);
+            assert_eq!(read_attr::<i64>(grp.get_root(), "sum").unwrap(), 22);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // Fixture balanced by bin length goes through .hic file, weights come back from KR normalization vectors.
+    #[test]
+    fn test_import_hic_round_trip() {
+        let dir = tmp_dir("import_hic");
+        let cooler_file = copy_fixture(&dir);
+        let matrix_file = dir.join("balanced.mcool");
+        let hic_file = dir.join("balanced.hic");
+        let imported_file = dir.join("imported.mcool");
+        crate::import_matrix(&cooler_file, &matrix_file).unwrap();
+        crate::balance(&matrix_file, &[1000], &Strategy::BinLength).unwrap();
+        crate::export_hic(&matrix_file, &hic_file).unwrap();
+        crate::import_matrix(&hic_file, &imported_file).unwrap();
+        {
+            let reader = MatrixReader::new(&imported_file).unwrap();
+            assert_eq!(reader.read_resolutions().unwrap(), vec![1000]);
+            assert_eq!(tig_names(&reader), vec!["ctg1", "ctg22"]);
+            assert_eq!(reader.read_chrom_lengths().unwrap(), arr1(&[2500, 1200]));
+
+            let grp = reader.get_res_group_reader(1000).unwrap();
+            assert_fixture_resolution(&grp);
+            let weights = grp.read_bin_table_weights().unwrap();
+            assert_eq!(weights.len(), 5);
+            assert!(weights.iter().all(|w| (w - 0.001).abs() < 1e-12));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_pairs_round_trip() {
+        let dir = tmp_dir("pairs");
+        let pairs_file = dir.join("test.pairs");
+        fs::write(&pairs_file, "## pairs format v1.0\n#chromsize: ctg2 2500\n#chromsize: ctg1 1200\n\
+                                #columns: readID chr1 pos1 chr2 pos2 strand1 strand2\n\
+                                r1\tctg1\t10\tctg1\t500\t+\t-\n\
+                                r2\tctg1\t1100\tctg2\t2100\t+\t-\n\
+                                r3\tctg2\t5\tctg1\t1150\t-\t+\n\
+                                r4\tctg1\t20\tctg1\t30\t+\t+\n").unwrap();
+
+        for &(name, rslns) in [("test.cool", &[1000][..]), ("test.mcool", &[1000, 2000][..])].iter() {
+            let matrix_file = dir.join(name);
+            crate::create_matrix_from_pairs(&pairs_file, None, &matrix_file, rslns, &Strategy::None, "1M",
+                                            Some(&dir), &Compression::default()).unwrap();
+
+            let reader = MatrixReader::new(&matrix_file).unwrap();
+            assert_eq!(reader.is_single_resolution(), rslns.len() == 1);
+            let mut file_rslns = reader.read_resolutions().unwrap();
+            file_rslns.sort_unstable();
+            assert_eq!(file_rslns, rslns);
+            // contigs are ordered by length
+            assert_eq!(tig_names(&reader), vec!["ctg1", "ctg2"]);
+            assert_eq!(reader.read_chrom_lengths().unwrap(), arr1(&[1200, 2500]));
+
+            let grp = reader.get_res_group_reader(1000).unwrap();
+            assert_eq!(grp.read_bin_table_starts().unwrap(), arr1(&[0, 1000, 0, 1000, 2000]));
+            assert_eq!(grp.read_pixels().unwrap(), (arr1(&[0, 1, 1]), arr1(&[0, 2, 4]), arr1(&[2, 1, 1])));
+            assert_eq!(grp.read_indices().unwrap(), (arr1(&[0, 2, 5]), arr1(&[0, 1, 3, 3, 3, 3])));
+
+            if rslns.len() > 1 {
+                let grp = reader.get_res_group_reader(2000).unwrap();
+                assert_eq!(grp.read_pixels().unwrap(), (arr1(&[0, 0, 0]), arr1(&[0, 1, 2]), arr1(&[2, 1, 1])));
+                assert_eq!(read_attr::<i64>(grp.get_root(), "bin-size").unwrap(), 2000);
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_attr_round_trip() {
+        let dir = tmp_dir("attrs");
+        {
+            let file = hdf5::File::create(dir.join("attrs.h5")).unwrap();
+            write_attr(&file, "bin-size", &1000_i64).unwrap();
+            write_attr(&file, "bin-size", &2000_i64).unwrap();
+            write_str_attr(&file, "format", "HDF5::Cooler").unwrap();
+
+            assert_eq!(read_attr::<i64>(&file, "bin-size").unwrap(), 2000);
+            assert_eq!(read_attr::<u32>(&file, "bin-size").unwrap(), 2000);
+            assert_eq!(read_attr::<VarLenUnicode>(&file, "format").unwrap().as_str(), "HDF5::Cooler");
+            assert!(read_attr::<i64>(&file, "nbins").is_err());
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // Dataset of several chunks is compressed by LZF filter and decoded by HDF5 on reading.
+    #[test]
+    fn test_lzf_dataset_round_trip() {
+        let dir = tmp_dir("lzf");
+        let path = dir.join("lzf.h5");
+        let compression = Compression::from_string("lzf", true).unwrap();
+        let column = Array1::from_iter((0..200_000_i64).map(|i| i / 7));
+        {
+            let file = hdf5::File::create(&path).unwrap();
+            write_compressed_dataset(&file, "bin1_id", column.view(), &compression).unwrap();
+        }
+        let file = hdf5::File::open(&path).unwrap();
+        assert_eq!(file.dataset("bin1_id").unwrap().read_1d::<i64>().unwrap(), column);
+        assert!(fs::metadata(&path).unwrap().len() < (column.len() * 8 / 4) as u64);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -116,6 +116,10 @@ impl ResGroup {
         self.reader.read_chrom_offsets()
     }
 
+    pub fn get_weights(&self) -> hdf5::Result<Array1<f64>> {
+        self.reader.read_bin_table_weights()
+    }
+
     pub fn get_raw_pixels(&self) -> hdf5::Result<(Array1<u32>, Array1<u32>, Array1<u32>)> {
         self.reader.read_pixels()
     }
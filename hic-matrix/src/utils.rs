@@ -1,4 +1,5 @@
 use std::fs::File;
+use std::io::{self, BufRead, BufReader};
 use std::path::Path;
 use serde::Deserialize;
 use ascii::{AsciiString, AsAsciiStr};
@@ -6,8 +7,11 @@ use std::error::Error;
 use std::cmp::Ordering;
 use ndarray::{azip, Array1, ArrayView1, self};
 use num_traits::identities;
+use flate2::read::MultiGzDecoder;
 use std::ops;
 
+use super::errors::PairsHeaderError;
+
 // pub const CHUNKSIZE: usize = 50_000_000;
 
 #[derive(Debug, Deserialize)]
@@ -36,6 +40,67 @@ pub fn parse_tig_lengths(file_name: &Path) -> Result<Vec<(AsciiString, u64)>, Bo
     Ok(tig_lengths)
 }
 
+// Layout of pairs file. Files in 4DN format have header with chromsizes and columns and 1-based positions,
+// headerless files have columns readID, tig1, pos1, tig2, pos2, strand1, strand2 and 0-based positions.
+// Pairs are sorted if #sorted line starts with chr1-chr2 (blocks of contig pairs are contiguous).
+pub struct PairsLayout {
+    pub pos_offset: u64,
+    pub is_sorted: bool,
+    pub chromsizes: Vec<(AsciiString, u64)>,
+    pub col_tig1: usize,
+    pub col_pos1: usize,
+    pub col_tig2: usize,
+    pub col_pos2: usize,
+}
+
+// Opens plain or gzip-compressed pairs file (BGZF file is a series of gzip members, so it is read in the same way).
+pub fn open_pairs_file(file_name: &Path) -> io::Result<Box<dyn BufRead>> {
+    let mut reader = BufReader::new(File::open(file_name)?);
+    if reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+pub fn parse_pairs_header(file_name: &Path) -> Result<PairsLayout, Box<dyn Error>> {
+    let mut layout = PairsLayout {
+        pos_offset: 0,
+        is_sorted: false,
+        chromsizes: Vec::new(),
+        col_tig1: 1,
+        col_pos1: 2,
+        col_tig2: 3,
+        col_pos2: 4,
+    };
+
+    let reader = open_pairs_file(file_name)?;
+    for line in reader.lines() {
+        let line = line?;
+        if !line.starts_with('#') { break; }
+
+        if line.starts_with("## pairs format") {
+            layout.pos_offset = 1;
+        } else if let Some(v) = line.strip_prefix("#chromsize:") {
+            let fields: Vec<&str> = v.split_whitespace().collect();
+            if fields.len() != 2 { return Err(PairsHeaderError.into()); }
+            layout.chromsizes.push((AsciiString::from(fields[0].as_ascii_str()?), fields[1].parse()?));
+        } else if let Some(v) = line.strip_prefix("#sorted:") {
+            layout.is_sorted = v.trim().starts_with("chr1-chr2");
+        } else if let Some(v) = line.strip_prefix("#columns:") {
+            let columns: Vec<&str> = v.split_whitespace().collect();
+            let find = |name: &str| columns.iter().position(|c| *c == name).ok_or(PairsHeaderError);
+            layout.col_tig1 = find("chr1")?;
+            layout.col_pos1 = find("pos1")?;
+            layout.col_tig2 = find("chr2")?;
+            layout.col_pos2 = find("pos2")?;
+        }
+    }
+
+    layout.chromsizes.sort_by_key(|x| x.1);
+    Ok(layout)
+}
+
 // https://rosettacode.org/wiki/Quickselect_algorithm#Rust
 pub fn get_array_wrt_predicate<T: Copy>(predicate: ArrayView1<bool>, array: ArrayView1<T>) -> Vec<T> {
     assert_eq!(predicate.len(), array.len());
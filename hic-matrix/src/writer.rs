@@ -1,15 +1,91 @@
 use std::error::Error;
 use std::iter::FromIterator;
+use std::ffi::{c_void, CString, OsStr};
+use std::os::raw::c_uint;
 use std::path::Path;
 
 
 use ascii::AsciiString;
-use ndarray::{Array1, ArrayView1};
-use hdf5::types;
-
-use super::reader::PixelT;
+use ndarray::{s, Array1, ArrayView1};
+use hdf5::types::{self, FixedAscii, VarLenUnicode};
+use hdf5::h5check;
+use hdf5_sys::h5a::{H5Acreate2, H5Adelete, H5Aexists, H5Awrite, H5Aclose};
+use hdf5_sys::h5::hsize_t;
+use hdf5_sys::h5d::{H5Dcreate2, H5Dclose};
+use hdf5_sys::h5p::{H5Pcreate, H5Pclose, H5Pset_chunk, H5Pset_shuffle, H5Pset_filter, H5P_CLS_DATASET_CREATE, H5P_DEFAULT};
+use hdf5_sys::h5s::{H5Screate, H5Screate_simple, H5Sclose, H5S_class_t, H5S_UNLIMITED};
+use hdf5_sys::h5z::H5Z_FLAG_OPTIONAL;
+
+use super::lzf;
+use super::reader::{self, PixelT};
 use super::builders::pair_builder::PairsBuilder;
-use super::builders::res_grp_builder::ResGrpBuilder;
+use super::builders::res_grp_builder::{ResGrpBuilder, PixelIter};
+use super::errors::{SingleResolutionError, TigNameError, CompressionError};
+
+// Files are written in cooler format (https://cooler.readthedocs.io/en/latest/schema.html): multi-resolution
+// files (.mcool) keep a complete cooler in each resolutions/<r> group, single-resolution files (.cool) keep it
+// in the root group. Contigs are also kept in the root chroms group of multi-resolution files.
+const MCOOL_FORMAT: &str = "HDF5::MCOOL";
+const MCOOL_FORMAT_VERSION: i64 = 2;
+const COOL_FORMAT: &str = "HDF5::Cooler";
+const COOL_FORMAT_VERSION: i64 = 3;
+const COOL_FORMAT_URL: &str = "https://github.com/open2c/cooler";
+const BIN_TYPE: &str = "fixed";
+const STORAGE_MODE: &str = "symmetric-upper";
+const GENERATED_BY: &str = concat!("hic-matrix-", env!("CARGO_PKG_VERSION"));
+const SINGLE_RES_EXT: &str = "cool";
+// Pixels are appended to datasets by chunks of this size.
+const PIXEL_CHUNKSIZE: usize = 1_000_000;
+// The largest HDF5 chunk of datasets, smaller datasets are written as a single chunk.
+const DATASET_CHUNKSIZE: usize = 1 << 16;
+const DEFAULT_GZIP_LEVEL: u8 = 6;
+
+// Gzip is a filter of hdf5 crate (szip is not supported by cooler), LZF is a filter of h5py which is registered
+// by the lzf module and set up via HDF5 C API.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Codec {
+    None,
+    Gzip(u8),
+    Lzf,
+}
+
+// Filters of chunks of datasets. Bytes are shuffled before compression, which helps with integer columns.
+// Filters are decoded by HDF5 library on reading, so readers do not depend on them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Compression {
+    pub codec: Codec,
+    pub shuffle: bool,
+}
+
+// Gzip with shuffling as cooler does.
+impl Default for Compression {
+    fn default() -> Compression {
+        Compression { codec: Codec::Gzip(DEFAULT_GZIP_LEVEL), shuffle: true }
+    }
+}
+
+impl Compression {
+    // Codec is none, lzf, gzip or gzip:<level> (level from 0 to 9).
+    pub fn from_string(s: &str, shuffle: bool) -> Result<Compression, CompressionError> {
+        let codec = match s {
+            "none" => Codec::None,
+            "lzf" => Codec::Lzf,
+            "gzip" => Codec::Gzip(DEFAULT_GZIP_LEVEL),
+            _ => match s.strip_prefix("gzip:").map(|l| l.parse::<u8>()) {
+                Some(Ok(level)) if level <= 9 => Codec::Gzip(level),
+                _ => return Err(CompressionError)
+            }
+        };
+        Ok(Compression { codec, shuffle: shuffle && codec != Codec::None })
+    }
+
+    pub fn from_option(s: Option<&str>, shuffle: bool) -> Result<Compression, CompressionError> {
+        match s {
+            Some(compression) => Compression::from_string(compression, shuffle),
+            None => Ok(Compression { shuffle, ..Compression::default() })
+        }
+    }
+}
 
 enum MatrixWriterMode {
     Write,
@@ -19,9 +95,12 @@ enum MatrixWriterMode {
 pub struct MatrixWriter {
     wrt_mode: MatrixWriterMode,
     file: hdf5::File,
+    is_single_res: bool,
+    compression: Compression,
 }
 
 impl MatrixWriter {
+    // Single-resolution cooler is written if file has .cool extension.
     pub fn new_in_writing_mode(filename: &Path) -> hdf5::Result<MatrixWriter> {
         MatrixWriter::new(filename, MatrixWriterMode::Write)
     }
@@ -30,19 +109,52 @@ impl MatrixWriter {
         MatrixWriter::new(filename, MatrixWriterMode::Append)
     }
 
+    // Filters of datasets written after this call (default is gzip with shuffling).
+    pub fn update_compression(mut writer: MatrixWriter, compression: Compression) -> MatrixWriter {
+        writer.compression = compression;
+        writer
+    }
+
     pub fn get_file_handler(&self) -> &hdf5::File {
         &self.file
     }
 
+    // Group of the resolution, it is the root group for single-resolution file.
+    pub fn get_res_group(&self, res: u32) -> hdf5::Result<hdf5::Group> {
+        if self.is_single_res {
+            self.file.group("/")
+        } else {
+            self.file.group(format!("resolutions/{}", res).as_ref())
+        }
+    }
+
     pub fn write_matrix(&self, builder: &PairsBuilder) -> Result<(), Box<dyn Error>> {
-        self.write_chroms_group(builder.tig_names_view(), builder.tig_lengths_view())?;
+        self.write_tigs(builder.tig_names_view(), builder.tig_lengths_view())?;
         self.write_resolution_group(builder)?;
         Ok(())
     }
 
+    // Root attributes and contigs of a new file, resolution groups are written after them.
+    pub fn write_tigs(&self, tig_order: ArrayView1<AsciiString>, tig_lengths: ArrayView1<u64>) -> Result<(), Box<dyn Error>> {
+        if !self.is_single_res {
+            write_str_attr(&self.file, "format", MCOOL_FORMAT)?;
+            write_attr(&self.file, "format-version", &MCOOL_FORMAT_VERSION)?;
+            write_str_attr(&self.file, "bin-type", BIN_TYPE)?;
+        }
+        let grp = self.file.create_group("chroms")?;
+        write_chroms_group(&grp, tig_order, tig_lengths, &self.compression)
+    }
+
     pub fn write_resolution_group(&self, builder: &impl ResGrpBuilder) -> Result<(), Box<dyn Error>> {
-        let grp = self.file.create_group(format!("resolutions/{}", builder.get_resolution()).as_ref())?;
-        ResGrpWriter::write_resolution_group(&grp, builder)?;
+        let grp_writer = ResGrpWriter { compression: self.compression };
+        if self.is_single_res {
+            if self.file.link_exists("bins") { return Err(SingleResolutionError.into()); }
+            grp_writer.write_resolution_group(&self.file, builder)?;
+        } else {
+            let grp = self.file.create_group(format!("resolutions/{}", builder.get_resolution()).as_ref())?;
+            grp_writer.copy_chroms_group(&self.file.group("chroms")?, &grp)?;
+            grp_writer.write_resolution_group(&grp, builder)?;
+        }
         Ok(())
     }
 
@@ -52,8 +164,8 @@ impl MatrixWriter {
                 return Err(hdf5::Error::Internal(String::from("File opened in non-appending mode")));
             }
             MatrixWriterMode::Append => {
-                let root = self.file.group(format!("resolutions/{}", res).as_ref())?;
-                ResGrpWriter::write_balancing_weights(&root, weights)?;
+                let root = self.get_res_group(res)?;
+                ResGrpWriter { compression: self.compression }.write_balancing_weights(&root, weights)?;
             }
         };
         Ok(())
@@ -63,108 +175,288 @@ impl MatrixWriter {
         match wrt_mode {
             MatrixWriterMode::Write => Ok(MatrixWriter {
                 file: hdf5::File::create(filename)?,
-                wrt_mode
-            }),
-            MatrixWriterMode::Append => Ok(MatrixWriter {
-                file: hdf5::File::open_rw(filename)?,
-                wrt_mode
+                is_single_res: filename.extension() == Some(OsStr::new(SINGLE_RES_EXT)),
+                wrt_mode,
+                compression: Compression::default()
             }),
+            MatrixWriterMode::Append => {
+                let file = hdf5::File::open_rw(filename)?;
+                Ok(MatrixWriter {
+                    is_single_res: !file.link_exists("resolutions") && file.link_exists("bins"),
+                    file,
+                    wrt_mode,
+                    compression: Compression::default()
+                })
+            },
         }
     }
+}
 
-    fn write_chroms_group(&self, tig_order: ArrayView1<AsciiString>, tig_lengths: ArrayView1<u64>) -> hdf5::Result<()> {
-        let grp = self.file.create_group("chroms")?;
-
-        let tig_orders = Array1::from_iter(
-            tig_order.iter()
-                .map(|x| {types::VarLenAscii::from_ascii(x.as_bytes()).unwrap()} )
-        );
-        write_dataset(&grp, "name", tig_orders.len(), tig_orders.view())?;
-        write_dataset(&grp, "length", tig_lengths.len(), tig_lengths)?;
-
-        Ok(())
+// Names are stored as fixed-length ASCII strings (as cooler does), length is picked by the longest name.
+fn write_chroms_group(grp: &hdf5::Group, tig_order: ArrayView1<AsciiString>, tig_lengths: ArrayView1<u64>,
+                      compression: &Compression) -> Result<(), Box<dyn Error>> {
+    let max_len = tig_order.iter().map(|x| x.len()).max().unwrap_or(0);
+    match max_len {
+        0..=32 => write_tig_names::<[u8; 32]>(grp, tig_order, compression)?,
+        33..=64 => write_tig_names::<[u8; 64]>(grp, tig_order, compression)?,
+        65..=128 => write_tig_names::<[u8; 128]>(grp, tig_order, compression)?,
+        129..=256 => write_tig_names::<[u8; 256]>(grp, tig_order, compression)?,
+        _ => return Err(TigNameError.into())
     }
+    let tig_lengths = tig_lengths.mapv(|x| x as i64);
+    write_compressed_dataset(grp, "length", tig_lengths.view(), compression)?;
+    Ok(())
 }
 
-struct ResGrpWriter {}
+fn write_tig_names<A: types::Array<Item = u8>>(grp: &hdf5::Group, tig_order: ArrayView1<AsciiString>,
+                                               compression: &Compression) -> hdf5::Result<()> {
+    let names = Array1::from_iter(
+        tig_order.iter()
+            .map(|x| {FixedAscii::<A>::from_ascii(x.as_bytes()).unwrap()} )
+    );
+    write_compressed_dataset(grp, "name", names.view(), compression)
+}
+
+struct ResGrpWriter {
+    compression: Compression,
+}
 
 impl ResGrpWriter {
 
-    fn write_balancing_weights(grp: &hdf5::Group, weights: ArrayView1<f64>) -> hdf5::Result<()> {
+    // Cooler weights are multiplicative: balanced value is count * weight1 * weight2.
+    fn write_balancing_weights(&self, grp: &hdf5::Group, weights: ArrayView1<f64>) -> hdf5::Result<()> {
         let grp = grp.group("bins")?;
         match grp.dataset("weight") {
             Ok(dts) => {
                 dts.resize(weights.len())?;
-                dts.write(weights);
+                dts.write(weights)?;
             }
-            _ => write_dataset(&grp, "weight", weights.len(), weights)?
+            _ => write_compressed_dataset(&grp, "weight", weights, &self.compression)?
         };
+        let dts = grp.dataset("weight")?;
+        write_attr(&dts, "divisive_weights", &false)?;
 
         Ok(())
     }
 
-    fn write_resolution_group(grp: &hdf5::Group, builder: &impl ResGrpBuilder) -> Result<(), Box<dyn Error>> {
-        // Writing indexes
-        let pixels = ResGrpWriter::write_index_group(grp, builder)?;
+    fn write_resolution_group(&self, grp: &hdf5::Group, builder: &impl ResGrpBuilder) -> Result<(), Box<dyn Error>> {
+        // Saving pixels, offsets of bins are counted along the way
+        let n_bins = builder.get_bin_table().0.len();
+        let (bin_offsets, nnz, sum) = self.consume_and_write_pixels(grp, builder.get_pixels()?, n_bins)?;
 
-        // Saving pixels
-        ResGrpWriter::consume_and_write_pixels(grp, pixels)?;
+        // Writing indexes
+        self.write_index_group(grp, builder.get_tig_offsets_view(), bin_offsets.view())?;
 
         // Saving bin information
-        ResGrpWriter::write_bins_description(grp, builder)?;
+        self.write_bins_description(grp, builder)?;
+
+        // Saving cooler attributes
+        let n_chroms = builder.get_tig_offsets_view().len().saturating_sub(1);
+        ResGrpWriter::write_cooler_attrs(grp, builder.get_resolution(), n_chroms, n_bins, nnz, sum)?;
 
         Ok(())
     }
 
-    fn write_bins_description(grp: &hdf5::Group, builder: &impl ResGrpBuilder) -> hdf5::Result<()> {
+    fn write_cooler_attrs(grp: &hdf5::Group, rsltn: u32, n_chroms: usize, n_bins: usize, nnz: usize, sum: u64)
+        -> hdf5::Result<()> {
+        write_str_attr(grp, "format", COOL_FORMAT)?;
+        write_attr(grp, "format-version", &COOL_FORMAT_VERSION)?;
+        write_str_attr(grp, "format-url", COOL_FORMAT_URL)?;
+        write_str_attr(grp, "bin-type", BIN_TYPE)?;
+        write_attr(grp, "bin-size", &(rsltn as i64))?;
+        write_str_attr(grp, "storage-mode", STORAGE_MODE)?;
+        write_attr(grp, "nchroms", &(n_chroms as i64))?;
+        write_attr(grp, "nbins", &(n_bins as i64))?;
+        write_attr(grp, "nnz", &(nnz as i64))?;
+        write_attr(grp, "sum", &(sum as i64))?;
+        write_str_attr(grp, "generated-by", GENERATED_BY)?;
+        write_str_attr(grp, "creation-date", chrono::Local::now().format("%Y-%m-%dT%H:%M:%S%.6f").to_string().as_str())?;
+        write_str_attr(grp, "genome-assembly", "unknown")?;
+        write_str_attr(grp, "metadata", "{}")?;
+        Ok(())
+    }
+
+    fn copy_chroms_group(&self, from_grp: &hdf5::Group, grp: &hdf5::Group) -> Result<(), Box<dyn Error>> {
+        let names = reader::read_tig_names(from_grp)?;
+        let lengths = reader::read_dataset::<u64>(from_grp, "length")?;
+        write_chroms_group(&grp.create_group("chroms")?, names.view(), lengths.view(), &self.compression)
+    }
+
+    // Dtypes follow cooler: int32 for contig ids and counts, int64 for coordinates, bin ids and offsets.
+    fn write_bins_description(&self, grp: &hdf5::Group, builder: &impl ResGrpBuilder) -> hdf5::Result<()> {
         let grp = grp.create_group("bins")?;
         let (chrs, starts, ends) = builder.get_bin_table();
-        write_dataset(&grp, "chrom", chrs.len(), chrs.view())?;
-        write_dataset(&grp, "start", starts.len(), starts.view())?;
-        write_dataset(&grp, "end", ends.len(), ends.view())?;
+        write_compressed_dataset(&grp, "chrom", chrs.mapv(|x| x as i32).view(), &self.compression)?;
+        write_compressed_dataset(&grp, "start", starts.mapv(|x| x as i64).view(), &self.compression)?;
+        write_compressed_dataset(&grp, "end", ends.mapv(|x| x as i64).view(), &self.compression)?;
         Ok(())
     }
 
-    fn write_index_group(grp: &hdf5::Group, builder: &impl ResGrpBuilder) -> Result<Vec<PixelT>, Box<dyn Error>> {
+    fn write_index_group(&self, grp: &hdf5::Group, tig_offsets: ArrayView1<u32>, bin_offsets: ArrayView1<i64>) -> hdf5::Result<()> {
         let grp = grp.create_group("indexes")?;
-
-        let tig_ofssets = builder.get_tig_offsets_view();
-        let pixels = builder.get_pixels()?;
-        write_dataset(&grp,"chrom_offset",tig_ofssets.len(), tig_ofssets)?;
-        let bin_offsets = builder.get_bin_offsets(&pixels);
-        write_dataset(&grp,"bin1_offset",bin_offsets.len(), bin_offsets.view())?;
-
-        Ok(pixels)
+        let tig_offsets = tig_offsets.mapv(|x| x as i64);
+        write_compressed_dataset(&grp, "chrom_offset", tig_offsets.view(), &self.compression)?;
+        write_compressed_dataset(&grp, "bin1_offset", bin_offsets, &self.compression)?;
+        Ok(())
     }
 
-    fn consume_and_write_pixels(grp: &hdf5::Group, pixels: Vec<PixelT>) -> hdf5::Result<()> {
+    // Pixels are taken from the stream and appended to resizable datasets by chunks, so the whole matrix is
+    // never kept in memory. Returns offsets of bins in pixels, number of pixels and sum of counts.
+    fn consume_and_write_pixels(&self, grp: &hdf5::Group, pixels: PixelIter, n_bins: usize)
+        -> Result<(Array1<i64>, usize, u64), Box<dyn Error>> {
         let grp = grp.create_group("pixels")?;
+        let bin1_dts = create_extensible_dataset::<i64>(&grp, "bin1_id", &self.compression)?;
+        let bin2_dts = create_extensible_dataset::<i64>(&grp, "bin2_id", &self.compression)?;
+        let count_dts = create_extensible_dataset::<i32>(&grp, "count", &self.compression)?;
+
+        let mut bin_offsets: Array1<i64> = Array1::zeros(n_bins + 1);
+        let mut chunk: Vec<PixelT> = Vec::with_capacity(PIXEL_CHUNKSIZE);
+        let mut n_pixels = 0_usize;
+        let mut sum = 0_u64;
+        let mut prev: Option<PixelT> = None;
+
+        for pixel in pixels {
+            let pixel = pixel?;
+            if pixel.0 as usize >= n_bins || pixel.1 as usize >= n_bins {
+                return Err(hdf5::Error::Internal(format!("Pixel {:?} is outside of bin table", pixel)).into());
+            }
+            if matches!(prev, Some(p) if (p.0, p.1) >= (pixel.0, pixel.1)) {
+                return Err(hdf5::Error::Internal(format!("Pixels are not sorted at {:?}", pixel)).into());
+            }
+            prev = Some(pixel);
+
+            bin_offsets[pixel.0 as usize + 1] += 1;
+            sum += pixel.2 as u64;
+            chunk.push(pixel);
+            if chunk.len() == PIXEL_CHUNKSIZE {
+                append_pixels(&bin1_dts, &bin2_dts, &count_dts, n_pixels, &chunk)?;
+                n_pixels += chunk.len();
+                chunk.clear();
+            }
+        }
+        append_pixels(&bin1_dts, &bin2_dts, &count_dts, n_pixels, &chunk)?;
+        n_pixels += chunk.len();
 
-        let mut bin1_ids: Array1<u32> = Array1::default(pixels.len());
-        let mut bin2_ids: Array1<u32> = Array1::default(pixels.len());
-        let mut counts: Array1<u32> = Array1::default(pixels.len());
-        pixels.into_iter().enumerate().for_each(|(i, info)| {
-            let (bin1_id, bin2_id, count) = info;
-            bin1_ids[i] = bin1_id;
-            bin2_ids[i] = bin2_id;
-            counts[i] = count;
-        });
+        for i in 1..bin_offsets.len() {
+            bin_offsets[i] += bin_offsets[i - 1];
+        }
+        Ok((bin_offsets, n_pixels, sum))
+    }
+}
 
-        write_dataset(&grp,"bin1_id",bin1_ids.len(), bin1_ids.view())?;
-        write_dataset(&grp,"bin2_id",bin2_ids.len(), bin2_ids.view())?;
-        write_dataset(&grp,"count",counts.len(), counts.view())?;
+fn append_pixels(bin1_dts: &hdf5::Dataset, bin2_dts: &hdf5::Dataset, count_dts: &hdf5::Dataset, start: usize,
+                 pixels: &[PixelT]) -> hdf5::Result<()> {
+    if pixels.is_empty() { return Ok(()); }
+    let bin1_ids = Array1::from_iter(pixels.iter().map(|p| p.0 as i64));
+    let bin2_ids = Array1::from_iter(pixels.iter().map(|p| p.1 as i64));
+    let counts = Array1::from_iter(pixels.iter().map(|p| p.2 as i32));
+    append_to_dataset(bin1_dts, start, bin1_ids.view())?;
+    append_to_dataset(bin2_dts, start, bin2_ids.view())?;
+    append_to_dataset(count_dts, start, counts.view())
+}
 
-        Ok(())
+// Empty one-dimensional dataset without size limit, it grows by appending.
+pub fn create_extensible_dataset<Q: hdf5::H5Type>(grp: &hdf5::Group, name: &str, compression: &Compression)
+    -> hdf5::Result<hdf5::Dataset> {
+    create_dataset::<Q>(grp, name, 0, DATASET_CHUNKSIZE, compression)
+}
+
+// All datasets are chunked and resizable, so they can be compressed and extended later.
+fn create_dataset<Q: hdf5::H5Type>(grp: &hdf5::Group, name: &str, shape: usize, chunk: usize,
+                                   compression: &Compression) -> hdf5::Result<hdf5::Dataset> {
+    if compression.codec == Codec::Lzf {
+        return create_lzf_dataset::<Q>(grp, name, shape, chunk, compression.shuffle);
+    }
+    let mut builder = grp.new_dataset::<Q>();
+    builder.chunk(chunk).resizable(true);
+    builder.shuffle(compression.shuffle);
+    if let Codec::Gzip(level) = compression.codec {
+        builder.gzip(level);
     }
+    builder.create(name, shape)
+}
+
+// Resizable chunked dataset with LZF filter (and shuffling before it). Custom filters are not covered by
+// hdf5 crate 0.7, so the dataset is created by HDF5 C API and then opened as usual.
+fn create_lzf_dataset<Q: hdf5::H5Type>(grp: &hdf5::Group, name: &str, shape: usize, chunk: usize,
+                                       shuffle: bool) -> hdf5::Result<hdf5::Dataset> {
+    lzf::register_filter()?;
+    let c_name = CString::new(name).map_err(|_| hdf5::Error::Internal(format!("Incorrect dataset name {}", name)))?;
+    let dtype = hdf5::Datatype::from_type::<Q>()?;
+    // Parameters of the filter as h5py saves them: versions of the filter and liblzf and size of chunk in bytes.
+    let cd_values = [lzf::LZF_FILTER_VERSION, lzf::LZF_VERSION, (chunk * dtype.size()) as c_uint];
+    hdf5::sync::sync(|| unsafe {
+        let dcpl = h5check(H5Pcreate(*H5P_CLS_DATASET_CREATE))?;
+        let created = h5check(H5Pset_chunk(dcpl, 1, [chunk as hsize_t].as_ptr()))
+            .and_then(|_| if shuffle { h5check(H5Pset_shuffle(dcpl)) } else { Ok(0) })
+            .and_then(|_| h5check(H5Pset_filter(dcpl, lzf::LZF_FILTER, H5Z_FLAG_OPTIONAL, cd_values.len(),
+                                                cd_values.as_ptr())))
+            .and_then(|_| {
+                let space = h5check(H5Screate_simple(1, [shape as hsize_t].as_ptr(), [H5S_UNLIMITED].as_ptr()))?;
+                let created = h5check(H5Dcreate2(grp.id(), c_name.as_ptr(), dtype.id(), space, H5P_DEFAULT, dcpl,
+                                                 H5P_DEFAULT))
+                    .map(|dataset| { H5Dclose(dataset); });
+                H5Sclose(space);
+                created
+            });
+        H5Pclose(dcpl);
+        created
+    })?;
+    grp.dataset(name)
+}
+
+pub fn append_to_dataset<Q: hdf5::H5Type>(dts: &hdf5::Dataset, start: usize, ar: ArrayView1<Q>) -> hdf5::Result<()> {
+    dts.resize(start + ar.len())?;
+    dts.write_slice(ar, s![start..start + ar.len()])
 }
 
+// Dataset with default compression.
 pub fn write_dataset<Q: hdf5::H5Type>(grp: &hdf5::Group, name: &str, shape: usize, ar: ArrayView1<Q>)
     -> hdf5::Result<()> {
-    let dts = grp.new_dataset::<Q>().create(name, shape)?;
+    let dts = create_dataset::<Q>(grp, name, shape, shape.max(1).min(DATASET_CHUNKSIZE), &Compression::default())?;
+    dts.write(ar)?;
+    Ok(())
+}
+
+pub fn write_compressed_dataset<Q: hdf5::H5Type>(grp: &hdf5::Group, name: &str, ar: ArrayView1<Q>,
+                                                 compression: &Compression) -> hdf5::Result<()> {
+    let dts = create_dataset::<Q>(grp, name, ar.len(), ar.len().max(1).min(DATASET_CHUNKSIZE), compression)?;
     dts.write(ar)?;
     Ok(())
 }
 
+// Scalar attribute, it is overwritten if it already exists. Attributes are not covered by hdf5 crate 0.7,
+// so they are written by HDF5 C API.
+pub fn write_attr<T: hdf5::H5Type>(loc: &hdf5::Location, name: &str, value: &T) -> hdf5::Result<()> {
+    let name = attr_name(name)?;
+    let dtype = hdf5::Datatype::from_type::<T>()?;
+    hdf5::sync::sync(|| unsafe {
+        if h5check(H5Aexists(loc.id(), name.as_ptr()))? > 0 {
+            h5check(H5Adelete(loc.id(), name.as_ptr()))?;
+        }
+
+        let space = h5check(H5Screate(H5S_class_t::H5S_SCALAR))?;
+        let written = h5check(H5Acreate2(loc.id(), name.as_ptr(), dtype.id(), space, H5P_DEFAULT, H5P_DEFAULT))
+            .and_then(|attr| {
+                let written = h5check(H5Awrite(attr, dtype.id(), value as *const T as *const c_void));
+                H5Aclose(attr);
+                written
+            });
+        H5Sclose(space);
+        written.map(|_| ())
+    })
+}
+
+pub fn attr_name(name: &str) -> hdf5::Result<CString> {
+    CString::new(name).map_err(|_| hdf5::Error::Internal(format!("Incorrect attribute name {}", name)))
+}
+
+pub fn write_str_attr(loc: &hdf5::Location, name: &str, value: &str) -> hdf5::Result<()> {
+    let value: VarLenUnicode = value.parse().map_err(|_| hdf5::Error::Internal(format!("Incorrect value of {}", name)))?;
+    write_attr(loc, name, &value)
+}
+
+
 
 // pub struct ResGrpWriter<'a, T: ResGrpBuilder> {
 //     builder: &'a T,
@@ -0,0 +1,146 @@
+#!/usr/bin/env python3
+# Writes cooler_small.cool, a single-resolution cooler with the dtypes of files written by cooler:
+# int64 bin-size attribute, contig names as fixed-length (S5) strings and int32 contig lengths.
+# The file is encoded by hand (superblock v2, v2 object headers, compact groups and contiguous
+# datasets), so neither h5py nor HDF5 library is needed to regenerate it.
+import os
+import struct
+
+UNDEF = 0xFFFFFFFFFFFFFFFF
+MASK = 0xFFFFFFFF
+
+BIN_SIZE = 1000
+CHROMS = [(b"ctg1", 2500), (b"ctg22", 1200)]
+BINS = [(0, 0, 1000), (0, 1000, 2000), (0, 2000, 2500), (1, 0, 1000), (1, 1000, 1200)]
+PIXELS = [(0, 0, 5), (0, 1, 3), (0, 3, 1), (1, 1, 4), (2, 4, 2), (3, 3, 6), (4, 4, 1)]
+CHROM_OFFSETS = [0, 3, 5]
+BIN1_OFFSETS = [0, 3, 4, 5, 6, 7]
+
+
+def rot(x, k):
+    return ((x << k) | (x >> (32 - k))) & MASK
+
+
+# Jenkins lookup3 hash (hashlittle), it is the checksum of HDF5 metadata.
+def lookup3(data, initval=0):
+    a = b = c = (0xDEADBEEF + len(data) + initval) & MASK
+    length, i = len(data), 0
+    if length == 0:
+        return c
+    while length > 12:
+        a = (a + struct.unpack_from("<I", data, i)[0]) & MASK
+        b = (b + struct.unpack_from("<I", data, i + 4)[0]) & MASK
+        c = (c + struct.unpack_from("<I", data, i + 8)[0]) & MASK
+        a = (a - c) & MASK; a ^= rot(c, 4); c = (c + b) & MASK
+        b = (b - a) & MASK; b ^= rot(a, 6); a = (a + c) & MASK
+        c = (c - b) & MASK; c ^= rot(b, 8); b = (b + a) & MASK
+        a = (a - c) & MASK; a ^= rot(c, 16); c = (c + b) & MASK
+        b = (b - a) & MASK; b ^= rot(a, 19); a = (a + c) & MASK
+        c = (c - b) & MASK; c ^= rot(b, 4); b = (b + a) & MASK
+        length -= 12
+        i += 12
+    tail = data[i:] + bytes(12 - length)
+    a = (a + struct.unpack_from("<I", tail, 0)[0]) & MASK
+    b = (b + struct.unpack_from("<I", tail, 4)[0]) & MASK
+    c = (c + struct.unpack_from("<I", tail, 8)[0]) & MASK
+    c ^= b; c = (c - rot(b, 14)) & MASK
+    a ^= c; a = (a - rot(c, 11)) & MASK
+    b ^= a; b = (b - rot(a, 25)) & MASK
+    c ^= b; c = (c - rot(b, 16)) & MASK
+    a ^= c; a = (a - rot(c, 4)) & MASK
+    b ^= a; b = (b - rot(a, 14)) & MASK
+    c ^= b; c = (c - rot(b, 24)) & MASK
+    return c
+
+
+def int_type(size):
+    # fixed-point class, version 1, little-endian and signed
+    return struct.pack("<BBBBIHH", 0x10, 0x08, 0, 0, size, 0, size * 8)
+
+
+def str_type(size):
+    # string class, version 1, null-padded ASCII
+    return struct.pack("<BBBBI", 0x13, 0x01, 0, 0, size)
+
+
+def simple_space(n):
+    return struct.pack("<BBBBQ", 2, 1, 0, 1, n)
+
+
+SCALAR_SPACE = struct.pack("<BBBB", 2, 0, 0, 0)
+
+
+class Hdf5File:
+    SUPERBLOCK_SIZE = 48
+
+    def __init__(self):
+        self.buf = bytearray(self.SUPERBLOCK_SIZE)
+
+    def append(self, data):
+        addr = len(self.buf)
+        self.buf += data
+        return addr
+
+    def object_header(self, messages):
+        body = b"".join(struct.pack("<BHB", msg_type, len(data), 0) + data for msg_type, data in messages)
+        header = b"OHDR" + struct.pack("<BBI", 2, 0x02, len(body)) + body
+        return self.append(header + struct.pack("<I", lookup3(header)))
+
+    def dataset(self, dtype, values, fmt):
+        addr = self.append(b"".join(struct.pack(fmt, v) for v in values))
+        size = len(self.buf) - addr
+        return self.object_header([
+            (0x01, simple_space(len(values))),
+            (0x03, dtype),
+            (0x05, struct.pack("<BB", 3, 0x0A)),
+            (0x08, struct.pack("<BBQQ", 3, 1, addr, size)),
+        ])
+
+    def group(self, links, attrs=()):
+        messages = [(0x02, struct.pack("<BBQQ", 0, 0, UNDEF, UNDEF)), (0x0A, struct.pack("<BB", 0, 0))]
+        for name, addr in links:
+            name = name.encode()
+            messages.append((0x06, struct.pack("<BBB", 1, 0, len(name)) + name + struct.pack("<Q", addr)))
+        for name, dtype, space, value in attrs:
+            name = name.encode() + b"\0"
+            messages.append((0x0C, struct.pack("<BBHHHB", 3, 0, len(name), len(dtype), len(space), 0)
+                             + name + dtype + space + value))
+        return self.object_header(messages)
+
+    def save(self, path, root):
+        superblock = b"\x89HDF\r\n\x1a\n" + struct.pack("<BBBBQQQQ", 2, 8, 8, 0, 0, UNDEF, len(self.buf), root)
+        self.buf[:self.SUPERBLOCK_SIZE] = superblock + struct.pack("<I", lookup3(superblock))
+        with open(path, "wb") as f:
+            f.write(self.buf)
+
+
+def main():
+    assert lookup3(b"Four score and seven years ago") == 0x17770551
+
+    f = Hdf5File()
+    name_len = max(len(name) for name, _ in CHROMS)
+    chroms = f.group([
+        ("name", f.dataset(str_type(name_len), [name for name, _ in CHROMS], "<%ds" % name_len)),
+        ("length", f.dataset(int_type(4), [length for _, length in CHROMS], "<i")),
+    ])
+    bins = f.group([
+        ("chrom", f.dataset(int_type(4), [b[0] for b in BINS], "<i")),
+        ("start", f.dataset(int_type(8), [b[1] for b in BINS], "<q")),
+        ("end", f.dataset(int_type(8), [b[2] for b in BINS], "<q")),
+    ])
+    pixels = f.group([
+        ("bin1_id", f.dataset(int_type(8), [p[0] for p in PIXELS], "<q")),
+        ("bin2_id", f.dataset(int_type(8), [p[1] for p in PIXELS], "<q")),
+        ("count", f.dataset(int_type(4), [p[2] for p in PIXELS], "<i")),
+    ])
+    indexes = f.group([
+        ("chrom_offset", f.dataset(int_type(8), CHROM_OFFSETS, "<q")),
+        ("bin1_offset", f.dataset(int_type(8), BIN1_OFFSETS, "<q")),
+    ])
+    root = f.group([("chroms", chroms), ("bins", bins), ("pixels", pixels), ("indexes", indexes)],
+                   [("bin-size", int_type(8), SCALAR_SPACE, struct.pack("<q", BIN_SIZE))])
+    f.save(os.path.join(os.path.dirname(os.path.abspath(__file__)), "cooler_small.cool"), root)
+
+
+if __name__ == "__main__":
+    main()
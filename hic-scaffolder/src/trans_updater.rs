@@ -43,7 +43,7 @@ impl MaxInRowFinder {
             println!("Adding max trans interaction value for each row. Resolution {}", rstln);
             let max_vals = self.calc_trans_max_in_rows(matrix.get_local_matrix(rstln).unwrap(), tig_lengths)?;
             let writer = MatrixWriter::new_in_appending_mode(matrix.get_filepath())?;
-            let root = writer.get_file_handler().group(format!("resolutions/{}", rstln).as_ref())?;
+            let root = writer.get_res_group(rstln)?;
             MaxInRowFinder::write_max_values_for_rows(&root, max_vals.view())?;
         }
 